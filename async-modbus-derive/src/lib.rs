@@ -0,0 +1,437 @@
+//! async-modbus 的派生宏.
+//!
+//! 通过 `async-modbus` 的 `derive` 特性使用, 字段属性见 `async_modbus::registers` 模块的文档.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitInt, LitStr, Type};
+
+/// 一个读请求最多读取的寄存器数量, 和 `async_modbus::planner::MAX_READ_REGISTERS` 相同
+const MAX_READ_REGISTERS: u16 = 125;
+
+/// 为结构体实现 `async_modbus::registers::ModbusRegisters`
+///
+/// 每个字段通过 `#[modbus(...)]` 属性声明在寄存器中的位置和类型,
+/// 生成的 `read` 会把所有字段合并成尽量少的读请求.
+#[proc_macro_derive(ModbusRegisters, attributes(modbus))]
+pub fn derive_modbus_registers(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// 字段的数据类型
+enum Kind {
+    /// 实现了 `RegisterValue` 的数值类型
+    Value(Ident),
+    Bool,
+    String,
+    Bcd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Table {
+    Coil,
+    DiscreteInput,
+    InputRegister,
+    HoldingRegister,
+}
+
+/// `#[modbus(...)]` 中的原始属性
+#[derive(Default)]
+struct Attrs {
+    addr: Option<u16>,
+    ty: Option<LitStr>,
+    order: Option<LitStr>,
+    table: Option<LitStr>,
+    len: Option<LitInt>,
+    swap_bytes: bool,
+    read_only: bool,
+    skip: bool,
+}
+
+/// 检查过的字段
+struct Field {
+    ident: Ident,
+    /// `skip` 的字段为 None
+    mapping: Option<Mapping>,
+}
+
+struct Mapping {
+    kind: Kind,
+    table: Table,
+    addr: u16,
+    len: u16,
+    order: TokenStream2,
+    swap_bytes: bool,
+    writable: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "ModbusRegisters only supports structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "ModbusRegisters only supports structs",
+            ))
+        }
+    };
+
+    let fields = fields
+        .iter()
+        .map(parse_field)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let read_items = fields.iter().filter_map(read_item);
+    let decodes = fields.iter().map(decode);
+    let idents = fields.iter().map(|field| &field.ident);
+    let encodes = fields.iter().filter_map(encode);
+
+    Ok(quote! {
+        impl #impl_generics ::async_modbus::registers::ModbusRegisters for #name #ty_generics #where_clause {
+            fn read_items() -> ::std::vec::Vec<::async_modbus::planner::ReadItem> {
+                ::std::vec![#(#read_items),*]
+            }
+
+            fn from_values(
+                values: ::std::vec::Vec<::async_modbus::planner::ItemValue>,
+            ) -> ::std::result::Result<Self, ::async_modbus::ModbusError> {
+                let mut values = values.into_iter();
+                #(#decodes)*
+                ::std::result::Result::Ok(Self { #(#idents),* })
+            }
+
+            fn write_items(
+                &self,
+            ) -> ::std::result::Result<
+                ::std::vec::Vec<::async_modbus::registers::WriteItem>,
+                ::async_modbus::ModbusError,
+            > {
+                let mut items = ::std::vec::Vec::new();
+                #(#encodes)*
+                ::std::result::Result::Ok(items)
+            }
+        }
+    })
+}
+
+fn parse_attrs(field: &syn::Field) -> syn::Result<Attrs> {
+    let mut attrs = Attrs::default();
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("modbus"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("addr") {
+                attrs.addr = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("ty") {
+                attrs.ty = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("order") {
+                attrs.order = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("table") {
+                attrs.table = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("len") {
+                attrs.len = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("swap_bytes") {
+                attrs.swap_bytes = true;
+            } else if meta.path.is_ident("read_only") {
+                attrs.read_only = true;
+            } else if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else {
+                return Err(meta.error("unknown modbus attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<Field> {
+    let ident = field.ident.clone().expect("named fields have identifiers");
+    let attrs = parse_attrs(field)?;
+    if attrs.skip {
+        return Ok(Field {
+            ident,
+            mapping: None,
+        });
+    }
+
+    let addr = attrs.addr.ok_or_else(|| {
+        Error::new_spanned(
+            &ident,
+            "missing `#[modbus(addr = ...)]`, use `#[modbus(skip)]` to ignore the field",
+        )
+    })?;
+
+    let kind = match &attrs.ty {
+        Some(ty) => parse_kind(&ty.value()).ok_or_else(|| {
+            Error::new_spanned(
+                ty,
+                "ty must be u16, i16, u32, i32, f32, u64, i64, f64, bool, string or bcd",
+            )
+        })?,
+        None => infer_kind(&field.ty).ok_or_else(|| {
+            Error::new_spanned(&field.ty, "can not infer the modbus type, set `ty`")
+        })?,
+    };
+
+    let len = match (&kind, &attrs.len) {
+        (Kind::Value(ty), None) => match ty.to_string().as_str() {
+            "u16" | "i16" => 1,
+            "u32" | "i32" | "f32" => 2,
+            _ => 4,
+        },
+        (Kind::Bool, None) => 1,
+        (Kind::Value(_) | Kind::Bool, Some(len)) => {
+            return Err(Error::new_spanned(
+                len,
+                "len is only used by string and bcd",
+            ))
+        }
+        (Kind::String | Kind::Bcd, None) => {
+            return Err(Error::new_spanned(&ident, "string and bcd need `len`"))
+        }
+        (Kind::String, Some(len)) => parse_len(len, MAX_READ_REGISTERS)?,
+        (Kind::Bcd, Some(len)) => parse_len(len, 5)?,
+    };
+    if u32::from(addr) + u32::from(len) > 0x1_0000 {
+        return Err(Error::new_spanned(
+            &ident,
+            "field exceeds the address space",
+        ));
+    }
+
+    let table = match &attrs.table {
+        Some(table) => match table.value().to_ascii_lowercase().as_str() {
+            "coil" => Table::Coil,
+            "discrete" | "discrete_input" => Table::DiscreteInput,
+            "input" | "input_register" => Table::InputRegister,
+            "holding" | "holding_register" => Table::HoldingRegister,
+            _ => {
+                return Err(Error::new_spanned(
+                    table,
+                    "table must be holding, input, coil or discrete",
+                ))
+            }
+        },
+        None if matches!(kind, Kind::Bool) => Table::Coil,
+        None => Table::HoldingRegister,
+    };
+    let is_bit = matches!(table, Table::Coil | Table::DiscreteInput);
+    if is_bit != matches!(kind, Kind::Bool) {
+        return Err(Error::new_spanned(
+            &ident,
+            "bool fields must use coil or discrete, other fields must use holding or input",
+        ));
+    }
+
+    let order = match &attrs.order {
+        Some(order) if matches!(kind, Kind::Bool | Kind::String) => {
+            return Err(Error::new_spanned(
+                order,
+                "order is not used by bool and string, use `swap_bytes` for string",
+            ))
+        }
+        Some(order) => {
+            let variant = match order.value().to_ascii_uppercase().as_str() {
+                "ABCD" => "Abcd",
+                "CDAB" => "Cdab",
+                "BADC" => "Badc",
+                "DCBA" => "Dcba",
+                _ => {
+                    return Err(Error::new_spanned(
+                        order,
+                        "order must be ABCD, CDAB, BADC or DCBA",
+                    ))
+                }
+            };
+            let variant = Ident::new(variant, Span::call_site());
+            quote!(::async_modbus::codec::ByteOrder::#variant)
+        }
+        None => quote!(::async_modbus::codec::ByteOrder::Abcd),
+    };
+    if attrs.swap_bytes && !matches!(kind, Kind::String) {
+        return Err(Error::new_spanned(
+            &ident,
+            "swap_bytes is only used by string",
+        ));
+    }
+
+    Ok(Field {
+        ident,
+        mapping: Some(Mapping {
+            kind,
+            table,
+            addr,
+            len,
+            order,
+            swap_bytes: attrs.swap_bytes,
+            writable: !attrs.read_only && matches!(table, Table::Coil | Table::HoldingRegister),
+        }),
+    })
+}
+
+fn parse_kind(ty: &str) -> Option<Kind> {
+    match ty {
+        "u16" | "i16" | "u32" | "i32" | "f32" | "u64" | "i64" | "f64" => {
+            Some(Kind::Value(Ident::new(ty, Span::call_site())))
+        }
+        "bool" => Some(Kind::Bool),
+        "string" | "String" => Some(Kind::String),
+        "bcd" => Some(Kind::Bcd),
+        _ => None,
+    }
+}
+
+fn infer_kind(ty: &Type) -> Option<Kind> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            let ty = path.path.segments.last()?.ident.to_string();
+            // bcd 必须显式声明
+            if ty == "bcd" {
+                return None;
+            }
+            parse_kind(&ty)
+        }
+        _ => None,
+    }
+}
+
+fn parse_len(len: &LitInt, max: u16) -> syn::Result<u16> {
+    match len.base10_parse::<u16>()? {
+        len @ 1.. if len <= max => Ok(len),
+        _ => Err(Error::new_spanned(
+            len,
+            format!("len must be between 1 and {max}"),
+        )),
+    }
+}
+
+fn read_item(field: &Field) -> Option<TokenStream2> {
+    let mapping = field.mapping.as_ref()?;
+    let table = match mapping.table {
+        Table::Coil => quote!(Coil),
+        Table::DiscreteInput => quote!(DiscreteInput),
+        Table::InputRegister => quote!(InputRegister),
+        Table::HoldingRegister => quote!(HoldingRegister),
+    };
+    let (addr, len) = (mapping.addr, mapping.len);
+    Some(quote! {
+        ::async_modbus::planner::ReadItem::new(::async_modbus::planner::Table::#table, #addr, #len)
+    })
+}
+
+fn decode(field: &Field) -> TokenStream2 {
+    let ident = &field.ident;
+    let Some(mapping) = &field.mapping else {
+        return quote!(let #ident = ::std::default::Default::default(););
+    };
+
+    let missing = format!("Missing value for field `{ident}`");
+    let mismatch = format!("Unexpected value type for field `{ident}`");
+    let order = &mapping.order;
+    let value = match &mapping.kind {
+        Kind::Bool => quote! {
+            value
+                .as_bits()
+                .and_then(|bits| bits.first().copied())
+                .ok_or_else(|| ::async_modbus::ModbusError::Protocol(#mismatch.to_string()))?
+        },
+        kind => {
+            let decode = match kind {
+                Kind::Value(ty) => quote! {
+                    ::async_modbus::codec::decode::<#ty>(registers, #order)
+                        .map_err(::async_modbus::codec::protocol)?
+                },
+                Kind::String => {
+                    let swap_bytes = mapping.swap_bytes;
+                    quote! {
+                        ::async_modbus::codec::decode_string(
+                            registers,
+                            ::async_modbus::codec::StringFormat::new().swap_bytes(#swap_bytes),
+                        )
+                        .map_err(::async_modbus::codec::protocol)?
+                    }
+                }
+                Kind::Bcd => {
+                    let range = format!("BCD value of field `{ident}` is out of range");
+                    quote! {
+                        ::std::convert::TryFrom::try_from(
+                            ::async_modbus::codec::decode_bcd(registers, #order)
+                                .map_err(::async_modbus::codec::protocol)?,
+                        )
+                        .map_err(|_| ::async_modbus::ModbusError::Protocol(#range.to_string()))?
+                    }
+                }
+                Kind::Bool => unreachable!(),
+            };
+            quote! {{
+                let registers = value
+                    .as_registers()
+                    .ok_or_else(|| ::async_modbus::ModbusError::Protocol(#mismatch.to_string()))?;
+                #decode
+            }}
+        }
+    };
+
+    quote! {
+        let #ident = {
+            let value = values
+                .next()
+                .ok_or_else(|| ::async_modbus::ModbusError::Protocol(#missing.to_string()))?;
+            #value
+        };
+    }
+}
+
+fn encode(field: &Field) -> Option<TokenStream2> {
+    let mapping = field.mapping.as_ref().filter(|mapping| mapping.writable)?;
+    let ident = &field.ident;
+    let (addr, len, order) = (mapping.addr, mapping.len, &mapping.order);
+
+    let values = match &mapping.kind {
+        Kind::Bool => {
+            return Some(quote! {
+                items.push(::async_modbus::registers::WriteItem::Coil {
+                    address: #addr,
+                    value: self.#ident,
+                });
+            })
+        }
+        Kind::Value(_) => quote!(::async_modbus::codec::encode(&self.#ident, #order)),
+        Kind::String => {
+            let swap_bytes = mapping.swap_bytes;
+            quote! {
+                ::async_modbus::codec::encode_string(
+                    &self.#ident,
+                    #len,
+                    ::async_modbus::codec::StringFormat::new().swap_bytes(#swap_bytes),
+                )?
+            }
+        }
+        Kind::Bcd => quote! {
+            ::async_modbus::codec::encode_bcd(::std::convert::Into::<u64>::into(self.#ident), #len, #order)?
+        },
+    };
+    Some(quote! {
+        items.push(::async_modbus::registers::WriteItem::Registers {
+            address: #addr,
+            values: #values,
+        });
+    })
+}
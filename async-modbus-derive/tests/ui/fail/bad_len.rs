@@ -0,0 +1,21 @@
+use async_modbus::ModbusRegisters;
+
+#[derive(ModbusRegisters)]
+struct Meter {
+    #[modbus(addr = 200, ty = "string", len = 126)]
+    serial: String,
+}
+
+#[derive(ModbusRegisters)]
+struct Counter {
+    #[modbus(addr = 0, ty = "bcd", len = 6)]
+    total: u64,
+}
+
+#[derive(ModbusRegisters)]
+struct Status {
+    #[modbus(addr = 0, len = 2)]
+    status: u16,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use async_modbus::ModbusRegisters;
+
+#[derive(ModbusRegisters)]
+struct Meter {
+    #[modbus(addr = 100, scale = 10)]
+    voltage: u16,
+}
+
+fn main() {}
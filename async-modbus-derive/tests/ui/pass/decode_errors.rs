@@ -0,0 +1,33 @@
+use async_modbus::planner::ItemValue;
+use async_modbus::{ModbusError, ModbusRegisters};
+
+#[derive(Debug, ModbusRegisters)]
+struct Nameplate {
+    #[modbus(addr = 0, ty = "string", len = 2)]
+    model: String,
+    #[modbus(addr = 2, ty = "bcd", len = 1)]
+    serial: u16,
+    #[modbus(addr = 3, ty = "u32")]
+    hours: u32,
+}
+
+fn decode(model: Vec<u16>, serial: Vec<u16>, hours: Vec<u16>) -> Result<Nameplate, ModbusError> {
+    Nameplate::from_values(vec![
+        ItemValue::Registers(model),
+        ItemValue::Registers(serial),
+        ItemValue::Registers(hours),
+    ])
+}
+
+fn main() {
+    let nameplate = decode(vec![0x4142, 0x4300], vec![0x1234], vec![0, 7]).unwrap();
+    assert_eq!((nameplate.model.as_str(), nameplate.serial, nameplate.hours), ("ABC", 1234, 7));
+
+    // 从机返回的数据解码失败是协议错误
+    let invalid_utf8 = decode(vec![0xFFFE, 0], vec![0x1234], vec![0, 7]);
+    assert!(matches!(invalid_utf8, Err(ModbusError::Protocol(_))));
+    let invalid_bcd = decode(vec![0x4142, 0], vec![0x12A4], vec![0, 7]);
+    assert!(matches!(invalid_bcd, Err(ModbusError::Protocol(_))));
+    let short = decode(vec![0x4142, 0], vec![0x1234], vec![7]);
+    assert!(matches!(short, Err(ModbusError::Protocol(_))));
+}
@@ -0,0 +1,31 @@
+use async_modbus::planner::{ReadItem, Table};
+use async_modbus::ModbusRegisters;
+
+#[derive(ModbusRegisters)]
+struct Meter {
+    #[modbus(addr = 100, ty = "f32", order = "CDAB")]
+    voltage: f32,
+    #[modbus(addr = 102, table = "input")]
+    status: u16,
+    #[modbus(addr = 200, ty = "string", len = 125)]
+    serial: String,
+    #[modbus(addr = 300, ty = "bcd", len = 2, read_only)]
+    energy: u64,
+    #[modbus(addr = 0)]
+    running: bool,
+    #[modbus(skip)]
+    label: String,
+}
+
+fn main() {
+    assert_eq!(
+        Meter::read_items(),
+        [
+            ReadItem::new(Table::HoldingRegister, 100, 2),
+            ReadItem::new(Table::InputRegister, 102, 1),
+            ReadItem::new(Table::HoldingRegister, 200, 125),
+            ReadItem::new(Table::HoldingRegister, 300, 2),
+            ReadItem::new(Table::Coil, 0, 1),
+        ]
+    );
+}
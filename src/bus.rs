@@ -0,0 +1,245 @@
+//! 多个 rtu 从机共享的 RS-485 总线.
+//!
+//! 一条串口线路上通常连接多个从机. [`RtuBus`] 独占串口, 通过 [`RtuBus::slave`] 为每个从机返回一个 [`Client`],
+//! 它实现了 [`crate::Reader`] 和 [`crate::Writer`], 可以分别设置超时时间和重试策略.
+//! 所有从机的请求在总线上依次发送:
+//! - 帧之间至少间隔 3.5 个字符的静默时间, 按照波特率计算
+//! - 广播 (从机 id 0) 之后等待转换延时 (turnaround delay), 让从机处理完广播再发送下一个请求
+//!
+//! ```ignore
+//! let bus = RtuBus::builder()
+//!     .turnaround(Duration::from_millis(200))
+//!     .connect("rtu:///dev/ttyUSB0?baud=19200&parity=even")
+//!     .await?;
+//! let mut meter = bus.slave(1);
+//! let mut inverter = bus.slave(2);
+//! ```
+
+use crate::client::{Client, ClientBuilder};
+use crate::endpoint::{Endpoint, SerialSettings, Transport};
+use crate::error::{ModbusError, Result};
+use crate::transport::DEFAULT_RTU_TURNAROUND;
+use std::fmt::Debug;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// 按照波特率计算帧之间的静默时间, 也就是 3.5 个字符的时间
+///
+/// 每个字符按照 11 位计算. 波特率大于 19200 时协议规定使用固定的 1.75 毫秒.
+pub fn inter_frame_silence(baud_rate: u32) -> Duration {
+    if baud_rate == 0 || baud_rate > 19200 {
+        return Duration::from_micros(1750);
+    }
+    // 3.5 * 11 位 = 38.5 位
+    Duration::from_micros(38_500_000_u64.div_ceil(u64::from(baud_rate)))
+}
+
+/// 总线构建器
+#[derive(Debug, Clone)]
+pub struct BusBuilder {
+    client_builder: ClientBuilder,
+    silence: Option<Duration>,
+    turnaround: Duration,
+}
+
+impl Default for BusBuilder {
+    fn default() -> Self {
+        BusBuilder {
+            client_builder: ClientBuilder::new(),
+            silence: None,
+            turnaround: DEFAULT_RTU_TURNAROUND,
+        }
+    }
+}
+
+impl BusBuilder {
+    /// 使用默认配置创建构建器
+    ///
+    /// 默认按照波特率计算静默时间, 广播之后等待 100 毫秒.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置每个从机的 Client 默认使用的配置, 比如超时时间和重试策略
+    pub fn client_builder(mut self, client_builder: ClientBuilder) -> Self {
+        self.client_builder = client_builder;
+        self
+    }
+
+    /// 设置帧之间的静默时间, 代替按照波特率计算的值
+    pub fn silence(mut self, silence: Duration) -> Self {
+        self.silence = Some(silence);
+        self
+    }
+
+    /// 设置广播之后等待从机处理的时间
+    pub fn turnaround(mut self, turnaround: Duration) -> Self {
+        self.turnaround = turnaround;
+        self
+    }
+
+    /// 按照连接地址打开串口
+    ///
+    /// # 参数
+    /// - url: rtu 连接地址, 比如 `rtu:///dev/ttyUSB0?baud=19200`, 地址格式见 [`crate::endpoint`]
+    ///
+    /// # 返回
+    /// - 成功: 返回总线
+    /// - 失败: 地址不是 rtu 地址或者打开串口失败时返回错误
+    pub async fn connect(self, url: &str) -> Result<RtuBus> {
+        let endpoint: Endpoint = url.parse()?;
+        if !matches!(endpoint.transport, Transport::Rtu(_)) {
+            return Err(ModbusError::InvalidRequest(format!(
+                "{endpoint} is not a rtu endpoint"
+            )));
+        }
+        let client = self.timed().connect_endpoint(&endpoint).await?;
+        Ok(RtuBus { client })
+    }
+
+    /// 按照串口参数打开串口
+    ///
+    /// # 返回
+    /// - 成功: 返回总线
+    /// - 失败: 返回打开串口的错误
+    pub async fn open(self, serial: &SerialSettings) -> Result<RtuBus> {
+        let baud_rate = serial.baud_rate;
+        self.build(serial.open()?, baud_rate).await
+    }
+
+    /// 使用已经打开的传输实例构建总线
+    ///
+    /// # 参数
+    /// - transport: 传输实例, 比如串口或者串口服务器的 tcp 连接
+    /// - baud_rate: 线路的波特率, 用来计算静默时间
+    ///
+    /// # 返回
+    /// - 成功: 返回总线
+    /// - 失败: 返回错误信息
+    pub async fn build<T>(self, transport: T, baud_rate: u32) -> Result<RtuBus>
+    where
+        T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+    {
+        let silence = self
+            .silence
+            .unwrap_or_else(|| inter_frame_silence(baud_rate));
+        let client = self
+            .timed()
+            .rtu_silence(silence)
+            .build_rtu(transport)
+            .await?;
+        Ok(RtuBus { client })
+    }
+
+    /// 把总线的时间参数设置到 Client 构建器中
+    fn timed(self) -> ClientBuilder {
+        let builder = self.client_builder.rtu_turnaround(self.turnaround);
+        match self.silence {
+            Some(silence) => builder.rtu_silence(silence),
+            None => builder,
+        }
+    }
+}
+
+/// 多个从机共享的 RS-485 总线
+///
+/// 可以 `clone`, 所有克隆和返回的 Client 共享同一个串口.
+#[derive(Clone)]
+pub struct RtuBus {
+    client: Client,
+}
+
+impl RtuBus {
+    /// 创建总线构建器
+    pub fn builder() -> BusBuilder {
+        BusBuilder::new()
+    }
+
+    /// 使用默认配置按照连接地址打开串口, 参考 [`BusBuilder::connect`]
+    pub async fn connect(url: &str) -> Result<RtuBus> {
+        BusBuilder::new().connect(url).await
+    }
+
+    /// 返回访问从机 `slave_id` 的 Client
+    ///
+    /// 返回的 Client 使用构建器中的超时时间和重试策略, 之后可以分别修改.
+    /// 从机 id 0 和 [`RtuBus::broadcast`] 相同.
+    pub fn slave(&self, slave_id: u8) -> Client {
+        self.client.for_unit(slave_id)
+    }
+
+    /// 返回向总线上所有从机广播写请求的 Client, 参考 [`Client::broadcast`]
+    pub fn broadcast(&self) -> Client {
+        self.client.broadcast()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::crc16;
+    use crate::Reader;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{timeout, Instant};
+
+    #[test]
+    fn silence_by_baud_rate() {
+        // 38.5 位在 9600 波特率下的时间
+        assert_eq!(inter_frame_silence(9600), Duration::from_micros(4011));
+        assert_eq!(inter_frame_silence(19200), Duration::from_micros(2006));
+        for baud_rate in [0, 19201, 38400, 115200] {
+            assert_eq!(inter_frame_silence(baud_rate), Duration::from_micros(1750));
+        }
+    }
+
+    #[tokio::test]
+    async fn serialise_slaves() {
+        let (transport, mut server) = duplex(1024);
+        let silence = Duration::from_millis(100);
+        let bus = RtuBus::builder()
+            .client_builder(
+                ClientBuilder::new()
+                    .timeout(Duration::from_millis(50))
+                    .retry_count(1),
+            )
+            .silence(silence)
+            .build(transport, 9600)
+            .await
+            .unwrap();
+        let mut meter = bus.slave(1);
+        let mut inverter = bus.slave(2);
+
+        let script = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let mut request = [0; 8];
+                server.read_exact(&mut request).await.unwrap();
+                let received = Instant::now();
+                // 响应之前总线上没有其他请求
+                let mut next = [0; 1];
+                let next = timeout(Duration::from_millis(20), server.read_exact(&mut next)).await;
+                assert!(next.is_err());
+
+                let mut response = vec![request[0], 0x03, 0x02, 0x00, request[0]];
+                let crc = crc16(&response);
+                response.extend_from_slice(&crc.to_le_bytes());
+                server.write_all(&response).await.unwrap();
+                requests.push((request[0], received));
+            }
+            requests
+        });
+        let (a, b) = tokio::join!(
+            meter.read_holding_registers(0, 1),
+            inverter.read_holding_registers(0, 1)
+        );
+        // 静默时间比超时时间长, 但是不计入超时时间
+        assert_eq!(a.unwrap(), [1]);
+        assert_eq!(b.unwrap(), [2]);
+
+        let requests = script.await.unwrap();
+        let mut slaves = [requests[0].0, requests[1].0];
+        slaves.sort();
+        assert_eq!(slaves, [1, 2]);
+        assert!(requests[1].1 - requests[0].1 >= silence);
+    }
+}
@@ -2,29 +2,666 @@
 //!
 //! tcp 和 rtu 客户端的使用方式是相同的, 所以通过 Client 同一实现, 并增加了超时重发功能.
 
-use anyhow::{bail, Result};
+#[cfg(feature = "modbus_rtu_client")]
+use crate::bus::inter_frame_silence;
+use crate::endpoint::{Endpoint, Transport};
+use crate::error::{ModbusError, Result};
+use crate::planner::{MAX_READ_BITS, MAX_READ_REGISTERS};
+#[cfg(feature = "modbus_tcp_client")]
+use crate::retry::ExponentialBackoff;
+use crate::retry::{FixedRetry, RetryOn, RetryPolicy};
+#[cfg(feature = "modbus_tcp_client")]
+use crate::transport::{Pipeline, TcpTransport};
+#[cfg(feature = "modbus_rtu_client")]
+use crate::transport::{
+    RtuPacing, RtuTiming, RtuTransport, DEFAULT_RTU_DRAIN, DEFAULT_RTU_TURNAROUND,
+};
+use crate::BroadcastWrite;
 use async_trait::async_trait;
 use std::borrow::Cow;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+#[cfg(feature = "modbus_tcp_client")]
+use std::io;
+#[cfg(feature = "modbus_tcp_client")]
 use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 use std::time::Duration;
+#[cfg(feature = "modbus_rtu_client")]
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::time::timeout;
+#[cfg(feature = "modbus_tcp_client")]
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time::error::Elapsed;
+use tokio::time::{sleep, timeout};
 use tokio_modbus::prelude::*;
 
-enum ResultValue {
+/// 请求的结果
+pub(crate) enum ResultValue {
     U16(Vec<u16>),
     Bool(Vec<bool>),
+    Unit,
 }
 
 /// tcp 和 rtu 客户端
+///
+/// 通过 [`Client::for_unit`] 或 `clone` 得到的 Client 共享同一个连接, 每个 Client 有自己的从机 id,
+/// 超时时间和重试策略, 适合通过一个网关访问多个从机.
+#[derive(Clone)]
 pub struct Client {
-    ctx: Box<client::Context>,
-    timeout_millis: u64,
-    retry_count: u64,
+    conn: Arc<Mutex<Connection>>,
+    slave_id: u8,
+    timeout: Duration,
+    retry_policy: Arc<dyn RetryPolicy>,
+    retry_on: RetryOn,
+    limits: RequestLimits,
+    idempotency: Idempotency,
+    /// 广播写请求, 只发送一次并且不等待响应
+    broadcast: bool,
+}
+
+/// 写多个寄存器请求最多写入的寄存器数量 (0x10)
+pub const MAX_WRITE_REGISTERS: u16 = 123;
+
+/// 写多个线圈请求最多写入的线圈数量 (0x0F)
+pub const MAX_WRITE_BITS: u16 = 1968;
+
+/// 读写多个寄存器请求最多读取的寄存器数量 (0x17)
+pub const MAX_READ_WRITE_READ_REGISTERS: u16 = 125;
+
+/// 读写多个寄存器请求最多写入的寄存器数量 (0x17)
+pub const MAX_READ_WRITE_WRITE_REGISTERS: u16 = 121;
+
+/// 每个请求最多读写的数量
+///
+/// 超过限制的读请求和写多个线圈/寄存器请求会被拆分成多个请求依次发送. 默认使用协议规定的最大数量,
+/// 有些设备只支持更少的数量, 比如一次最多读写 64 个寄存器.
+///
+/// 拆分后的写请求不是原子操作, 每个请求分别按照重试策略重试. 第一个请求失败时直接返回它的错误,
+/// 没有写入任何值; 之后的请求失败时返回 [`ModbusError::PartialWrite`], 包含已经写入的数量,
+/// 失败的请求之后的部分不再发送.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    read_registers: u16,
+    read_bits: u16,
+    write_registers: u16,
+    write_bits: u16,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            read_registers: MAX_READ_REGISTERS,
+            read_bits: MAX_READ_BITS,
+            write_registers: MAX_WRITE_REGISTERS,
+            write_bits: MAX_WRITE_BITS,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// 使用协议规定的最大数量
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置一次最多读取的寄存器数量, 取值范围 1 ~ 125
+    pub fn read_registers(mut self, max: u16) -> Self {
+        self.read_registers = max.clamp(1, MAX_READ_REGISTERS);
+        self
+    }
+
+    /// 设置一次最多读取的线圈或离散输入数量, 取值范围 1 ~ 2000
+    pub fn read_bits(mut self, max: u16) -> Self {
+        self.read_bits = max.clamp(1, MAX_READ_BITS);
+        self
+    }
+
+    /// 设置一次最多写入的寄存器数量, 取值范围 1 ~ 123
+    pub fn write_registers(mut self, max: u16) -> Self {
+        self.write_registers = max.clamp(1, MAX_WRITE_REGISTERS);
+        self
+    }
+
+    /// 设置一次最多写入的线圈数量, 取值范围 1 ~ 1968
+    pub fn write_bits(mut self, max: u16) -> Self {
+        self.write_bits = max.clamp(1, MAX_WRITE_BITS);
+        self
+    }
+}
+
+/// 哪些写请求不是幂等的
+///
+/// 计数器, 脉冲线圈和命令寄存器等地址重复写入会让动作执行两次. 写请求已经到达从机但是响应丢失时,
+/// 重新发送就会重复写入, 所以写入这些地址的请求只发送一次, 不会重试:
+/// - 超时和 I/O 错误返回 [`ModbusError::OutcomeUnknown`], 因为不知道从机是否已经执行
+/// - 其他错误直接返回
+///
+/// 只要写请求覆盖的地址和任意一个范围重叠就认为不是幂等的, 读请求总是幂等的.
+/// 默认所有写请求都是幂等的.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Idempotency {
+    all_writes: bool,
+    coils: Vec<RangeInclusive<u16>>,
+    registers: Vec<RangeInclusive<u16>>,
+}
+
+impl Idempotency {
+    /// 所有写请求都是幂等的
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 所有写请求都不是幂等的
+    pub fn no_write_retry() -> Self {
+        Idempotency {
+            all_writes: true,
+            ..Default::default()
+        }
+    }
+
+    /// 增加一段不是幂等的线圈地址
+    pub fn coils(mut self, range: RangeInclusive<u16>) -> Self {
+        self.coils.push(range);
+        self
+    }
+
+    /// 增加一段不是幂等的保持寄存器地址
+    pub fn registers(mut self, range: RangeInclusive<u16>) -> Self {
+        self.registers.push(range);
+        self
+    }
+
+    /// 判断请求是否可以重试
+    fn is_idempotent(&self, request: &Request<'_>) -> bool {
+        let (ranges, address, count) = match *request {
+            Request::WriteSingleCoil(address, _) => (&self.coils, address, 1),
+            Request::WriteMultipleCoils(address, ref values) => {
+                (&self.coils, address, values.len())
+            }
+            Request::WriteSingleRegister(address, _) | Request::MaskWriteRegister(address, ..) => {
+                (&self.registers, address, 1)
+            }
+            Request::WriteMultipleRegisters(address, ref values)
+            | Request::ReadWriteMultipleRegisters(_, _, address, ref values) => {
+                (&self.registers, address, values.len())
+            }
+            _ => return true,
+        };
+        if self.all_writes {
+            return false;
+        }
+        let first = u32::from(address);
+        let last = first + count.max(1) as u32 - 1;
+        !ranges
+            .iter()
+            .any(|range| u32::from(*range.start()) <= last && first <= u32::from(*range.end()))
+    }
+}
+
+/// 多个 Client 共享的连接
+struct Connection {
+    ctx: client::Context,
+    /// rtu 连接, 只有 rtu 支持广播
+    rtu: bool,
+    /// rtu 线路空闲的时间, 发送请求前和广播之后在超时时间之外等待
+    #[cfg(feature = "modbus_rtu_client")]
+    pacing: Option<RtuPacing>,
+    /// 流水线模式下的 tcp 连接, 等待响应时不占用连接
+    #[cfg(feature = "modbus_tcp_client")]
+    pipeline: Option<Pipeline>,
+    #[cfg(feature = "modbus_tcp_client")]
+    reconnect: Option<Arc<Reconnect>>,
+    /// 每次重连成功后加 1, 用来判断连接是否已经被其他 Client 重连
+    #[cfg(feature = "modbus_tcp_client")]
+    generation: u64,
+}
+
+/// tcp 连接断开后的重连事件
+#[cfg(feature = "modbus_tcp_client")]
+#[derive(Debug, Clone)]
+pub enum ReconnectEvent {
+    /// 检测到连接断开
+    Disconnected {
+        /// 对端地址
+        socket_addr: SocketAddr,
+        /// 断开原因
+        reason: String,
+    },
+
+    /// 开始第 `attempt` 次重连
+    Reconnecting {
+        /// 对端地址
+        socket_addr: SocketAddr,
+        /// 重连次数, 从 1 开始
+        attempt: u32,
+    },
+
+    /// 重连成功
+    Reconnected {
+        /// 新连接的对端地址
+        socket_addr: SocketAddr,
+        /// 一共尝试的次数
+        attempts: u32,
+    },
+
+    /// 重连策略用尽, 放弃重连, 下次请求时会再次重连
+    Failed {
+        /// 对端地址
+        socket_addr: SocketAddr,
+        /// 一共尝试的次数
+        attempts: u32,
+        /// 最后一次失败的原因
+        reason: String,
+    },
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+type OnReconnect = Arc<dyn Fn(ReconnectEvent) + Send + Sync>;
+
+/// tcp 重连配置
+#[cfg(feature = "modbus_tcp_client")]
+struct Reconnect {
+    /// 当前连接的地址, 同时保证同一时间只有一个 Client 重连
+    socket_addr: Mutex<SocketAddr>,
+    socket_addrs: Vec<SocketAddr>,
+    slave: Slave,
+    connect_timeout: Duration,
+    pipeline: usize,
+    policy: Arc<dyn RetryPolicy>,
+    on_event: Option<OnReconnect>,
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl Reconnect {
+    fn notify(&self, event: ReconnectEvent) {
+        log::warn!("CLIENT: {event:?}");
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+}
+
+/// Client 构建器
+///
+/// 用来配置请求超时时间, 重试策略, 连接超时时间, 重连策略和从机 id, 然后构建 tcp 或 rtu 客户端.
+#[derive(Clone)]
+pub struct ClientBuilder {
+    slave_id: u8,
+    timeout: Duration,
+    retry_policy: Arc<dyn RetryPolicy>,
+    retry_on: RetryOn,
+    limits: RequestLimits,
+    idempotency: Idempotency,
+    connect_timeout: Duration,
+    #[cfg(feature = "modbus_rtu_client")]
+    rtu_drain: Duration,
+    #[cfg(feature = "modbus_rtu_client")]
+    rtu_silence: Option<Duration>,
+    #[cfg(feature = "modbus_rtu_client")]
+    rtu_turnaround: Duration,
+    #[cfg(feature = "modbus_tcp_client")]
+    pipeline: usize,
+    #[cfg(feature = "modbus_tcp_client")]
+    reconnect_policy: Option<Arc<dyn RetryPolicy>>,
+    #[cfg(feature = "modbus_tcp_client")]
+    on_reconnect: Option<OnReconnect>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            slave_id: 1,
+            timeout: Duration::from_millis(200),
+            retry_policy: Arc::new(FixedRetry::new(Duration::ZERO, 5)),
+            retry_on: RetryOn::default(),
+            limits: RequestLimits::default(),
+            idempotency: Idempotency::default(),
+            connect_timeout: Duration::from_secs(5),
+            #[cfg(feature = "modbus_rtu_client")]
+            rtu_drain: DEFAULT_RTU_DRAIN,
+            #[cfg(feature = "modbus_rtu_client")]
+            rtu_silence: None,
+            #[cfg(feature = "modbus_rtu_client")]
+            rtu_turnaround: DEFAULT_RTU_TURNAROUND,
+            #[cfg(feature = "modbus_tcp_client")]
+            pipeline: 1,
+            #[cfg(feature = "modbus_tcp_client")]
+            reconnect_policy: Some(Arc::new(ExponentialBackoff::new(
+                Duration::from_millis(100),
+                Duration::from_secs(5),
+                5,
+            ))),
+            #[cfg(feature = "modbus_tcp_client")]
+            on_reconnect: None,
+        }
+    }
+}
+
+impl Debug for ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("ClientBuilder");
+        f.field("slave_id", &self.slave_id)
+            .field("timeout", &self.timeout)
+            .field("retry_policy", &self.retry_policy)
+            .field("retry_on", &self.retry_on)
+            .field("limits", &self.limits)
+            .field("idempotency", &self.idempotency)
+            .field("connect_timeout", &self.connect_timeout);
+        #[cfg(feature = "modbus_rtu_client")]
+        f.field("rtu_drain", &self.rtu_drain)
+            .field("rtu_silence", &self.rtu_silence)
+            .field("rtu_turnaround", &self.rtu_turnaround);
+        #[cfg(feature = "modbus_tcp_client")]
+        f.field("pipeline", &self.pipeline)
+            .field("reconnect_policy", &self.reconnect_policy);
+        f.finish_non_exhaustive()
+    }
+}
+
+impl ClientBuilder {
+    /// 使用默认配置创建构建器
+    ///
+    /// 默认从机 id 为 1, 请求超时 200 毫秒, 最多发送 5 次, 连接超时 5 秒.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置从机 id
+    ///
+    /// rtu 上的从机 id 0 和 [`Client::broadcast`] 相同, 只能发送写请求并且不等待响应.
+    pub fn slave_id(mut self, slave_id: u8) -> Self {
+        self.slave_id = slave_id;
+        self
+    }
+
+    /// 设置每次请求的超时时间
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 设置每个请求最多发送的次数 (包含第一次), 超时后立即重新发送
+    ///
+    /// 等同于 `retry_policy(FixedRetry::new(Duration::ZERO, retry_count))`.
+    pub fn retry_count(self, retry_count: u32) -> Self {
+        self.retry_policy(FixedRetry::new(Duration::ZERO, retry_count))
+    }
+
+    /// 设置重试策略, 由策略决定请求失败后是否重新发送以及等待多久
+    pub fn retry_policy<P: RetryPolicy + 'static>(mut self, retry_policy: P) -> Self {
+        self.retry_policy = Arc::new(retry_policy);
+        self
+    }
+
+    /// 设置哪些错误可以重试
+    ///
+    /// 默认重试超时和 `Acknowledge`, `ServerDeviceBusy`, `GatewayPathUnavailable`, `GatewayTargetDevice` 异常.
+    pub fn retry_on(mut self, retry_on: RetryOn) -> Self {
+        self.retry_on = retry_on;
+        self
+    }
+
+    /// 设置每个请求最多读写的数量, 超过限制的请求会被拆分
+    pub fn request_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// 设置哪些写请求不是幂等的, 这些请求只发送一次
+    pub fn idempotency(mut self, idempotency: Idempotency) -> Self {
+        self.idempotency = idempotency;
+        self
+    }
+
+    /// 设置 rtu 请求超时后丢弃残留数据的等待时间, 默认 50 毫秒
+    ///
+    /// rtu 没有事务 id, 请求超时后从机可能仍然返回响应. 下一个请求发送之前, 先丢弃线路上的数据,
+    /// 直到线路上持续这段时间没有数据. 应该大于从机超时后仍然可能返回响应的时间.
+    ///
+    /// 等待的时间计入下一个请求的超时时间, 所以应该小于请求超时时间, 否则重试时会多消耗一次发送次数.
+    #[cfg(feature = "modbus_rtu_client")]
+    pub fn rtu_drain(mut self, drain: Duration) -> Self {
+        self.rtu_drain = drain;
+        self
+    }
+
+    /// 设置 rtu 帧之间至少间隔的静默时间
+    ///
+    /// 协议规定帧之间至少间隔 3.5 个字符的时间, 可以用 [`inter_frame_silence`] 按照波特率计算.
+    /// 通过 [`ClientBuilder::connect`] 打开串口时默认按照波特率计算, 其他情况默认不等待.
+    /// 等待的时间不计入请求的超时时间.
+    #[cfg(feature = "modbus_rtu_client")]
+    pub fn rtu_silence(mut self, silence: Duration) -> Self {
+        self.rtu_silence = Some(silence);
+        self
+    }
+
+    /// 设置 rtu 广播 (从机 id 0) 之后等待从机处理的时间, 默认 100 毫秒
+    ///
+    /// 从机不响应广播, 广播发送完成后等待这段时间再返回成功, 等待的时间不计入请求的超时时间.
+    #[cfg(feature = "modbus_rtu_client")]
+    pub fn rtu_turnaround(mut self, turnaround: Duration) -> Self {
+        self.rtu_turnaround = turnaround;
+        self
+    }
+
+    /// 设置建立 tcp 连接的超时时间
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// 设置 tcp 流水线的窗口大小, 也就是最多同时等待响应的请求数量, 默认 1, 也就是一问一答
+    ///
+    /// 大于 1 时多个共享连接的 Client 可以同时发送请求, 不用等待上一个响应, 响应按照事务 id 匹配.
+    /// 有请求和其他请求同时发送后超时时, 认为设备不支持流水线, 之后退回到一问一答.
+    /// 退回后连续收到 100 个响应时重新启用流水线, 每次退回后需要的响应数量加倍, 最多 102400 个;
+    /// 重新连接后也会重新启用.
+    #[cfg(feature = "modbus_tcp_client")]
+    pub fn pipeline(mut self, window: usize) -> Self {
+        self.pipeline = window.max(1);
+        self
+    }
+
+    /// 设置 tcp 连接断开后的重连策略
+    ///
+    /// 请求遇到 I/O 错误时会按照策略重新连接原来的地址, 重连成功后由重试策略决定是否重新发送请求.
+    /// 默认使用指数退避, 初始 100 毫秒, 最长 5 秒, 最多尝试 5 次.
+    #[cfg(feature = "modbus_tcp_client")]
+    pub fn reconnect_policy<P: RetryPolicy + 'static>(mut self, reconnect_policy: P) -> Self {
+        self.reconnect_policy = Some(Arc::new(reconnect_policy));
+        self
+    }
+
+    /// 关闭 tcp 自动重连
+    #[cfg(feature = "modbus_tcp_client")]
+    pub fn disable_reconnect(mut self) -> Self {
+        self.reconnect_policy = None;
+        self
+    }
+
+    /// 设置重连事件的回调
+    #[cfg(feature = "modbus_tcp_client")]
+    pub fn on_reconnect<F>(mut self, on_reconnect: F) -> Self
+    where
+        F: Fn(ReconnectEvent) + Send + Sync + 'static,
+    {
+        self.on_reconnect = Some(Arc::new(on_reconnect));
+        self
+    }
+
+    /// 构建 Modbus TCP 协议客户端
+    ///
+    /// # 参数
+    ///
+    /// - socket_addr: socket 地址
+    ///
+    /// # 返回
+    ///
+    /// - 成功: 返 Client 实例
+    ///
+    /// - 失败: 返回错误信息
+    #[cfg(feature = "modbus_tcp_client")]
+    pub async fn build_tcp(self, socket_addr: SocketAddr) -> Result<Client> {
+        self.build_tcp_addrs(&[socket_addr]).await
+    }
+
+    /// 构建 Modbus TCP 协议客户端, 依次尝试每个地址直到连接成功
+    ///
+    /// 每个地址的连接时间都不超过连接超时时间, 适合主机名同时解析出 IPv4 和 IPv6 地址的情况.
+    /// 自动重连时同样会依次尝试这些地址.
+    ///
+    /// # 参数
+    ///
+    /// - socket_addrs: socket 地址列表
+    ///
+    /// # 返回
+    ///
+    /// - 成功: 返 Client 实例
+    ///
+    /// - 失败: 返回最后一个地址的错误信息
+    #[cfg(feature = "modbus_tcp_client")]
+    pub async fn build_tcp_addrs(mut self, socket_addrs: &[SocketAddr]) -> Result<Client> {
+        let slave = Slave(self.slave_id);
+        let (ctx, pipeline, socket_addr) =
+            connect_tcp(socket_addrs, slave, self.connect_timeout, self.pipeline).await?;
+        let reconnect = self.reconnect_policy.take().map(|policy| {
+            Arc::new(Reconnect {
+                socket_addr: Mutex::new(socket_addr),
+                socket_addrs: socket_addrs.to_vec(),
+                slave,
+                connect_timeout: self.connect_timeout,
+                pipeline: self.pipeline,
+                policy,
+                on_event: self.on_reconnect.take(),
+            })
+        });
+
+        let client = self.build(ctx, false);
+        let mut conn = client.conn.lock().await;
+        conn.pipeline = pipeline;
+        conn.reconnect = reconnect;
+        drop(conn);
+        Ok(client)
+    }
+
+    /// 构建 Modbus RTU 协议客户端
+    ///
+    /// # 参数
+    ///
+    /// - transport: 传输实例.
+    ///
+    ///   可以通过串口或者网络发送 Modbus RTU 协议.
+    ///
+    /// # 返回
+    ///
+    /// - 成功: 返 Client 实例
+    ///
+    /// - 失败: 返回错误信息
+    #[cfg(feature = "modbus_rtu_client")]
+    pub async fn build_rtu<T>(self, transport: T) -> Result<Client>
+    where
+        T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+    {
+        let timing = RtuTiming {
+            drain: self.rtu_drain,
+            silence: self.rtu_silence.unwrap_or_default(),
+            turnaround: self.rtu_turnaround,
+        };
+        let pacing = RtuPacing::default();
+        let ctx = RtuTransport::attach(transport, Slave(self.slave_id), timing, pacing.clone());
+        let client = self.build(ctx, true);
+        client.conn.lock().await.pacing = Some(pacing);
+        Ok(client)
+    }
+
+    /// 按照连接地址构建客户端
+    ///
+    /// 地址中的 unit, timeout 和 retries 参数会覆盖构建器中的设置, 地址格式见 [`crate::endpoint`].
+    ///
+    /// # 参数
+    ///
+    /// - url: 连接地址, 比如 `tcp://plc.local:502?unit=3` 或 `rtu:///dev/ttyUSB0?baud=19200`
+    ///
+    /// # 返回
+    ///
+    /// - 成功: 返 Client 实例
+    ///
+    /// - 失败: 返回错误信息
+    pub async fn connect(self, url: &str) -> Result<Client> {
+        self.connect_endpoint(&url.parse()?).await
+    }
+
+    /// 按照已经解析的连接地址构建客户端, 参考 [`ClientBuilder::connect`]
+    pub async fn connect_endpoint(mut self, endpoint: &Endpoint) -> Result<Client> {
+        if let Some(unit) = endpoint.unit {
+            self = self.slave_id(unit);
+        }
+        if let Some(timeout) = endpoint.timeout {
+            self = self.timeout(timeout);
+        }
+        if let Some(retries) = endpoint.retries {
+            self = self.retry_count(retries);
+        }
+
+        match &endpoint.transport {
+            #[cfg(feature = "modbus_tcp_client")]
+            Transport::Tcp { .. } => {
+                let socket_addrs = endpoint.resolve().await?;
+                self.build_tcp_addrs(&socket_addrs).await
+            }
+            #[cfg(feature = "modbus_rtu_client")]
+            Transport::Rtu(serial) => {
+                if self.rtu_silence.is_none() {
+                    self.rtu_silence = Some(inter_frame_silence(serial.baud_rate));
+                }
+                self.build_rtu(serial.open()?).await
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(ModbusError::InvalidRequest(format!(
+                "{endpoint} is not supported by the enabled features"
+            ))),
+        }
+    }
+
+    fn build(self, ctx: client::Context, rtu: bool) -> Client {
+        let conn = Connection {
+            ctx,
+            rtu,
+            #[cfg(feature = "modbus_rtu_client")]
+            pacing: None,
+            #[cfg(feature = "modbus_tcp_client")]
+            pipeline: None,
+            #[cfg(feature = "modbus_tcp_client")]
+            reconnect: None,
+            #[cfg(feature = "modbus_tcp_client")]
+            generation: 0,
+        };
+        Client {
+            conn: Arc::new(Mutex::new(conn)),
+            slave_id: self.slave_id,
+            timeout: self.timeout,
+            retry_policy: self.retry_policy,
+            retry_on: self.retry_on,
+            limits: self.limits,
+            idempotency: self.idempotency,
+            broadcast: false,
+        }
+    }
 }
 
 impl Client {
+    /// 创建 Client 构建器
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// 按照连接地址创建客户端, 参考 [`ClientBuilder::connect`]
+    pub async fn connect(url: &str) -> Result<Client> {
+        ClientBuilder::new().connect(url).await
+    }
+
     /// 创建新的 Modbus TCP 协议客户端
     ///
     /// # 参数
@@ -40,15 +677,10 @@ impl Client {
     /// - 失败: 返回错误信息
     #[cfg(feature = "modbus_tcp_client")]
     pub async fn new_tcp(socket_addr: SocketAddr, slave_id: u8) -> Result<Client> {
-        let ctx = tcp::connect_slave(socket_addr, Slave::from(slave_id))
+        ClientBuilder::new()
+            .slave_id(slave_id)
+            .build_tcp(socket_addr)
             .await
-            .unwrap();
-
-        Ok(Client {
-            ctx: Box::new(ctx),
-            timeout_millis: 200,
-            retry_count: 5,
-        })
     }
 
     /// 创建新的 Modbus RTU 协议客户端
@@ -68,15 +700,96 @@ impl Client {
     /// - 失败: 返回错误信息
     #[cfg(feature = "modbus_rtu_client")]
     pub async fn new_rtu<T>(transport: T, slave_id: u8) -> Result<Client>
-        where
-            T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+    where
+        T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
     {
-        let ctx = rtu::attach_slave(transport, Slave(slave_id));
-        Ok(Client {
-            ctx: Box::new(ctx),
-            timeout_millis: 200,
-            retry_count: 5,
-        })
+        ClientBuilder::new()
+            .slave_id(slave_id)
+            .build_rtu(transport)
+            .await
+    }
+
+    /// 创建访问另一个从机的 Client
+    ///
+    /// 新的 Client 和当前 Client 共享同一个连接, 并复制当前的超时时间和重试策略,
+    /// 之后可以分别修改. 请求在共享的连接上依次发送.
+    ///
+    /// # 参数
+    ///
+    /// - slave_id: 从机 id, rtu 上的从机 id 0 和 [`Client::broadcast`] 相同, 只能发送写请求并且不等待响应
+    pub fn for_unit(&self, slave_id: u8) -> Client {
+        let mut client = self.clone();
+        client.slave_id = slave_id;
+        client.broadcast = false;
+        client
+    }
+
+    /// 返回从机 id
+    pub fn slave_id(&self) -> u8 {
+        self.slave_id
+    }
+
+    /// 设置从机 id, 之后的请求都会发送给这个从机
+    ///
+    /// rtu 上的从机 id 0 和 [`Client::broadcast`] 相同, 只能发送写请求并且不等待响应.
+    pub fn set_slave_id(&mut self, slave_id: u8) {
+        self.slave_id = slave_id;
+        self.broadcast = false;
+    }
+
+    /// 设置每次请求的超时时间, 只影响当前 Client
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// 设置重试策略, 只影响当前 Client
+    pub fn set_retry_policy<P: RetryPolicy + 'static>(&mut self, retry_policy: P) {
+        self.retry_policy = Arc::new(retry_policy);
+    }
+
+    /// 设置哪些错误可以重试, 只影响当前 Client
+    pub fn set_retry_on(&mut self, retry_on: RetryOn) {
+        self.retry_on = retry_on;
+    }
+
+    /// 设置每个请求最多读写的数量, 只影响当前 Client
+    pub fn set_request_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    /// 设置哪些写请求不是幂等的, 只影响当前 Client
+    pub fn set_idempotency(&mut self, idempotency: Idempotency) {
+        self.idempotency = idempotency;
+    }
+
+    /// 创建所有写请求都只发送一次的 Client, 用来单独发送一次不是幂等的写请求
+    ///
+    /// 新的 Client 和当前 Client 共享同一个连接.
+    ///
+    /// ```ignore
+    /// client.no_write_retry().write_single_coil(0, true).await?;
+    /// ```
+    pub fn no_write_retry(&self) -> Client {
+        let mut client = self.clone();
+        client.idempotency = Idempotency::no_write_retry();
+        client
+    }
+
+    /// 创建向所有 rtu 从机广播 (从机 id 0) 的 Client
+    ///
+    /// 新的 Client 和当前 Client 共享同一个连接, 只能发送写请求. 读请求和 tcp 连接上的请求返回
+    /// [`ModbusError::InvalidRequest`], 也可以通过 [`crate::Writer::broadcast_write`] 发送广播.
+    /// 从机不响应广播, 写请求只发送一次, 发送完成后等待 [`ClientBuilder::rtu_turnaround`] 设置的时间,
+    /// 让从机处理完广播再返回成功, 等待期间其他 Client 的请求也不会发送. 等待的时间不计入请求的超时时间.
+    ///
+    /// ```ignore
+    /// client.broadcast().write_single_coil(0, false).await?;
+    /// ```
+    pub fn broadcast(&self) -> Client {
+        let mut client = self.clone();
+        client.slave_id = 0;
+        client.broadcast = true;
+        client
     }
 }
 
@@ -116,6 +829,12 @@ impl crate::Writer for Client {
             .handle_timeout_write(Request::MaskWriteRegister(address, and_mask, or_mask))
             .await?)
     }
+
+    async fn broadcast_write(&mut self, request: BroadcastWrite<'_>) -> Result<()> {
+        self.broadcast()
+            .handle_timeout_write(broadcast_request(request))
+            .await
+    }
 }
 
 #[async_trait]
@@ -170,130 +889,982 @@ impl crate::Reader for Client {
 impl Client {
     /// 写超时后会重试
     async fn handle_timeout_write(&mut self, request: Request<'_>) -> Result<()> {
-        let mut retry_count = self.retry_count;
-        let timeout_duration = Duration::from_millis(self.timeout_millis);
+        let result = self.execute(&request).await?;
+        result_value_unit(result)
+    }
 
-        while retry_count > 0 {
-            let future = match request {
-                Request::WriteSingleCoil(address, coil) => {
-                    self.ctx.write_single_coil(address, coil)
-                }
-                Request::WriteSingleRegister(address, data) => {
-                    self.ctx.write_single_register(address, data)
-                }
-                Request::WriteMultipleCoils(address, ref coil) => {
-                    self.ctx.write_multiple_coils(address, coil)
-                }
-                Request::WriteMultipleRegisters(address, ref coil) => {
-                    self.ctx.write_multiple_registers(address, coil)
+    /// 处理读超时
+    async fn handle_timeout_read(&mut self, request: Request<'_>) -> Result<ResultValue> {
+        self.execute(&request).await
+    }
+
+    /// 发送请求, 超过数量限制的请求拆分成多个请求依次发送, 然后合并结果
+    ///
+    /// 拆分后的写请求不是原子操作, 参考 [`RequestLimits`].
+    pub(crate) async fn execute(&mut self, request: &Request<'_>) -> Result<ResultValue> {
+        let mut attempts = 0;
+        self.execute_counted(request, &mut attempts).await
+    }
+
+    /// 和 [`Client::execute`] 相同, 并且把实际发送的次数 (包含拆分和重试) 累加到 `attempts`
+    pub(crate) async fn execute_counted(
+        &mut self,
+        request: &Request<'_>,
+        attempts: &mut u32,
+    ) -> Result<ResultValue> {
+        let limits = self.limits;
+        match *request {
+            Request::ReadCoils(address, count) if count > limits.read_bits => {
+                let max = limits.read_bits;
+                self.read_chunked(address, count, max, Request::ReadCoils, attempts)
+                    .await
+            }
+            Request::ReadDiscreteInputs(address, count) if count > limits.read_bits => {
+                let max = limits.read_bits;
+                self.read_chunked(address, count, max, Request::ReadDiscreteInputs, attempts)
+                    .await
+            }
+            Request::ReadHoldingRegisters(address, count) if count > limits.read_registers => {
+                let max = limits.read_registers;
+                self.read_chunked(address, count, max, Request::ReadHoldingRegisters, attempts)
+                    .await
+            }
+            Request::ReadInputRegisters(address, count) if count > limits.read_registers => {
+                let max = limits.read_registers;
+                self.read_chunked(address, count, max, Request::ReadInputRegisters, attempts)
+                    .await
+            }
+            Request::WriteMultipleCoils(address, ref values)
+                if values.len() > usize::from(limits.write_bits) =>
+            {
+                let max = limits.write_bits;
+                self.write_chunked(address, values, max, Request::WriteMultipleCoils, attempts)
+                    .await
+            }
+            Request::WriteMultipleRegisters(address, ref values)
+                if values.len() > usize::from(limits.write_registers) =>
+            {
+                let max = limits.write_registers;
+                self.write_chunked(
+                    address,
+                    values,
+                    max,
+                    Request::WriteMultipleRegisters,
+                    attempts,
+                )
+                .await
+            }
+            Request::ReadWriteMultipleRegisters(_, read_count, _, ref write_data)
+                if read_count > limits.read_registers.min(MAX_READ_WRITE_READ_REGISTERS)
+                    || write_data.len()
+                        > usize::from(
+                            limits.write_registers.min(MAX_READ_WRITE_WRITE_REGISTERS),
+                        ) =>
+            {
+                // 读写是一个原子操作, 不能拆分
+                Err(ModbusError::InvalidRequest(format!(
+                    "ReadWriteMultipleRegisters can not read {read_count} and write {} registers at once",
+                    write_data.len()
+                )))
+            }
+            _ => self.handle_timeout(request, attempts).await,
+        }
+    }
+
+    /// 把读请求拆分成每个最多 `max` 个的请求依次发送, 然后合并结果
+    async fn read_chunked(
+        &mut self,
+        address: u16,
+        count: u16,
+        max: u16,
+        request: fn(u16, u16) -> Request<'static>,
+        attempts: &mut u32,
+    ) -> Result<ResultValue> {
+        check_range(address, usize::from(count))?;
+
+        let mut offset = 0;
+        let mut result: Option<ResultValue> = None;
+        while offset < count {
+            let chunk = (count - offset).min(max);
+            let value = self
+                .handle_timeout(&request(address + offset, chunk), attempts)
+                .await?;
+            result = Some(match (result, value) {
+                (None, value) => value,
+                (Some(ResultValue::Bool(mut values)), ResultValue::Bool(chunk)) => {
+                    values.extend(chunk);
+                    ResultValue::Bool(values)
                 }
-                Request::MaskWriteRegister(address, and_mask, or_mask) => {
-                    self.ctx.masked_write_register(address, and_mask, or_mask)
+                (Some(ResultValue::U16(mut values)), ResultValue::U16(chunk)) => {
+                    values.extend(chunk);
+                    ResultValue::U16(values)
                 }
                 _ => {
-                    bail!("Out of handle_timeout options range")
+                    return Err(ModbusError::Protocol(
+                        "Result does not match the request".to_string(),
+                    ))
                 }
-            };
+            });
+            offset += chunk;
+        }
+        // 只有 count 超过 max 时才会拆分, 所以至少发送了一个请求
+        Ok(result.unwrap_or(ResultValue::Unit))
+    }
+
+    /// 把写请求拆分成每个最多 `max` 个的请求依次发送
+    ///
+    /// 第一个请求之后的请求失败时返回 [`ModbusError::PartialWrite`].
+    async fn write_chunked<'a, T: Clone>(
+        &mut self,
+        address: u16,
+        values: &'a [T],
+        max: u16,
+        request: fn(u16, Cow<'a, [T]>) -> Request<'a>,
+        attempts: &mut u32,
+    ) -> Result<ResultValue> {
+        check_range(address, values.len())?;
+
+        // 写完最后一个地址 0xFFFF 时写入的数量是 0x10000, 超出 u16 的范围
+        let mut written = 0usize;
+        for chunk in values.chunks(usize::from(max)) {
+            // check_range 保证还有值没有写入时, 已经写入的数量和下一个地址都不超过 0xFFFF
+            let offset = u16::try_from(written).expect("checked by check_range");
+            let start = address.checked_add(offset).expect("checked by check_range");
+            let request = request(start, Cow::Borrowed(chunk));
+            let result = self
+                .handle_timeout(&request, attempts)
+                .await
+                .and_then(result_value_unit);
+            match result {
+                Ok(()) => written += chunk.len(),
+                Err(e) if offset == 0 => return Err(e),
+                Err(e) => {
+                    return Err(ModbusError::PartialWrite {
+                        address,
+                        written: offset,
+                        source: Box::new(e),
+                    })
+                }
+            }
+        }
+        Ok(ResultValue::Unit)
+    }
+
+    /// 在超时时间内发送一次请求, 返回结果和连接
+    ///
+    /// 流水线模式下等待响应时不占用连接, 共享连接的 Client 可以同时发送请求.
+    async fn send(
+        &self,
+        request: &Request<'_>,
+    ) -> (
+        Result<Result<ResultValue>, Elapsed>,
+        MutexGuard<'_, Connection>,
+    ) {
+        let mut conn = self.conn.lock().await;
+        #[cfg(feature = "modbus_tcp_client")]
+        if let Some(pipeline) = conn.pipeline.clone() {
+            drop(conn);
+            let mut ctx = pipeline.context(Slave(self.slave_id));
+            let result = timeout(self.timeout, call(&mut ctx, request)).await;
+            return (result, self.conn.lock().await);
+        }
+        conn.ctx.set_slave(Slave(self.slave_id));
+        // 帧之间的静默时间和广播之后的转换延时不计入请求的超时时间
+        #[cfg(feature = "modbus_rtu_client")]
+        if let Some(pacing) = &conn.pacing {
+            pacing.wait().await;
+        }
+        let result = timeout(self.timeout, call(&mut conn.ctx, request)).await;
+        (result, conn)
+    }
 
-            match timeout(timeout_duration, future).await {
+    /// 只发送一次请求, 不重试也不重连, 用来检查连接是否可用
+    #[cfg(feature = "modbus_tcp_client")]
+    pub(crate) async fn probe(&self, request: &Request<'_>) -> Result<ResultValue> {
+        let (result, _conn) = self.send(request).await;
+        result.unwrap_or(Err(ModbusError::Timeout { attempts: 1 }))
+    }
+
+    /// 发送请求, 超时后按照重试策略等待一段时间再重新发送
+    ///
+    /// 每发送一次 `attempts` 加 1.
+    async fn handle_timeout(
+        &mut self,
+        request: &Request<'_>,
+        attempts: &mut u32,
+    ) -> Result<ResultValue> {
+        // rtu 上发送给从机 id 0 的请求也是广播, tcp 上的从机 id 0 是普通的单元标识符
+        let broadcast = self.broadcast || (self.slave_id == 0 && self.conn.lock().await.rtu);
+        if broadcast && !is_write(request) {
+            return Err(ModbusError::InvalidRequest(
+                "broadcast only supports write requests".to_string(),
+            ));
+        }
+        if self.broadcast && !self.conn.lock().await.rtu {
+            return Err(ModbusError::InvalidRequest(
+                "broadcast is only supported by rtu".to_string(),
+            ));
+        }
+        let mut attempt = 0;
+        let idempotent = !broadcast && self.idempotency.is_idempotent(request);
+
+        loop {
+            attempt += 1;
+            *attempts += 1;
+            let (result, conn) = self.send(request).await;
+            // 从机不响应广播, 等待从机处理完再返回, 等待时仍然占用连接, 其他请求不会在这期间发送
+            #[cfg(feature = "modbus_rtu_client")]
+            if let (true, Ok(Ok(_)), Some(pacing)) = (broadcast, &result, &conn.pacing) {
+                pacing.wait().await;
+            }
+            #[cfg(feature = "modbus_tcp_client")]
+            let (reconnect, generation) = (conn.reconnect.clone(), conn.generation);
+            // 等待重连和重试时不占用连接
+            drop(conn);
+
+            let error = match result {
                 Ok(Ok(response)) => {
                     return Ok(response);
                 }
+                #[cfg(feature = "modbus_tcp_client")]
+                Ok(Err(ModbusError::Io(e))) if reconnect.is_some() => {
+                    // 重连成功后由重试策略决定是否重新发送, 不是幂等的请求只重连不重新发送
+                    let reconnect = reconnect.as_deref().expect("checked by is_some");
+                    if !self.reconnect(reconnect, generation, &e).await || !idempotent {
+                        return Err(outcome(idempotent, ModbusError::Io(e)));
+                    }
+                    ModbusError::Io(e)
+                }
+                Ok(Err(e)) if !idempotent => return Err(outcome(idempotent, e)),
+                Ok(Err(e)) if self.retry_on.is_retryable(&e) => e,
                 Ok(Err(e)) => {
-                    bail!(e)
+                    return Err(e);
                 }
                 Err(_) => {
-                    retry_count -= 1;
-                    continue;
+                    let error = ModbusError::Timeout { attempts: attempt };
+                    if !idempotent || !self.retry_on.is_retryable(&error) {
+                        return Err(outcome(idempotent, error));
+                    }
+                    error
                 }
+            };
+
+            match self.retry_policy.next_delay(attempt) {
+                Some(delay) if delay.is_zero() => {}
+                Some(delay) => sleep(delay).await,
+                None => return Err(error),
             }
         }
-        bail!("Timeout: deadline has elapsed")
     }
+}
 
-    /// 处理读超时
-    async fn handle_timeout_read(&mut self, request: Request<'_>) -> Result<ResultValue> {
-        let timeout_duration = Duration::from_millis(self.timeout_millis);
-        let mut retry_count = self.retry_count;
-
-        while retry_count > 0 {
-            let future =
-                match request {
-                    Request::ReadHoldingRegisters(addr, cnt) => {
-                        Some(self.ctx.read_holding_registers(addr, cnt))
-                    }
-                    Request::ReadInputRegisters(addr, cnt) => {
-                        Some(self.ctx.read_input_registers(addr, cnt))
-                    }
-                    Request::ReadWriteMultipleRegisters(
-                        read_addr,
-                        read_count,
-                        write_addr,
-                        ref write_data,
-                    ) => Some(self.ctx.read_write_multiple_registers(
-                        read_addr, read_count, write_addr, write_data,
-                    )),
-                    _ => None,
-                };
-            if let Some(future) = future {
-                match timeout(timeout_duration, future).await {
-                    Ok(Ok(response)) => {
-                        return Ok(ResultValue::U16(response));
-                    }
-                    Ok(Err(e)) => {
-                        bail!(e)
-                    }
-                    Err(_) => {
-                        retry_count -= 1;
-                        continue;
-                    }
-                }
-            }
-            drop(future);
+impl Client {
+    /// 连接断开后按照重连策略重新连接
+    ///
+    /// 等待和建立连接时不占用连接, 只在替换连接时加锁, 所以等待重连的请求可以被取消.
+    /// 同一时间只有一个 Client 重连, 其他 Client 等待它完成后直接使用新的连接.
+    ///
+    /// # 参数
+    /// - generation: 发生错误时连接的 [`Connection::generation`]
+    ///
+    /// # 返回
+    /// 重连成功或者连接已经被其他 Client 重连时返回 true.
+    #[cfg(feature = "modbus_tcp_client")]
+    async fn reconnect(&self, reconnect: &Reconnect, generation: u64, error: &io::Error) -> bool {
+        let mut socket_addr = reconnect.socket_addr.lock().await;
+        if self.conn.lock().await.is_replaced(generation) {
+            return true;
+        }
+        reconnect.notify(ReconnectEvent::Disconnected {
+            socket_addr: *socket_addr,
+            reason: error.to_string(),
+        });
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            reconnect.notify(ReconnectEvent::Reconnecting {
+                socket_addr: *socket_addr,
+                attempt,
+            });
 
-            let future = match request {
-                Request::ReadCoils(addr, cnt) => Some(self.ctx.read_coils(addr, cnt)),
-                Request::ReadDiscreteInputs(addr, cnt) => {
-                    Some(self.ctx.read_discrete_inputs(addr, cnt))
+            let connect = connect_tcp(
+                &reconnect.socket_addrs,
+                reconnect.slave,
+                reconnect.connect_timeout,
+                reconnect.pipeline,
+            );
+            let reason = match connect.await {
+                Ok((ctx, pipeline, connected_addr)) => {
+                    let mut conn = self.conn.lock().await;
+                    conn.ctx = ctx;
+                    conn.pipeline = pipeline;
+                    conn.generation += 1;
+                    drop(conn);
+                    *socket_addr = connected_addr;
+                    reconnect.notify(ReconnectEvent::Reconnected {
+                        socket_addr: connected_addr,
+                        attempts: attempt,
+                    });
+                    return true;
                 }
-                _ => None,
+                Err(e) => e.to_string(),
             };
 
-            if let Some(future) = future {
-                match timeout(timeout_duration, future).await {
-                    Ok(Ok(response)) => {
-                        return Ok(ResultValue::Bool(response));
-                    }
-                    Ok(Err(e)) => {
-                        println!("{:?}", e);
-                        bail!(e)
-                    }
-                    Err(_) => {
-                        retry_count -= 1;
-                    }
+            match reconnect.policy.next_delay(attempt) {
+                Some(delay) => sleep(delay).await,
+                None => {
+                    reconnect.notify(ReconnectEvent::Failed {
+                        socket_addr: *socket_addr,
+                        attempts: attempt,
+                        reason,
+                    });
+                    return false;
                 }
-            } else {
-                bail!("Out of handle_timeout options range")
             }
         }
-        bail!("Timeout: deadline has elapsed")
     }
 }
 
-fn result_value_bool(result: ResultValue) -> Result<Vec<bool>> {
-    match result {
-        ResultValue::Bool(v) => Ok(v),
+impl Connection {
+    /// 连接是否已经被其他 Client 重连
+    ///
+    /// 流水线模式下同时等待的请求都会收到错误, 流水线没有关闭说明其他 Client 已经重连.
+    #[cfg(feature = "modbus_tcp_client")]
+    fn is_replaced(&self, generation: u64) -> bool {
+        self.generation != generation
+            || self
+                .pipeline
+                .as_ref()
+                .is_some_and(|pipeline| !pipeline.is_closed())
+    }
+}
+
+/// 把广播写请求转换成 tokio-modbus 的请求
+pub(crate) fn broadcast_request(request: BroadcastWrite<'_>) -> Request<'_> {
+    match request {
+        BroadcastWrite::SingleCoil(address, value) => Request::WriteSingleCoil(address, value),
+        BroadcastWrite::SingleRegister(address, value) => {
+            Request::WriteSingleRegister(address, value)
+        }
+        BroadcastWrite::MultipleCoils(address, value) => {
+            Request::WriteMultipleCoils(address, Cow::from(value))
+        }
+        BroadcastWrite::MultipleRegisters(address, value) => {
+            Request::WriteMultipleRegisters(address, Cow::from(value))
+        }
+        BroadcastWrite::MaskWriteRegister(address, and_mask, or_mask) => {
+            Request::MaskWriteRegister(address, and_mask, or_mask)
+        }
+    }
+}
+
+/// 是否是写请求, 读写多个寄存器 (0x17) 也会读取, 不算写请求
+fn is_write(request: &Request<'_>) -> bool {
+    matches!(
+        request,
+        Request::WriteSingleCoil(..)
+            | Request::WriteMultipleCoils(..)
+            | Request::WriteSingleRegister(..)
+            | Request::WriteMultipleRegisters(..)
+            | Request::MaskWriteRegister(..)
+    )
+}
+
+/// 依次连接每个地址, 每个地址都在超时时间内建立 tcp 连接
+///
+/// 返回第一个连接成功的地址, 全部失败时返回最后一个错误. `pipeline` 大于 1 时同时返回流水线.
+#[cfg(feature = "modbus_tcp_client")]
+async fn connect_tcp(
+    socket_addrs: &[SocketAddr],
+    slave: Slave,
+    connect_timeout: Duration,
+    pipeline: usize,
+) -> Result<(client::Context, Option<Pipeline>, SocketAddr)> {
+    let mut last_error =
+        io::Error::new(io::ErrorKind::InvalidInput, "No socket address to connect");
+
+    for &socket_addr in socket_addrs {
+        match timeout(connect_timeout, TcpStream::connect(socket_addr)).await {
+            Ok(Ok(stream)) => {
+                if let Err(e) = stream.set_nodelay(true) {
+                    log::debug!("CLIENT: Set TCP_NODELAY on {socket_addr} failed: {e}");
+                }
+                if pipeline > 1 {
+                    let pipeline = Pipeline::new(stream, pipeline);
+                    return Ok((pipeline.context(slave), Some(pipeline), socket_addr));
+                }
+                return Ok((TcpTransport::attach(stream, slave), None, socket_addr));
+            }
+            Ok(Err(e)) => {
+                log::debug!("CLIENT: Connect {socket_addr} failed: {e}");
+                last_error = io::Error::new(e.kind(), format!("Connect {socket_addr}: {e}"));
+            }
+            Err(_) => {
+                log::debug!("CLIENT: Connect {socket_addr} timeout");
+                last_error = io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Connect timeout: {socket_addr}"),
+                );
+            }
+        }
+    }
+    Err(ModbusError::Io(last_error))
+}
+
+/// 发送一次请求
+async fn call(ctx: &mut client::Context, request: &Request<'_>) -> Result<ResultValue> {
+    let value = match *request {
+        Request::ReadCoils(addr, cnt) => ResultValue::Bool(ctx.read_coils(addr, cnt).await?),
+        Request::ReadDiscreteInputs(addr, cnt) => {
+            ResultValue::Bool(ctx.read_discrete_inputs(addr, cnt).await?)
+        }
+        Request::ReadHoldingRegisters(addr, cnt) => {
+            ResultValue::U16(ctx.read_holding_registers(addr, cnt).await?)
+        }
+        Request::ReadInputRegisters(addr, cnt) => {
+            ResultValue::U16(ctx.read_input_registers(addr, cnt).await?)
+        }
+        Request::ReadWriteMultipleRegisters(read_addr, read_count, write_addr, ref write_data) => {
+            ResultValue::U16(
+                ctx.read_write_multiple_registers(read_addr, read_count, write_addr, write_data)
+                    .await?,
+            )
+        }
+        Request::WriteSingleCoil(address, coil) => {
+            ctx.write_single_coil(address, coil).await?;
+            ResultValue::Unit
+        }
+        Request::WriteSingleRegister(address, data) => {
+            ctx.write_single_register(address, data).await?;
+            ResultValue::Unit
+        }
+        Request::WriteMultipleCoils(address, ref coil) => {
+            ctx.write_multiple_coils(address, coil).await?;
+            ResultValue::Unit
+        }
+        Request::WriteMultipleRegisters(address, ref data) => {
+            ctx.write_multiple_registers(address, data).await?;
+            ResultValue::Unit
+        }
+        Request::MaskWriteRegister(address, and_mask, or_mask) => {
+            ctx.masked_write_register(address, and_mask, or_mask)
+                .await?;
+            ResultValue::Unit
+        }
         _ => {
-            bail!("Result is not bool")
+            return Err(ModbusError::InvalidRequest(
+                "Out of handle_timeout options range".to_string(),
+            ))
         }
+    };
+    Ok(value)
+}
+
+/// 不是幂等的写请求超时或者发生 I/O 错误时, 不知道从机是否已经执行
+fn outcome(idempotent: bool, error: ModbusError) -> ModbusError {
+    match error {
+        ModbusError::Timeout { .. } | ModbusError::Io(_) if !idempotent => {
+            ModbusError::OutcomeUnknown(Box::new(error))
+        }
+        error => error,
+    }
+}
+
+/// 检查从 `address` 开始的 `count` 个地址没有超出地址范围
+fn check_range(address: u16, count: usize) -> Result<()> {
+    if usize::from(address) + count > 0x1_0000 {
+        return Err(ModbusError::InvalidRequest(format!(
+            "{count} elements starting at {address} exceed the address space"
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn result_value_unit(result: ResultValue) -> Result<()> {
+    match result {
+        ResultValue::Unit => Ok(()),
+        _ => Err(ModbusError::Protocol("Result is not unit".to_string())),
+    }
+}
+
+pub(crate) fn result_value_bool(result: ResultValue) -> Result<Vec<bool>> {
+    match result {
+        ResultValue::Bool(v) => Ok(v),
+        _ => Err(ModbusError::Protocol("Result is not bool".to_string())),
     }
 }
 
-fn result_value_u16(result: ResultValue) -> Result<Vec<u16>> {
+pub(crate) fn result_value_u16(result: ResultValue) -> Result<Vec<u16>> {
     match result {
         ResultValue::U16(v) => Ok(v),
-        _ => {
-            bail!("Result is not u16")
+        _ => Err(ModbusError::Protocol("Result is not u16".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "modbus_tcp_client")]
+    use crate::mock::{
+        bits_response, read_range, registers_response, write_response, MockServer, Reply,
+    };
+    use crate::Reader;
+    use crate::Writer;
+    #[cfg(feature = "modbus_rtu_client")]
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    #[cfg(feature = "modbus_rtu_client")]
+    use tokio::time::Instant;
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[tokio::test]
+    async fn broadcast_turnaround_longer_than_timeout() {
+        let (transport, mut server) = duplex(1024);
+        let turnaround = Duration::from_millis(200);
+        let client = ClientBuilder::new()
+            .timeout(Duration::from_millis(50))
+            .retry_count(1)
+            .rtu_turnaround(turnaround)
+            .build_rtu(transport)
+            .await
+            .unwrap();
+
+        // 广播在返回之前等待转换延时
+        let started = Instant::now();
+        client
+            .broadcast()
+            .write_single_register(1, 5)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= turnaround);
+        let mut frame = [0; 8];
+        server.read_exact(&mut frame).await.unwrap();
+        assert_eq!(frame[..6], [0x00, 0x06, 0x00, 0x01, 0x00, 0x05]);
+
+        // 之后的请求不再等待, 只发送一次也不会超时
+        let script = tokio::spawn(async move {
+            server.read_exact(&mut frame).await.unwrap();
+            let mut response = vec![0x01, 0x03, 0x02, 0x00, 0x07];
+            let crc = crate::transport::crc16(&response);
+            response.extend_from_slice(&crc.to_le_bytes());
+            server.write_all(&response).await.unwrap();
+            server
+        });
+        let mut client = client.for_unit(1);
+        assert_eq!(client.read_holding_registers(0, 1).await.unwrap(), [7]);
+        script.await.unwrap();
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[tokio::test]
+    async fn broadcast_write() {
+        let (transport, mut server) = duplex(1024);
+        let mut client = ClientBuilder::new().build_rtu(transport).await.unwrap();
+
+        client
+            .broadcast_write(BroadcastWrite::SingleCoil(2, true))
+            .await
+            .unwrap();
+        let mut frame = [0; 8];
+        server.read_exact(&mut frame).await.unwrap();
+        assert_eq!(frame[..6], [0x00, 0x05, 0x00, 0x02, 0xFF, 0x00]);
+
+        let result = client.broadcast().read_holding_registers(0, 1).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[tokio::test]
+    async fn unit_zero_is_broadcast() {
+        let (transport, mut server) = duplex(1024);
+        let turnaround = Duration::from_millis(100);
+        let mut client = ClientBuilder::new()
+            .slave_id(0)
+            .timeout(Duration::from_millis(50))
+            .retry_count(3)
+            .rtu_turnaround(turnaround)
+            .build_rtu(transport)
+            .await
+            .unwrap();
+
+        // 不等待响应, 只发送一次, 等待转换延时后返回成功
+        let started = Instant::now();
+        client.write_single_register(1, 5).await.unwrap();
+        assert!(started.elapsed() >= turnaround);
+        let result = client.read_holding_registers(0, 1).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+
+        let mut unit = client.for_unit(1);
+        unit.set_slave_id(0);
+        unit.write_single_coil(2, true).await.unwrap();
+        let result = client.for_unit(0).read_coils(0, 1).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+
+        drop((client, unit));
+        let mut frames = Vec::new();
+        server.read_to_end(&mut frames).await.unwrap();
+        assert_eq!(frames.len(), 16);
+        assert_eq!(frames[..6], [0x00, 0x06, 0x00, 0x01, 0x00, 0x05]);
+        assert_eq!(frames[8..14], [0x00, 0x05, 0x00, 0x02, 0xFF, 0x00]);
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn tcp_unit_zero_is_not_broadcast() {
+        let server = MockServer::start(device).await;
+        let mut client = ClientBuilder::new()
+            .slave_id(0)
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+
+        assert_eq!(client.read_holding_registers(3, 1).await.unwrap(), [3]);
+        client.write_single_register(1, 5).await.unwrap();
+        assert_eq!(server.requests()[1].0, 0);
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn tcp_rejects_broadcast() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await });
+        let mut client = ClientBuilder::new().build_tcp(addr).await.unwrap();
+        let _server = accept.await.unwrap().unwrap();
+
+        let result = client
+            .broadcast_write(BroadcastWrite::SingleRegister(1, 5))
+            .await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+        let result = client.broadcast().write_single_coil(0, false).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+    }
+
+    /// 按照地址返回寄存器的值
+    #[cfg(feature = "modbus_tcp_client")]
+    fn echo_address(_: u8, pdu: &[u8]) -> Reply {
+        let (address, count) = read_range(pdu);
+        let values: Vec<u16> = (u32::from(address)..u32::from(address) + u32::from(count))
+            .map(|value| value as u16)
+            .collect();
+        Reply::Pdu(registers_response(pdu[0], &values))
+    }
+
+    /// 响应所有的读写请求, 寄存器的值等于地址, 线圈都是 false
+    #[cfg(feature = "modbus_tcp_client")]
+    fn device(unit_id: u8, pdu: &[u8]) -> Reply {
+        match pdu[0] {
+            0x01 | 0x02 => Reply::Pdu(bits_response(pdu[0], read_range(pdu).1)),
+            0x03 | 0x04 => echo_address(unit_id, pdu),
+            _ => Reply::Pdu(write_response(pdu)),
         }
     }
+
+    /// 返回每个请求的功能码, 地址和数量
+    #[cfg(feature = "modbus_tcp_client")]
+    fn requests(server: &MockServer) -> Vec<(u8, u16, u16)> {
+        server
+            .requests()
+            .iter()
+            .map(|(_, pdu)| {
+                let (address, count) = read_range(pdu);
+                (pdu[0], address, count)
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn reconnect_after_server_restart() {
+        let server = MockServer::start(echo_address).await;
+        let addr = server.addr;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut client = ClientBuilder::new()
+            .reconnect_policy(FixedRetry::new(Duration::from_millis(20), 100))
+            .on_reconnect({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push(event)
+            })
+            .build_tcp(addr)
+            .await
+            .unwrap();
+        assert_eq!(client.read_holding_registers(7, 1).await.unwrap(), [7]);
+
+        server.stop().await;
+        let read = tokio::spawn({
+            let mut client = client.clone();
+            async move { client.read_holding_registers(8, 1).await }
+        });
+        // 等待至少一次重连失败
+        while !events.lock().unwrap().iter().any(
+            |event| matches!(event, ReconnectEvent::Reconnecting { attempt, .. } if *attempt >= 2),
+        ) {
+            sleep(Duration::from_millis(5)).await;
+        }
+        // 等待重连时不占用连接
+        assert!(client.conn.try_lock().is_ok());
+
+        let _server = MockServer::start_at(addr, echo_address).await;
+        assert_eq!(read.await.unwrap().unwrap(), [8]);
+        assert_eq!(client.read_holding_registers(9, 1).await.unwrap(), [9]);
+
+        let events = events.lock().unwrap();
+        assert!(matches!(
+            events.first(),
+            Some(ReconnectEvent::Disconnected { socket_addr, .. }) if *socket_addr == addr
+        ));
+        assert!(matches!(
+            events.last(),
+            Some(ReconnectEvent::Reconnected { attempts, .. }) if *attempts >= 2
+        ));
+        let disconnects = events
+            .iter()
+            .filter(|event| matches!(event, ReconnectEvent::Disconnected { .. }))
+            .count();
+        assert_eq!(disconnects, 1);
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn reconnect_after_connection_closed() {
+        let mut closed = false;
+        let server = MockServer::start(move |unit_id, pdu| {
+            if read_range(pdu).0 == 8 && !closed {
+                closed = true;
+                return Reply::Close;
+            }
+            echo_address(unit_id, pdu)
+        })
+        .await;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut client = ClientBuilder::new()
+            .on_reconnect({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push(event)
+            })
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+
+        assert_eq!(client.read_holding_registers(8, 2).await.unwrap(), [8, 9]);
+        assert_eq!(server.requests().len(), 2);
+        let events = events.lock().unwrap();
+        assert!(matches!(
+            events[..],
+            [
+                ReconnectEvent::Disconnected { .. },
+                ReconnectEvent::Reconnecting { attempt: 1, .. },
+                ReconnectEvent::Reconnected { attempts: 1, .. },
+            ]
+        ));
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn split_reads_at_protocol_limits() {
+        let server = MockServer::start(device).await;
+        let mut client = ClientBuilder::new().build_tcp(server.addr).await.unwrap();
+
+        let values = client.read_holding_registers(10, 125).await.unwrap();
+        assert_eq!(values.len(), 125);
+        let values = client.read_input_registers(10, 251).await.unwrap();
+        assert_eq!(values, (10..261).collect::<Vec<u16>>());
+        assert_eq!(client.read_coils(0, 2000).await.unwrap().len(), 2000);
+        assert_eq!(
+            client.read_discrete_inputs(5, 2001).await.unwrap().len(),
+            2001
+        );
+        assert_eq!(
+            requests(&server),
+            [
+                (0x03, 10, 125),
+                (0x04, 10, 125),
+                (0x04, 135, 125),
+                (0x04, 260, 1),
+                (0x01, 0, 2000),
+                (0x02, 5, 2000),
+                (0x02, 2005, 1),
+            ]
+        );
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn split_writes_at_protocol_limits() {
+        let server = MockServer::start(device).await;
+        let mut client = ClientBuilder::new().build_tcp(server.addr).await.unwrap();
+
+        client.write_multiple_registers(0, &[1; 123]).await.unwrap();
+        client
+            .write_multiple_registers(200, &[1; 124])
+            .await
+            .unwrap();
+        client.write_multiple_coils(0, &[true; 1968]).await.unwrap();
+        client
+            .write_multiple_coils(3000, &[true; 1969])
+            .await
+            .unwrap();
+        assert_eq!(
+            requests(&server),
+            [
+                (0x10, 0, 123),
+                (0x10, 200, 123),
+                (0x10, 323, 1),
+                (0x0F, 0, 1968),
+                (0x0F, 3000, 1968),
+                (0x0F, 4968, 1),
+            ]
+        );
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn reject_split_requests_past_address_space() {
+        let server = MockServer::start(device).await;
+        let mut client = ClientBuilder::new().build_tcp(server.addr).await.unwrap();
+
+        // 最后一个地址是 0xFFFF
+        let values = client
+            .read_holding_registers(0xFFFF - 249, 250)
+            .await
+            .unwrap();
+        assert_eq!(values.last(), Some(&0xFFFF));
+        client
+            .write_multiple_registers(0xFFFF - 123, &[0; 124])
+            .await
+            .unwrap();
+        let sent = server.requests().len();
+
+        let result = client.read_holding_registers(0xFFFF - 248, 250).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+        let result = client.read_coils(0xFFFF, 2001).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+        let result = client
+            .write_multiple_registers(0xFFFF - 122, &[0; 124])
+            .await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+        let result = client.write_multiple_coils(0xFFFF, &[false; 1969]).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+        // 超出地址范围的请求一个也没有发送
+        assert_eq!(server.requests().len(), sent);
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn write_whole_address_space() {
+        let server = MockServer::start(device).await;
+        let mut client = ClientBuilder::new()
+            .request_limits(RequestLimits::new().write_bits(1024))
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+
+        // 65536 个线圈覆盖整个地址空间, 最后一个请求写到 0xFFFF
+        client
+            .write_multiple_coils(0, &vec![true; 0x1_0000])
+            .await
+            .unwrap();
+        let requests = requests(&server);
+        assert_eq!(requests.len(), 64);
+        assert_eq!(requests[0], (0x0F, 0, 1024));
+        assert_eq!(requests[63], (0x0F, 0xFC00, 1024));
+
+        let result = client.write_multiple_coils(0, &vec![true; 0x1_0001]).await;
+        assert!(matches!(result, Err(ModbusError::InvalidRequest(_))));
+        assert_eq!(server.requests().len(), 64);
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn report_partial_write() {
+        let server = MockServer::start(|unit_id, pdu| match read_range(pdu).0 {
+            10 => Reply::Pdu(vec![pdu[0] | 0x80, 0x02]),
+            746 => Reply::Silent,
+            _ => device(unit_id, pdu),
+        })
+        .await;
+        let mut client = ClientBuilder::new()
+            .timeout(Duration::from_millis(50))
+            .idempotency(Idempotency::no_write_retry())
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+
+        // 第一个请求失败时没有写入任何值, 直接返回原来的错误
+        let result = client.write_multiple_registers(10, &[0; 300]).await;
+        assert_eq!(
+            result.unwrap_err().exception(),
+            Some(Exception::IllegalDataAddress)
+        );
+
+        let error = client
+            .write_multiple_registers(500, &[0; 400])
+            .await
+            .unwrap_err();
+        assert!(error.is_outcome_unknown());
+        match error {
+            ModbusError::PartialWrite {
+                address,
+                written,
+                source,
+            } => {
+                assert_eq!((address, written), (500, 246));
+                assert!(matches!(
+                    *source,
+                    ModbusError::OutcomeUnknown(ref e) if e.is_timeout()
+                ));
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+        // 失败的请求只发送一次, 之后的部分没有发送
+        assert_eq!(
+            requests(&server),
+            [
+                (0x10, 10, 123),
+                (0x10, 500, 123),
+                (0x10, 623, 123),
+                (0x10, 746, 123),
+            ]
+        );
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn retry_only_idempotent_requests() {
+        let server = MockServer::start(|_, _| Reply::Silent).await;
+        let mut client = ClientBuilder::new()
+            .timeout(Duration::from_millis(50))
+            .retry_count(3)
+            .idempotency(Idempotency::new().registers(100..=109))
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+
+        // 不是幂等的写请求只发送一次
+        let error = client
+            .write_multiple_registers(108, &[1, 2, 3])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ModbusError::OutcomeUnknown(ref e) if matches!(**e, ModbusError::Timeout { attempts: 1 })
+        ));
+        assert_eq!(requests(&server), [(0x10, 108, 3)]);
+
+        // 同样的地址, 读请求按照重试策略发送
+        let error = client.read_holding_registers(108, 3).await.unwrap_err();
+        assert!(
+            matches!(error, ModbusError::Timeout { attempts: 3 }),
+            "{error}"
+        );
+        assert_eq!(server.requests().len(), 4);
+
+        // 范围外的写请求是幂等的
+        let error = client.write_single_register(110, 1).await.unwrap_err();
+        assert!(
+            matches!(error, ModbusError::Timeout { attempts: 3 }),
+            "{error}"
+        );
+        assert_eq!(server.requests().len(), 7);
+    }
 }
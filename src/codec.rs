@@ -0,0 +1,891 @@
+//! 寄存器数据编解码.
+//!
+//! 一个 Modbus 寄存器只有 16 位, 32 位和 64 位的数值需要占用多个连续的寄存器.
+//! 不同设备存放字节的顺序不同, 用 [`ByteOrder`] 表示. 以 32 位数值 `0x11223344` 为例,
+//! 字节 A, B, C, D 分别是 `0x11`, `0x22`, `0x33`, `0x44`:
+//!
+//! | 字节序 | 寄存器 |
+//! | --- | --- |
+//! | [`ByteOrder::Abcd`] | `[0x1122, 0x3344]` |
+//! | [`ByteOrder::Cdab`] | `[0x3344, 0x1122]` |
+//! | [`ByteOrder::Badc`] | `[0x2211, 0x4433]` |
+//! | [`ByteOrder::Dcba`] | `[0x4433, 0x2211]` |
+//!
+//! 64 位数值按照同样的规则处理: `Cdab` 和 `Dcba` 把寄存器的顺序反过来, `Badc` 和 `Dcba` 交换每个寄存器中的两个字节.
+//!
+//! 除了数值, 还支持以下几种常见的数据格式:
+//! - 字符串: 每个寄存器存放两个 ASCII 字符, 见 [`StringFormat`]
+//! - BCD 码: 每 4 位存放一个十进制数字, 一个寄存器存放 4 个数字
+//! - 位域: 一个寄存器中的若干位表示一个状态
+
+use crate::error::{ModbusError, Result};
+use crate::{Reader, Writer};
+use async_trait::async_trait;
+
+/// 多寄存器数值的字节序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ByteOrder {
+    /// 大端 (ABCD), Modbus 协议的标准顺序
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "ABCD", alias = "abcd"))]
+    Abcd,
+    /// 寄存器小端, 寄存器内大端 (CDAB)
+    #[cfg_attr(feature = "serde", serde(rename = "CDAB", alias = "cdab"))]
+    Cdab,
+    /// 寄存器大端, 寄存器内小端 (BADC)
+    #[cfg_attr(feature = "serde", serde(rename = "BADC", alias = "badc"))]
+    Badc,
+    /// 小端 (DCBA)
+    #[cfg_attr(feature = "serde", serde(rename = "DCBA", alias = "dcba"))]
+    Dcba,
+}
+
+impl ByteOrder {
+    /// 是否交换寄存器的顺序
+    fn swap_words(self) -> bool {
+        matches!(self, ByteOrder::Cdab | ByteOrder::Dcba)
+    }
+
+    /// 是否交换寄存器中的两个字节
+    fn swap_bytes(self) -> bool {
+        matches!(self, ByteOrder::Badc | ByteOrder::Dcba)
+    }
+
+    /// 把大端字节转换成寄存器
+    fn to_registers(self, bytes: &[u8]) -> Vec<u16> {
+        let mut registers: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| {
+                let register = u16::from_be_bytes([pair[0], pair[1]]);
+                if self.swap_bytes() {
+                    register.swap_bytes()
+                } else {
+                    register
+                }
+            })
+            .collect();
+        if self.swap_words() {
+            registers.reverse();
+        }
+        registers
+    }
+
+    /// 把寄存器转换成大端字节
+    fn to_bytes(self, registers: &[u16]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(registers.len() * 2);
+        let mut push = |register: u16| {
+            let register = if self.swap_bytes() {
+                register.swap_bytes()
+            } else {
+                register
+            };
+            bytes.extend_from_slice(&register.to_be_bytes());
+        };
+        if self.swap_words() {
+            registers.iter().rev().copied().for_each(&mut push);
+        } else {
+            registers.iter().copied().for_each(&mut push);
+        }
+        bytes
+    }
+}
+
+/// 可以存放在连续寄存器中的数值
+pub trait RegisterValue: Sized + Send {
+    /// 占用的寄存器数量
+    const REGISTERS: u16;
+
+    /// 从寄存器解码
+    ///
+    /// # 参数
+    /// - registers: 寄存器, 数量必须等于 [`RegisterValue::REGISTERS`]
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回解码后的数值
+    /// - 失败: 寄存器数量不对
+    fn from_registers(registers: &[u16], order: ByteOrder) -> Result<Self>;
+
+    /// 编码成寄存器
+    fn to_registers(&self, order: ByteOrder) -> Vec<u16>;
+}
+
+macro_rules! impl_register_value {
+    ($($ty:ty),*) => {
+        $(
+            impl RegisterValue for $ty {
+                const REGISTERS: u16 = (std::mem::size_of::<$ty>() / 2) as u16;
+
+                fn from_registers(registers: &[u16], order: ByteOrder) -> Result<Self> {
+                    check_len(registers, Self::REGISTERS, stringify!($ty))?;
+                    let bytes = order.to_bytes(registers);
+                    // 上面已经检查过长度
+                    Ok(<$ty>::from_be_bytes(bytes.try_into().unwrap()))
+                }
+
+                fn to_registers(&self, order: ByteOrder) -> Vec<u16> {
+                    order.to_registers(&self.to_be_bytes())
+                }
+            }
+        )*
+    };
+}
+
+impl_register_value!(u16, i16, u32, i32, f32, u64, i64, f64);
+
+/// 检查寄存器数量
+pub(crate) fn check_len(registers: &[u16], expected: u16, name: &str) -> Result<()> {
+    if registers.len() != usize::from(expected) {
+        return Err(ModbusError::InvalidRequest(format!(
+            "{name} needs {expected} registers, got {}",
+            registers.len()
+        )));
+    }
+    Ok(())
+}
+
+/// 从寄存器解码一个数值
+pub fn decode<T: RegisterValue>(registers: &[u16], order: ByteOrder) -> Result<T> {
+    T::from_registers(registers, order)
+}
+
+/// 从寄存器解码多个连续的数值
+///
+/// # 返回
+/// - 成功: 返回解码后的数值
+/// - 失败: 寄存器数量不是单个数值占用数量的整数倍
+pub fn decode_all<T: RegisterValue>(registers: &[u16], order: ByteOrder) -> Result<Vec<T>> {
+    let size = usize::from(T::REGISTERS);
+    if !registers.len().is_multiple_of(size) {
+        return Err(ModbusError::InvalidRequest(format!(
+            "{} registers can not be split into values of {size} registers",
+            registers.len()
+        )));
+    }
+    registers
+        .chunks(size)
+        .map(|chunk| T::from_registers(chunk, order))
+        .collect()
+}
+
+/// 把一个数值编码成寄存器
+pub fn encode<T: RegisterValue>(value: &T, order: ByteOrder) -> Vec<u16> {
+    value.to_registers(order)
+}
+
+/// 把多个数值编码成连续的寄存器
+pub fn encode_all<T: RegisterValue>(values: &[T], order: ByteOrder) -> Vec<u16> {
+    values
+        .iter()
+        .flat_map(|value| value.to_registers(order))
+        .collect()
+}
+
+/// 字符串格式
+///
+/// 默认每个寄存器的高字节是第一个字符, 写入时用 `\0` 补齐, 读取时去掉末尾的 `\0` 和空格.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StringFormat {
+    swap_bytes: bool,
+    padding: u8,
+    trim: bool,
+}
+
+impl Default for StringFormat {
+    fn default() -> Self {
+        StringFormat {
+            swap_bytes: false,
+            padding: 0,
+            trim: true,
+        }
+    }
+}
+
+impl StringFormat {
+    /// 使用默认格式
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置是否交换每个寄存器中的两个字符, 交换后寄存器的低字节是第一个字符
+    pub fn swap_bytes(mut self, swap_bytes: bool) -> Self {
+        self.swap_bytes = swap_bytes;
+        self
+    }
+
+    /// 设置写入时补齐长度使用的字符, 比如 `b' '`
+    pub fn padding(mut self, padding: u8) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// 设置读取时是否去掉末尾的补齐字符, `\0` 和空格
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+}
+
+/// 从寄存器解码字符串
+///
+/// # 返回
+/// - 成功: 返回字符串
+/// - 失败: 内容不是合法的 UTF-8
+pub fn decode_string(registers: &[u16], format: StringFormat) -> Result<String> {
+    let mut bytes: Vec<u8> = registers
+        .iter()
+        .flat_map(|&register| {
+            let [high, low] = register.to_be_bytes();
+            if format.swap_bytes {
+                [low, high]
+            } else {
+                [high, low]
+            }
+        })
+        .collect();
+    if format.trim {
+        while bytes
+            .last()
+            .is_some_and(|&b| b == format.padding || b == 0 || b == b' ')
+        {
+            bytes.pop();
+        }
+    }
+    String::from_utf8(bytes)
+        .map_err(|e| ModbusError::InvalidRequest(format!("String is not valid UTF-8: {e}")))
+}
+
+/// 把字符串编码成固定数量的寄存器, 不足的部分使用补齐字符
+///
+/// # 返回
+/// - 成功: 返回 `registers` 个寄存器
+/// - 失败: 字符串超过 `registers * 2` 个字节
+pub fn encode_string(value: &str, registers: u16, format: StringFormat) -> Result<Vec<u16>> {
+    let len = usize::from(registers) * 2;
+    if value.len() > len {
+        return Err(ModbusError::InvalidRequest(format!(
+            "String of {} bytes does not fit in {registers} registers",
+            value.len()
+        )));
+    }
+
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(len, format.padding);
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| {
+            if format.swap_bytes {
+                u16::from_le_bytes([pair[0], pair[1]])
+            } else {
+                u16::from_be_bytes([pair[0], pair[1]])
+            }
+        })
+        .collect())
+}
+
+/// 从寄存器解码 BCD 码, 每个寄存器存放 4 个十进制数字
+///
+/// # 参数
+/// - registers: 寄存器, 最多 5 个
+/// - order: 多个寄存器时的字节序
+///
+/// # 返回
+/// - 成功: 返回解码后的数值
+/// - 失败: 包含不是 0 ~ 9 的数字或者超过 u64 的范围
+pub fn decode_bcd(registers: &[u16], order: ByteOrder) -> Result<u64> {
+    let mut value: u64 = 0;
+    for byte in order.to_bytes(registers) {
+        for digit in [byte >> 4, byte & 0x0F] {
+            if digit > 9 {
+                return Err(ModbusError::InvalidRequest(format!(
+                    "{registers:04X?} is not valid BCD"
+                )));
+            }
+            value = value
+                .checked_mul(10)
+                .and_then(|value| value.checked_add(u64::from(digit)))
+                .ok_or_else(|| {
+                    ModbusError::InvalidRequest(format!("BCD {registers:04X?} overflows u64"))
+                })?;
+        }
+    }
+    Ok(value)
+}
+
+/// 把数值编码成固定数量寄存器的 BCD 码
+///
+/// # 返回
+/// - 成功: 返回 `registers` 个寄存器
+/// - 失败: 数值超过 `registers * 4` 个十进制数字
+pub fn encode_bcd(value: u64, registers: u16, order: ByteOrder) -> Result<Vec<u16>> {
+    let digits = usize::from(registers) * 4;
+    let mut bytes = vec![0u8; digits / 2];
+    let mut rest = value;
+    for i in (0..digits).rev() {
+        let digit = (rest % 10) as u8;
+        rest /= 10;
+        bytes[i / 2] |= if i % 2 == 0 { digit << 4 } else { digit };
+    }
+    if rest != 0 {
+        return Err(ModbusError::InvalidRequest(format!(
+            "{value} does not fit in {registers} BCD registers"
+        )));
+    }
+    Ok(order.to_registers(&bytes))
+}
+
+/// 检查位域的范围
+fn check_bit_field(offset: u8, width: u8) -> Result<()> {
+    if width == 0 || u16::from(offset) + u16::from(width) > 16 {
+        return Err(ModbusError::InvalidRequest(format!(
+            "Bit field of {width} bits at offset {offset} does not fit in a register"
+        )));
+    }
+    Ok(())
+}
+
+/// 位域的掩码, 不包含偏移
+fn bit_field_mask(width: u8) -> u16 {
+    (u32::MAX >> (32 - u32::from(width))) as u16
+}
+
+/// 返回寄存器中的一位, `index` 为 0 表示最低位
+pub fn bit(register: u16, index: u8) -> bool {
+    index < 16 && register & (1 << index) != 0
+}
+
+/// 把寄存器按照从低位到高位的顺序展开成位, 每个寄存器 16 位
+pub fn unpack_bits(registers: &[u16]) -> Vec<bool> {
+    registers
+        .iter()
+        .flat_map(|&register| (0..16).map(move |index| bit(register, index)))
+        .collect()
+}
+
+/// 取出寄存器中的位域
+///
+/// # 参数
+/// - register: 寄存器
+/// - offset: 位域最低位的位置, 0 表示寄存器的最低位
+/// - width: 位域的宽度
+///
+/// # 返回
+/// - 成功: 返回位域的值
+/// - 失败: 位域超出了寄存器
+pub fn bit_field(register: u16, offset: u8, width: u8) -> Result<u16> {
+    check_bit_field(offset, width)?;
+    Ok((register >> offset) & bit_field_mask(width))
+}
+
+/// 把位域的值设置到寄存器中, 其他位不变
+///
+/// # 返回
+/// - 成功: 返回设置后的寄存器
+/// - 失败: 位域超出了寄存器或者值超过了位域的宽度
+pub fn with_bit_field(register: u16, offset: u8, width: u8, value: u16) -> Result<u16> {
+    let (and_mask, or_mask) = bit_field_masks(offset, width, value)?;
+    Ok((register & and_mask) | or_mask)
+}
+
+/// 返回设置位域使用的 AND 掩码和 OR 掩码
+fn bit_field_masks(offset: u8, width: u8, value: u16) -> Result<(u16, u16)> {
+    check_bit_field(offset, width)?;
+    let mask = bit_field_mask(width);
+    if value > mask {
+        return Err(ModbusError::InvalidRequest(format!(
+            "{value} does not fit in a bit field of {width} bits"
+        )));
+    }
+    Ok((!(mask << offset), value << offset))
+}
+
+/// 读取多寄存器数据的扩展方法, 所有实现了 [`Reader`] 的类型都可以使用
+#[async_trait]
+pub trait ReaderExt: Reader + Send {
+    /// 从保持寄存器读取一个数值
+    ///
+    /// # 参数
+    /// - address: 数值的第一个寄存器地址
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回读取的数值
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_value<T: RegisterValue>(&mut self, address: u16, order: ByteOrder) -> Result<T> {
+        let registers = self.read_holding_registers(address, T::REGISTERS).await?;
+        T::from_registers(&registers, order).map_err(protocol)
+    }
+
+    /// 从输入寄存器读取一个数值
+    ///
+    /// # 参数
+    /// - address: 数值的第一个寄存器地址
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回读取的数值
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_input_value<T: RegisterValue>(
+        &mut self,
+        address: u16,
+        order: ByteOrder,
+    ) -> Result<T> {
+        let registers = self.read_input_registers(address, T::REGISTERS).await?;
+        T::from_registers(&registers, order).map_err(protocol)
+    }
+
+    /// 从保持寄存器读取多个连续的数值
+    ///
+    /// # 参数
+    /// - address: 第一个数值的第一个寄存器地址
+    /// - count: 数值的数量
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回读取的数值
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_values<T: RegisterValue>(
+        &mut self,
+        address: u16,
+        count: u16,
+        order: ByteOrder,
+    ) -> Result<Vec<T>> {
+        let registers = count.checked_mul(T::REGISTERS).ok_or_else(|| {
+            ModbusError::InvalidRequest(format!("{count} values exceed the address space"))
+        })?;
+        let registers = self.read_holding_registers(address, registers).await?;
+        decode_all(&registers, order).map_err(protocol)
+    }
+
+    /// 从保持寄存器读取 u32
+    async fn read_u32(&mut self, address: u16, order: ByteOrder) -> Result<u32> {
+        self.read_value(address, order).await
+    }
+
+    /// 从保持寄存器读取 i32
+    async fn read_i32(&mut self, address: u16, order: ByteOrder) -> Result<i32> {
+        self.read_value(address, order).await
+    }
+
+    /// 从保持寄存器读取 f32
+    async fn read_f32(&mut self, address: u16, order: ByteOrder) -> Result<f32> {
+        self.read_value(address, order).await
+    }
+
+    /// 从保持寄存器读取 u64
+    async fn read_u64(&mut self, address: u16, order: ByteOrder) -> Result<u64> {
+        self.read_value(address, order).await
+    }
+
+    /// 从保持寄存器读取 i64
+    async fn read_i64(&mut self, address: u16, order: ByteOrder) -> Result<i64> {
+        self.read_value(address, order).await
+    }
+
+    /// 从保持寄存器读取 f64
+    async fn read_f64(&mut self, address: u16, order: ByteOrder) -> Result<f64> {
+        self.read_value(address, order).await
+    }
+
+    /// 从保持寄存器读取字符串
+    ///
+    /// # 参数
+    /// - address: 第一个寄存器地址
+    /// - registers: 字符串占用的寄存器数量
+    /// - format: 字符串格式
+    ///
+    /// # 返回
+    /// - 成功: 返回读取的字符串
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_string(
+        &mut self,
+        address: u16,
+        registers: u16,
+        format: StringFormat,
+    ) -> Result<String> {
+        let registers = self.read_holding_registers(address, registers).await?;
+        decode_string(&registers, format).map_err(protocol)
+    }
+
+    /// 从输入寄存器读取字符串
+    ///
+    /// # 参数
+    /// - address: 第一个寄存器地址
+    /// - registers: 字符串占用的寄存器数量
+    /// - format: 字符串格式
+    ///
+    /// # 返回
+    /// - 成功: 返回读取的字符串
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_input_string(
+        &mut self,
+        address: u16,
+        registers: u16,
+        format: StringFormat,
+    ) -> Result<String> {
+        let registers = self.read_input_registers(address, registers).await?;
+        decode_string(&registers, format).map_err(protocol)
+    }
+
+    /// 从保持寄存器读取 BCD 码
+    ///
+    /// # 参数
+    /// - address: 第一个寄存器地址
+    /// - registers: BCD 码占用的寄存器数量, 最多 5 个
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回解码后的数值
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_bcd(&mut self, address: u16, registers: u16, order: ByteOrder) -> Result<u64> {
+        let registers = self.read_holding_registers(address, registers).await?;
+        decode_bcd(&registers, order).map_err(protocol)
+    }
+
+    /// 从输入寄存器读取 BCD 码
+    ///
+    /// # 参数
+    /// - address: 第一个寄存器地址
+    /// - registers: BCD 码占用的寄存器数量, 最多 5 个
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回解码后的数值
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_input_bcd(
+        &mut self,
+        address: u16,
+        registers: u16,
+        order: ByteOrder,
+    ) -> Result<u64> {
+        let registers = self.read_input_registers(address, registers).await?;
+        decode_bcd(&registers, order).map_err(protocol)
+    }
+
+    /// 从保持寄存器读取位域
+    ///
+    /// # 参数
+    /// - address: 寄存器地址
+    /// - offset: 位域最低位的位置, 0 表示寄存器的最低位
+    /// - width: 位域的宽度
+    ///
+    /// # 返回
+    /// - 成功: 返回位域的值
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_bit_field(&mut self, address: u16, offset: u8, width: u8) -> Result<u16> {
+        check_bit_field(offset, width)?;
+        let registers = self.read_holding_registers(address, 1).await?;
+        check_len(&registers, 1, "Bit field").map_err(protocol)?;
+        bit_field(registers[0], offset, width)
+    }
+
+    /// 从输入寄存器读取位域
+    ///
+    /// # 参数
+    /// - address: 寄存器地址
+    /// - offset: 位域最低位的位置, 0 表示寄存器的最低位
+    /// - width: 位域的宽度
+    ///
+    /// # 返回
+    /// - 成功: 返回位域的值
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_input_bit_field(&mut self, address: u16, offset: u8, width: u8) -> Result<u16> {
+        check_bit_field(offset, width)?;
+        let registers = self.read_input_registers(address, 1).await?;
+        check_len(&registers, 1, "Bit field").map_err(protocol)?;
+        bit_field(registers[0], offset, width)
+    }
+}
+
+impl<R: Reader + Send + ?Sized> ReaderExt for R {}
+
+/// 写入多寄存器数据的扩展方法, 所有实现了 [`Writer`] 的类型都可以使用
+#[async_trait]
+pub trait WriterExt: Writer + Send {
+    /// 把一个数值写入保持寄存器 (0x10)
+    ///
+    /// # 参数
+    /// - address: 数值的第一个寄存器地址
+    /// - value: 要写入的值
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`]
+    async fn write_value<T: RegisterValue + Sync>(
+        &mut self,
+        address: u16,
+        value: T,
+        order: ByteOrder,
+    ) -> Result<()> {
+        let registers = value.to_registers(order);
+        self.write_multiple_registers(address, &registers).await
+    }
+
+    /// 把多个数值写入连续的保持寄存器 (0x10)
+    ///
+    /// # 参数
+    /// - address: 第一个数值的第一个寄存器地址
+    /// - values: 要写入的值
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`]
+    async fn write_values<T: RegisterValue + Sync>(
+        &mut self,
+        address: u16,
+        values: &[T],
+        order: ByteOrder,
+    ) -> Result<()> {
+        let registers = encode_all(values, order);
+        self.write_multiple_registers(address, &registers).await
+    }
+
+    /// 把 u32 写入保持寄存器
+    async fn write_u32(&mut self, address: u16, value: u32, order: ByteOrder) -> Result<()> {
+        self.write_value(address, value, order).await
+    }
+
+    /// 把 i32 写入保持寄存器
+    async fn write_i32(&mut self, address: u16, value: i32, order: ByteOrder) -> Result<()> {
+        self.write_value(address, value, order).await
+    }
+
+    /// 把 f32 写入保持寄存器
+    async fn write_f32(&mut self, address: u16, value: f32, order: ByteOrder) -> Result<()> {
+        self.write_value(address, value, order).await
+    }
+
+    /// 把 u64 写入保持寄存器
+    async fn write_u64(&mut self, address: u16, value: u64, order: ByteOrder) -> Result<()> {
+        self.write_value(address, value, order).await
+    }
+
+    /// 把 i64 写入保持寄存器
+    async fn write_i64(&mut self, address: u16, value: i64, order: ByteOrder) -> Result<()> {
+        self.write_value(address, value, order).await
+    }
+
+    /// 把 f64 写入保持寄存器
+    async fn write_f64(&mut self, address: u16, value: f64, order: ByteOrder) -> Result<()> {
+        self.write_value(address, value, order).await
+    }
+
+    /// 把字符串写入保持寄存器 (0x10), 不足的部分使用补齐字符
+    ///
+    /// # 参数
+    /// - address: 第一个寄存器地址
+    /// - value: 要写入的字符串
+    /// - registers: 字符串占用的寄存器数量
+    /// - format: 字符串格式
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`]
+    async fn write_string(
+        &mut self,
+        address: u16,
+        value: &str,
+        registers: u16,
+        format: StringFormat,
+    ) -> Result<()> {
+        let registers = encode_string(value, registers, format)?;
+        self.write_multiple_registers(address, &registers).await
+    }
+
+    /// 把数值以 BCD 码写入保持寄存器 (0x10)
+    ///
+    /// # 参数
+    /// - address: 第一个寄存器地址
+    /// - value: 要写入的值
+    /// - registers: BCD 码占用的寄存器数量
+    /// - order: 字节序
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`]
+    async fn write_bcd(
+        &mut self,
+        address: u16,
+        value: u64,
+        registers: u16,
+        order: ByteOrder,
+    ) -> Result<()> {
+        let registers = encode_bcd(value, registers, order)?;
+        self.write_multiple_registers(address, &registers).await
+    }
+
+    /// 设置保持寄存器中的位域 (0x16), 其他位不变
+    ///
+    /// # 参数
+    /// - address: 寄存器地址
+    /// - offset: 位域最低位的位置, 0 表示寄存器的最低位
+    /// - width: 位域的宽度
+    /// - value: 位域的值
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`]
+    async fn write_bit_field(
+        &mut self,
+        address: u16,
+        offset: u8,
+        width: u8,
+        value: u16,
+    ) -> Result<()> {
+        let (and_mask, or_mask) = bit_field_masks(offset, width, value)?;
+        self.masked_write_register(address, and_mask, or_mask).await
+    }
+}
+
+impl<W: Writer + Send + ?Sized> WriterExt for W {}
+
+/// 从机返回的数据解码失败时是协议错误, 不是请求参数错误
+///
+/// 派生宏生成的 `from_values` 也使用这个函数.
+#[doc(hidden)]
+pub fn protocol(e: ModbusError) -> ModbusError {
+    match e {
+        ModbusError::InvalidRequest(message) => ModbusError::Protocol(message),
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [ByteOrder; 4] = [
+        ByteOrder::Abcd,
+        ByteOrder::Cdab,
+        ByteOrder::Badc,
+        ByteOrder::Dcba,
+    ];
+
+    #[test]
+    fn u32_byte_orders() {
+        let value = 0x1234_5678_u32;
+        assert_eq!(encode(&value, ByteOrder::Abcd), [0x1234, 0x5678]);
+        assert_eq!(encode(&value, ByteOrder::Cdab), [0x5678, 0x1234]);
+        assert_eq!(encode(&value, ByteOrder::Badc), [0x3412, 0x7856]);
+        assert_eq!(encode(&value, ByteOrder::Dcba), [0x7856, 0x3412]);
+    }
+
+    #[test]
+    fn u64_byte_orders() {
+        let value = 0x0102_0304_0506_0708_u64;
+        assert_eq!(
+            encode(&value, ByteOrder::Abcd),
+            [0x0102, 0x0304, 0x0506, 0x0708]
+        );
+        assert_eq!(
+            encode(&value, ByteOrder::Cdab),
+            [0x0708, 0x0506, 0x0304, 0x0102]
+        );
+        assert_eq!(
+            encode(&value, ByteOrder::Badc),
+            [0x0201, 0x0403, 0x0605, 0x0807]
+        );
+        assert_eq!(
+            encode(&value, ByteOrder::Dcba),
+            [0x0807, 0x0605, 0x0403, 0x0201]
+        );
+    }
+
+    #[test]
+    fn round_trip_every_order() {
+        for order in ORDERS {
+            assert_eq!(
+                decode::<u16>(&encode(&0xBEEF_u16, order), order).unwrap(),
+                0xBEEF
+            );
+            assert_eq!(decode::<i16>(&encode(&-2_i16, order), order).unwrap(), -2);
+            assert_eq!(
+                decode::<u32>(&encode(&0xDEAD_BEEF_u32, order), order).unwrap(),
+                0xDEAD_BEEF
+            );
+            assert_eq!(
+                decode::<i32>(&encode(&-123_456_i32, order), order).unwrap(),
+                -123_456
+            );
+            assert_eq!(
+                decode::<f32>(&encode(&-1.5_f32, order), order).unwrap(),
+                -1.5
+            );
+            assert_eq!(
+                decode::<u64>(&encode(&(u64::MAX - 1), order), order).unwrap(),
+                u64::MAX - 1
+            );
+            assert_eq!(
+                decode::<i64>(&encode(&i64::MIN, order), order).unwrap(),
+                i64::MIN
+            );
+            assert_eq!(
+                decode::<f64>(&encode(&std::f64::consts::PI, order), order).unwrap(),
+                std::f64::consts::PI
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_all() {
+        let values = [1.0_f32, -2.5, 1e10];
+        for order in ORDERS {
+            let registers = encode_all(&values, order);
+            assert_eq!(registers.len(), 6);
+            assert_eq!(decode_all::<f32>(&registers, order).unwrap(), values);
+        }
+    }
+
+    #[test]
+    fn reject_wrong_length() {
+        assert!(decode::<u32>(&[1], ByteOrder::Abcd).is_err());
+        assert!(decode::<f64>(&[1, 2, 3], ByteOrder::Abcd).is_err());
+        assert!(decode_all::<u32>(&[1, 2, 3], ByteOrder::Abcd).is_err());
+    }
+
+    #[test]
+    fn string_round_trip() {
+        let format = StringFormat::new();
+        let registers = encode_string("ABC", 3, format).unwrap();
+        assert_eq!(registers, [0x4142, 0x4300, 0x0000]);
+        assert_eq!(decode_string(&registers, format).unwrap(), "ABC");
+
+        let format = StringFormat::new().swap_bytes(true).padding(b' ');
+        let registers = encode_string("ABC", 2, format).unwrap();
+        assert_eq!(registers, [0x4241, 0x2043]);
+        assert_eq!(decode_string(&registers, format).unwrap(), "ABC");
+
+        assert!(encode_string("ABCDE", 2, format).is_err());
+    }
+
+    #[test]
+    fn bcd_round_trip() {
+        assert_eq!(encode_bcd(1234, 1, ByteOrder::Abcd).unwrap(), [0x1234]);
+        assert_eq!(
+            encode_bcd(12_345_678, 2, ByteOrder::Cdab).unwrap(),
+            [0x5678, 0x1234]
+        );
+        assert_eq!(
+            decode_bcd(&[0x5678, 0x1234], ByteOrder::Cdab).unwrap(),
+            12_345_678
+        );
+        assert!(encode_bcd(10_000, 1, ByteOrder::Abcd).is_err());
+        assert!(decode_bcd(&[0x12A4], ByteOrder::Abcd).is_err());
+    }
+
+    #[test]
+    fn bit_fields() {
+        assert!(bit(0x0004, 2));
+        assert!(!bit(0x0004, 3));
+        assert!(!bit(0xFFFF, 16));
+        assert_eq!(unpack_bits(&[0x0005])[..4], [true, false, true, false]);
+        assert_eq!(bit_field(0xABCD, 4, 8).unwrap(), 0xBC);
+        assert_eq!(with_bit_field(0xABCD, 4, 8, 0x12).unwrap(), 0xA12D);
+        assert!(bit_field(0, 12, 8).is_err());
+        assert!(bit_field(0, 0, 0).is_err());
+        assert!(with_bit_field(0, 0, 4, 0x10).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+//! 连接地址解析.
+//!
+//! 把配置文件中的连接地址解析成 [`Endpoint`], 支持的格式:
+//! - `tcp://plc.local:502?unit=3&timeout=300ms&retries=2`
+//! - `rtu:///dev/ttyUSB0?baud=19200&parity=even&stop=1&unit=7`
+//!
+//! tcp 地址的端口默认为 502, 主机名在连接时通过 DNS 解析. IPv6 地址需要放在 `[]` 中, 比如 `tcp://[::1]:502`.
+//!
+//! rtu 地址支持的参数:
+//! - baud: 波特率, 默认 9600
+//! - parity: 校验位, `none`, `odd` 或 `even`, 默认 `none`
+//! - stop: 停止位, 1 或 2, 默认 1
+//! - data: 数据位, 5 ~ 8, 默认 8
+//!
+//! 通用参数:
+//! - unit: 从机 id, rtu 上的从机 id 0 是广播
+//! - timeout: 请求超时时间, 支持 `ms`, `s` 和 `m` 单位, 没有单位时表示毫秒
+//! - retries: 每个请求最多发送的次数
+
+use crate::error::{ModbusError, Result};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Modbus TCP 默认端口
+pub const DEFAULT_TCP_PORT: u16 = 502;
+
+/// 连接地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// 传输方式
+    pub transport: Transport,
+    /// 从机 id
+    pub unit: Option<u8>,
+    /// 请求超时时间
+    pub timeout: Option<Duration>,
+    /// 每个请求最多发送的次数
+    pub retries: Option<u32>,
+}
+
+/// 传输方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Modbus TCP
+    Tcp {
+        /// 主机名或 ip 地址
+        host: String,
+        /// 端口
+        port: u16,
+    },
+
+    /// Modbus RTU
+    Rtu(SerialSettings),
+}
+
+/// 串口参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialSettings {
+    /// 串口路径, 比如 `/dev/ttyUSB0` 或 `COM3`
+    pub path: String,
+    /// 波特率
+    pub baud_rate: u32,
+    /// 校验位
+    pub parity: Parity,
+    /// 停止位, 1 或 2
+    pub stop_bits: u8,
+    /// 数据位, 5 ~ 8
+    pub data_bits: u8,
+}
+
+/// 串口校验位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    /// 无校验
+    None,
+    /// 奇校验
+    Odd,
+    /// 偶校验
+    Even,
+}
+
+impl FromStr for Endpoint {
+    type Err = ModbusError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| invalid(s, "missing scheme"))?;
+        let (address, query) = match rest.split_once('?') {
+            Some((address, query)) => (address, query),
+            None => (rest, ""),
+        };
+
+        let mut endpoint = Endpoint {
+            transport: match scheme.to_ascii_lowercase().as_str() {
+                "tcp" => parse_tcp(s, address)?,
+                "rtu" => Transport::Rtu(SerialSettings {
+                    path: address.to_string(),
+                    baud_rate: 9600,
+                    parity: Parity::None,
+                    stop_bits: 1,
+                    data_bits: 8,
+                }),
+                _ => return Err(invalid(s, "unsupported scheme")),
+            },
+            unit: None,
+            timeout: None,
+            retries: None,
+        };
+        if let Transport::Rtu(serial) = &endpoint.transport {
+            if serial.path.is_empty() {
+                return Err(invalid(s, "missing serial port path"));
+            }
+        }
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| invalid(s, "query parameter without value"))?;
+            endpoint.apply(s, key, value)?;
+        }
+        Ok(endpoint)
+    }
+}
+
+impl Endpoint {
+    /// 解析连接地址
+    pub fn parse(s: &str) -> Result<Self> {
+        s.parse()
+    }
+
+    fn apply(&mut self, url: &str, key: &str, value: &str) -> Result<()> {
+        match (key, &mut self.transport) {
+            ("unit", _) => self.unit = Some(parse_number(url, key, value)?),
+            ("timeout", _) => self.timeout = Some(parse_duration(url, value)?),
+            ("retries", _) => self.retries = Some(parse_number(url, key, value)?),
+            ("baud", Transport::Rtu(serial)) => serial.baud_rate = parse_number(url, key, value)?,
+            ("parity", Transport::Rtu(serial)) => {
+                serial.parity = match value.to_ascii_lowercase().as_str() {
+                    "none" | "n" => Parity::None,
+                    "odd" | "o" => Parity::Odd,
+                    "even" | "e" => Parity::Even,
+                    _ => return Err(invalid(url, "parity must be none, odd or even")),
+                }
+            }
+            ("stop", Transport::Rtu(serial)) => {
+                serial.stop_bits = match value {
+                    "1" => 1,
+                    "2" => 2,
+                    _ => return Err(invalid(url, "stop must be 1 or 2")),
+                }
+            }
+            ("data", Transport::Rtu(serial)) => {
+                serial.data_bits = match parse_number(url, key, value)? {
+                    bits @ 5..=8 => bits,
+                    _ => return Err(invalid(url, "data must be between 5 and 8")),
+                }
+            }
+            _ => return Err(invalid(url, &format!("unknown parameter `{key}`"))),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut query = Vec::new();
+        match &self.transport {
+            Transport::Tcp { host, port } if host.contains(':') => {
+                write!(f, "tcp://[{host}]:{port}")?
+            }
+            Transport::Tcp { host, port } => write!(f, "tcp://{host}:{port}")?,
+            Transport::Rtu(serial) => {
+                write!(f, "rtu://{}", serial.path)?;
+                let parity = match serial.parity {
+                    Parity::None => "none",
+                    Parity::Odd => "odd",
+                    Parity::Even => "even",
+                };
+                query.push(format!("baud={}", serial.baud_rate));
+                query.push(format!("parity={parity}"));
+                query.push(format!("stop={}", serial.stop_bits));
+                query.push(format!("data={}", serial.data_bits));
+            }
+        }
+        if let Some(unit) = self.unit {
+            query.push(format!("unit={unit}"));
+        }
+        if let Some(timeout) = self.timeout {
+            query.push(format!("timeout={}ms", timeout.as_millis()));
+        }
+        if let Some(retries) = self.retries {
+            query.push(format!("retries={retries}"));
+        }
+        if !query.is_empty() {
+            write!(f, "?{}", query.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_tcp_server"))]
+impl Endpoint {
+    /// 通过 DNS 解析 tcp 地址
+    ///
+    /// # 返回
+    /// - 成功: 返回主机名对应的所有 socket 地址
+    /// - 失败: 不是 tcp 地址或者解析失败
+    pub async fn resolve(&self) -> Result<Vec<std::net::SocketAddr>> {
+        let Transport::Tcp { host, port } = &self.transport else {
+            return Err(ModbusError::InvalidRequest(format!(
+                "{self} is not a tcp endpoint"
+            )));
+        };
+        let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), *port))
+            .await?
+            .collect();
+        if addrs.is_empty() {
+            return Err(ModbusError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{host} did not resolve to any address"),
+            )));
+        }
+        Ok(addrs)
+    }
+}
+
+#[cfg(any(feature = "modbus_rtu_client", feature = "modbus_rtu_server"))]
+impl SerialSettings {
+    /// 按照参数打开串口
+    pub fn open(&self) -> Result<tokio_serial::SerialStream> {
+        use tokio_serial::SerialPortBuilderExt;
+
+        let parity = match self.parity {
+            Parity::None => tokio_serial::Parity::None,
+            Parity::Odd => tokio_serial::Parity::Odd,
+            Parity::Even => tokio_serial::Parity::Even,
+        };
+        let stop_bits = match self.stop_bits {
+            2 => tokio_serial::StopBits::Two,
+            _ => tokio_serial::StopBits::One,
+        };
+        let data_bits = match self.data_bits {
+            5 => tokio_serial::DataBits::Five,
+            6 => tokio_serial::DataBits::Six,
+            7 => tokio_serial::DataBits::Seven,
+            _ => tokio_serial::DataBits::Eight,
+        };
+
+        tokio_serial::new(&self.path, self.baud_rate)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .data_bits(data_bits)
+            .open_native_async()
+            .map_err(|e| ModbusError::Io(e.into()))
+    }
+}
+
+fn parse_tcp(url: &str, address: &str) -> Result<Transport> {
+    let address = address.trim_end_matches('/');
+    let (host, port) = if let Some(rest) = address.strip_prefix('[') {
+        // IPv6: [::1]:502
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| invalid(url, "unclosed `[` in host"))?;
+        match rest.strip_prefix(':') {
+            Some(port) => (host, Some(port)),
+            None if rest.is_empty() => (host, None),
+            None => return Err(invalid(url, "unexpected characters after host")),
+        }
+    } else {
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (address, None),
+        };
+        if host.contains(':') {
+            return Err(invalid(url, "IPv6 address must be enclosed in `[]`"));
+        }
+        (host, port)
+    };
+
+    if host.is_empty() {
+        return Err(invalid(url, "missing host"));
+    }
+    let port = match port {
+        Some(port) => parse_number(url, "port", port)?,
+        None => DEFAULT_TCP_PORT,
+    };
+    Ok(Transport::Tcp {
+        host: host.to_string(),
+        port,
+    })
+}
+
+fn parse_number<T: FromStr>(url: &str, key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(url, &format!("invalid {key} `{value}`")))
+}
+
+fn parse_duration(url: &str, value: &str) -> Result<Duration> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = parse_number(url, "timeout", number)?;
+    let millis_per_unit = match unit {
+        "" | "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        _ => return Err(invalid(url, &format!("invalid timeout unit `{unit}`"))),
+    };
+    number
+        .checked_mul(millis_per_unit)
+        .map(Duration::from_millis)
+        .ok_or_else(|| invalid(url, &format!("timeout `{value}` is too large")))
+}
+
+fn invalid(url: &str, reason: &str) -> ModbusError {
+    ModbusError::InvalidRequest(format!("invalid endpoint `{url}`: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(host: &str, port: u16) -> Transport {
+        Transport::Tcp {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parse_tcp_example() {
+        let endpoint =
+            Endpoint::parse("tcp://plc.local:502?unit=3&timeout=300ms&retries=2").unwrap();
+        assert_eq!(endpoint.transport, tcp("plc.local", 502));
+        assert_eq!(endpoint.unit, Some(3));
+        assert_eq!(endpoint.timeout, Some(Duration::from_millis(300)));
+        assert_eq!(endpoint.retries, Some(2));
+    }
+
+    #[test]
+    fn parse_rtu_example() {
+        let endpoint =
+            Endpoint::parse("rtu:///dev/ttyUSB0?baud=19200&parity=even&stop=1&unit=7").unwrap();
+        assert_eq!(
+            endpoint.transport,
+            Transport::Rtu(SerialSettings {
+                path: "/dev/ttyUSB0".to_string(),
+                baud_rate: 19200,
+                parity: Parity::Even,
+                stop_bits: 1,
+                data_bits: 8,
+            })
+        );
+        assert_eq!(endpoint.unit, Some(7));
+        assert_eq!(endpoint.timeout, None);
+    }
+
+    #[test]
+    fn parse_tcp_default_port() {
+        let endpoint = Endpoint::parse("tcp://192.168.1.10").unwrap();
+        assert_eq!(endpoint.transport, tcp("192.168.1.10", DEFAULT_TCP_PORT));
+    }
+
+    #[test]
+    fn parse_ipv6() {
+        let endpoint = Endpoint::parse("tcp://[::1]:1502").unwrap();
+        assert_eq!(endpoint.transport, tcp("::1", 1502));
+        assert_eq!(endpoint.to_string(), "tcp://[::1]:1502");
+
+        let endpoint = Endpoint::parse("tcp://[fe80::1]").unwrap();
+        assert_eq!(endpoint.transport, tcp("fe80::1", DEFAULT_TCP_PORT));
+    }
+
+    #[test]
+    fn reject_unbracketed_ipv6() {
+        assert!(Endpoint::parse("tcp://::1").is_err());
+        assert!(Endpoint::parse("tcp://fe80::1:502").is_err());
+        assert!(Endpoint::parse("tcp://[::1").is_err());
+        assert!(Endpoint::parse("tcp://[::1]x").is_err());
+    }
+
+    #[test]
+    fn parse_timeout_units() {
+        let timeout = |url: &str| Endpoint::parse(url).unwrap().timeout.unwrap();
+        assert_eq!(timeout("tcp://h?timeout=250"), Duration::from_millis(250));
+        assert_eq!(timeout("tcp://h?timeout=3s"), Duration::from_secs(3));
+        assert_eq!(timeout("tcp://h?timeout=2m"), Duration::from_secs(120));
+        assert!(Endpoint::parse("tcp://h?timeout=3h").is_err());
+    }
+
+    #[test]
+    fn reject_overflowing_timeout() {
+        assert!(Endpoint::parse("tcp://h?timeout=999999999999999999m").is_err());
+        assert!(Endpoint::parse("tcp://h?timeout=99999999999999999s").is_err());
+        assert!(Endpoint::parse("tcp://h?timeout=99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(Endpoint::parse("plc.local:502").is_err());
+        assert!(Endpoint::parse("udp://plc.local").is_err());
+        assert!(Endpoint::parse("tcp://:502").is_err());
+        assert!(Endpoint::parse("tcp://h:70000").is_err());
+        assert!(Endpoint::parse("rtu://?baud=9600").is_err());
+        assert!(Endpoint::parse("rtu:///dev/ttyS0?parity=mark").is_err());
+        assert!(Endpoint::parse("tcp://h?baud=9600").is_err());
+        assert!(Endpoint::parse("tcp://h?unit").is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let url = "rtu:///dev/ttyUSB0?baud=19200&parity=even&stop=2&data=7&unit=7&timeout=300ms";
+        let endpoint = Endpoint::parse(url).unwrap();
+        assert_eq!(Endpoint::parse(&endpoint.to_string()).unwrap(), endpoint);
+    }
+}
@@ -0,0 +1,261 @@
+//! 错误类型.
+
+use std::fmt;
+use std::io;
+use tokio_modbus::Exception;
+
+/// `Reader`, `Writer` 和 `Client` 使用的结果类型
+pub type Result<T, E = ModbusError> = std::result::Result<T, E>;
+
+/// Modbus 通信错误
+#[derive(Debug)]
+pub enum ModbusError {
+    /// 请求超时, 包含已经发送的次数
+    Timeout {
+        /// 已经发送的次数
+        attempts: u32,
+    },
+
+    /// 从机返回的异常码
+    Exception(Exception),
+
+    /// 传输层 I/O 错误
+    Io(io::Error),
+
+    /// 响应帧不符合协议
+    Protocol(String),
+
+    /// 请求参数不合法, 请求没有发送
+    InvalidRequest(String),
+
+    /// 请求队列已满, 请求没有发送
+    QueueFull,
+
+    /// 写入成功后读回的值和写入的值不同, 比如设备限制了值的范围或者忽略了写入
+    VerifyMismatch {
+        /// 第一个线圈或寄存器的地址
+        address: u16,
+        /// 写入的值
+        expected: Values,
+        /// 读回的值
+        actual: Values,
+    },
+
+    /// 不是幂等的写请求超时或者连接断开, 不知道从机是否已经执行, 请求没有重试
+    OutcomeUnknown(Box<ModbusError>),
+
+    /// 拆分成多个请求的写请求中途失败, 前面的请求已经写入, 后面的请求没有发送
+    PartialWrite {
+        /// 第一个线圈或寄存器的地址
+        address: u16,
+        /// 已经写入的数量, 从 `address` 开始
+        written: u16,
+        /// 失败的请求返回的错误
+        source: Box<ModbusError>,
+    },
+}
+
+/// 线圈或者寄存器的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Values {
+    /// 线圈
+    Coils(Vec<bool>),
+    /// 寄存器
+    Registers(Vec<u16>),
+}
+
+impl fmt::Display for Values {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Values::Coils(values) => write!(f, "{values:?}"),
+            Values::Registers(values) => write!(f, "{values:?}"),
+        }
+    }
+}
+
+impl ModbusError {
+    /// 是否是超时错误
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ModbusError::Timeout { .. })
+    }
+
+    /// 是否不知道写请求是否已经执行
+    ///
+    /// [`ModbusError::PartialWrite`] 中失败的请求不知道是否已经执行时也返回 true.
+    pub fn is_outcome_unknown(&self) -> bool {
+        match self {
+            ModbusError::OutcomeUnknown(_) => true,
+            ModbusError::PartialWrite { source, .. } => source.is_outcome_unknown(),
+            _ => false,
+        }
+    }
+
+    /// 返回从机的异常码
+    pub fn exception(&self) -> Option<Exception> {
+        match self {
+            ModbusError::Exception(exception) => Some(*exception),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ModbusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModbusError::Timeout { attempts } => {
+                write!(f, "Timeout: deadline has elapsed after {attempts} attempts")
+            }
+            ModbusError::Exception(exception) => write!(f, "Modbus exception: {exception}"),
+            ModbusError::Io(e) => write!(f, "I/O error: {e}"),
+            ModbusError::Protocol(message) => write!(f, "Protocol error: {message}"),
+            ModbusError::InvalidRequest(message) => write!(f, "Invalid request: {message}"),
+            ModbusError::QueueFull => write!(f, "Request queue is full"),
+            ModbusError::VerifyMismatch {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Verify mismatch at {address}: expected {expected}, actual {actual}"
+            ),
+            ModbusError::OutcomeUnknown(e) => write!(f, "Write outcome unknown: {e}"),
+            ModbusError::PartialWrite {
+                address,
+                written,
+                source,
+            } => write!(
+                f,
+                "Partial write at {address}: only {written} written before {source}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModbusError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ModbusError::Exception(exception) => Some(exception),
+            ModbusError::Io(e) => Some(e),
+            ModbusError::OutcomeUnknown(e) | ModbusError::PartialWrite { source: e, .. } => {
+                Some(e.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<Exception> for ModbusError {
+    fn from(exception: Exception) -> Self {
+        ModbusError::Exception(exception)
+    }
+}
+
+impl From<io::Error> for ModbusError {
+    /// tokio-modbus 把异常码和协议错误都包装成了 `io::Error`, 这里把它们还原出来
+    ///
+    /// 本库的传输层把 [`ModbusError`] 直接包装在 `io::Error` 中, 这里原样取出.
+    fn from(e: io::Error) -> Self {
+        let e = match e.downcast::<ModbusError>() {
+            Ok(error) => return error,
+            Err(e) => e,
+        };
+        if let Some(exception) = exception_from_io(&e) {
+            return ModbusError::Exception(exception);
+        }
+        match e.kind() {
+            io::ErrorKind::InvalidData => ModbusError::Protocol(e.to_string()),
+            _ => ModbusError::Io(e),
+        }
+    }
+}
+
+/// 所有的异常码
+const EXCEPTIONS: [Exception; 9] = [
+    Exception::IllegalFunction,
+    Exception::IllegalDataAddress,
+    Exception::IllegalDataValue,
+    Exception::ServerDeviceFailure,
+    Exception::Acknowledge,
+    Exception::ServerDeviceBusy,
+    Exception::MemoryParityError,
+    Exception::GatewayPathUnavailable,
+    Exception::GatewayTargetDevice,
+];
+
+/// 从 tokio-modbus 返回的 `io::Error` 中取出异常码
+///
+/// 只用于 tokio-modbus 自带的传输层, 本库的传输层直接返回 [`ModbusError::Exception`].
+/// tokio-modbus 没有公开异常响应的类型, 只能通过错误信息 `Modbus function {code}: {exception}` 识别,
+/// 错误信息的格式由测试固定, 升级 tokio-modbus 时如果格式变化, 测试会失败.
+fn exception_from_io(e: &io::Error) -> Option<Exception> {
+    if e.kind() != io::ErrorKind::Other {
+        return None;
+    }
+    let message = e.get_ref()?.to_string();
+    if !message.starts_with("Modbus function") {
+        return None;
+    }
+    EXCEPTIONS
+        .into_iter()
+        .find(|exception| message.ends_with(&exception.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwrap_wrapped_error() {
+        let e = io::Error::other(ModbusError::Exception(Exception::ServerDeviceBusy));
+        assert_eq!(
+            ModbusError::from(e).exception(),
+            Some(Exception::ServerDeviceBusy)
+        );
+
+        let e = io::Error::other(ModbusError::Protocol("bad frame".to_string()));
+        assert!(matches!(ModbusError::from(e), ModbusError::Protocol(_)));
+    }
+
+    #[test]
+    fn classify_io_errors() {
+        let e = io::Error::new(io::ErrorKind::InvalidData, "invalid");
+        assert!(matches!(ModbusError::from(e), ModbusError::Protocol(_)));
+
+        let e = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(matches!(ModbusError::from(e), ModbusError::Io(_)));
+
+        let e = io::Error::other("Modbus function 3: unknown");
+        assert!(matches!(ModbusError::from(e), ModbusError::Io(_)));
+    }
+
+    /// 固定 tokio-modbus 自带传输层返回的异常错误格式
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn stock_tcp_exception_format() {
+        use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+        use tokio_modbus::client::{tcp, Reader};
+
+        let (client, mut server) = duplex(1024);
+        let script = tokio::spawn(async move {
+            let mut request = [0; 12];
+            server.read_exact(&mut request).await.unwrap();
+            let mut response = request[..4].to_vec();
+            response.extend_from_slice(&[0, 3, request[6], 0x83, 0x02]);
+            server.write_all(&response).await.unwrap();
+            server
+        });
+
+        let mut ctx = tcp::attach(client);
+        let e = ctx.read_holding_registers(0, 1).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            e.to_string(),
+            format!("Modbus function 3: {}", Exception::IllegalDataAddress)
+        );
+        assert_eq!(
+            ModbusError::from(e).exception(),
+            Some(Exception::IllegalDataAddress)
+        );
+        script.await.unwrap();
+    }
+}
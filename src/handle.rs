@@ -0,0 +1,416 @@
+//! 可以在多个任务之间共享的客户端句柄.
+//!
+//! [`ClientHandle`] 把请求通过通道发送给一个独占 [`Client`] 的后台任务, 后台任务按照优先级依次发送请求.
+//! 句柄可以 `clone` 到轮询任务, HTTP 处理函数和报警逻辑中使用, 不需要外部加锁.
+
+use crate::client::{
+    broadcast_request, result_value_bool, result_value_u16, result_value_unit, Client, ResultValue,
+};
+use crate::error::{ModbusError, Result};
+use crate::BroadcastWrite;
+use async_trait::async_trait;
+use std::borrow::Cow;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tokio_modbus::Request;
+
+/// 默认每个优先级的队列长度
+pub const DEFAULT_QUEUE_DEPTH: usize = 64;
+
+/// 请求优先级
+///
+/// 后台任务总是先发送高优先级队列中的请求.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// 高优先级, 比如报警和控制命令
+    High,
+    /// 普通优先级
+    #[default]
+    Normal,
+    /// 低优先级, 比如后台轮询
+    Low,
+}
+
+struct Command {
+    request: Request<'static>,
+    /// 作为广播发送, 参考 [`Client::broadcast`]
+    broadcast: bool,
+    reply: oneshot::Sender<Result<ResultValue>>,
+}
+
+struct Queues {
+    high: mpsc::Sender<Command>,
+    normal: mpsc::Sender<Command>,
+    low: mpsc::Sender<Command>,
+}
+
+/// 客户端句柄
+///
+/// 实现了 [`crate::Reader`] 和 [`crate::Writer`], 可以替换原来使用 [`Client`] 的代码.
+/// 队列已满时请求立即返回 [`ModbusError::QueueFull`].
+#[derive(Clone)]
+pub struct ClientHandle {
+    queues: Arc<Queues>,
+    priority: Priority,
+}
+
+impl ClientHandle {
+    /// 启动后台任务并返回句柄, 每个优先级的队列长度为 [`DEFAULT_QUEUE_DEPTH`]
+    ///
+    /// 必须在 tokio 运行时中调用, 所有句柄都被释放后后台任务退出.
+    pub fn spawn(client: Client) -> ClientHandle {
+        Self::spawn_with_queue_depth(client, DEFAULT_QUEUE_DEPTH)
+    }
+
+    /// 启动后台任务并返回句柄
+    ///
+    /// # 参数
+    /// - client: 后台任务独占的客户端
+    /// - queue_depth: 每个优先级最多排队的请求数量, 最小为 1
+    pub fn spawn_with_queue_depth(client: Client, queue_depth: usize) -> ClientHandle {
+        let queue_depth = queue_depth.max(1);
+        let (high, high_rx) = mpsc::channel(queue_depth);
+        let (normal, normal_rx) = mpsc::channel(queue_depth);
+        let (low, low_rx) = mpsc::channel(queue_depth);
+
+        tokio::spawn(run(client, high_rx, normal_rx, low_rx));
+
+        ClientHandle {
+            queues: Arc::new(Queues { high, normal, low }),
+            priority: Priority::default(),
+        }
+    }
+
+    /// 返回使用指定优先级发送请求的句柄
+    pub fn with_priority(&self, priority: Priority) -> ClientHandle {
+        ClientHandle {
+            queues: Arc::clone(&self.queues),
+            priority,
+        }
+    }
+
+    /// 返回当前句柄的优先级
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// 设置当前句柄的优先级
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    /// 把请求放入队列并等待结果
+    async fn call(&self, request: Request<'static>) -> Result<ResultValue> {
+        self.enqueue(request, false).await
+    }
+
+    /// 把请求放入队列并等待结果, `broadcast` 为 true 时作为广播发送
+    async fn enqueue(&self, request: Request<'static>, broadcast: bool) -> Result<ResultValue> {
+        let queue = match self.priority {
+            Priority::High => &self.queues.high,
+            Priority::Normal => &self.queues.normal,
+            Priority::Low => &self.queues.low,
+        };
+
+        let (reply, response) = oneshot::channel();
+        queue
+            .try_send(Command {
+                request,
+                broadcast,
+                reply,
+            })
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => ModbusError::QueueFull,
+                mpsc::error::TrySendError::Closed(_) => stopped(),
+            })?;
+        response.await.map_err(|_| stopped())?
+    }
+}
+
+/// 后台任务, 按照优先级依次发送请求
+async fn run(
+    mut client: Client,
+    mut high: mpsc::Receiver<Command>,
+    mut normal: mpsc::Receiver<Command>,
+    mut low: mpsc::Receiver<Command>,
+) {
+    loop {
+        let command = tokio::select! {
+            biased;
+            Some(command) = high.recv() => command,
+            Some(command) = normal.recv() => command,
+            Some(command) = low.recv() => command,
+            else => break,
+        };
+
+        // 调用方已经放弃等待, 不再发送
+        if command.reply.is_closed() {
+            continue;
+        }
+        let result = if command.broadcast {
+            client.broadcast().execute(&command.request).await
+        } else {
+            client.execute(&command.request).await
+        };
+        let _ = command.reply.send(result);
+    }
+}
+
+fn stopped() -> ModbusError {
+    ModbusError::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "Client task has stopped",
+    ))
+}
+
+#[async_trait]
+impl crate::Writer for ClientHandle {
+    async fn write_single_coil(&mut self, address: u16, value: bool) -> Result<()> {
+        let result = self.call(Request::WriteSingleCoil(address, value)).await?;
+        result_value_unit(result)
+    }
+
+    async fn write_single_register(&mut self, address: u16, value: u16) -> Result<()> {
+        let result = self
+            .call(Request::WriteSingleRegister(address, value))
+            .await?;
+        result_value_unit(result)
+    }
+
+    async fn write_multiple_coils(&mut self, address: u16, value: &[bool]) -> Result<()> {
+        let result = self
+            .call(Request::WriteMultipleCoils(
+                address,
+                Cow::Owned(value.to_vec()),
+            ))
+            .await?;
+        result_value_unit(result)
+    }
+
+    async fn write_multiple_registers(&mut self, address: u16, value: &[u16]) -> Result<()> {
+        let result = self
+            .call(Request::WriteMultipleRegisters(
+                address,
+                Cow::Owned(value.to_vec()),
+            ))
+            .await?;
+        result_value_unit(result)
+    }
+
+    async fn masked_write_register(
+        &mut self,
+        address: u16,
+        and_mask: u16,
+        or_mask: u16,
+    ) -> Result<()> {
+        let result = self
+            .call(Request::MaskWriteRegister(address, and_mask, or_mask))
+            .await?;
+        result_value_unit(result)
+    }
+
+    async fn broadcast_write(&mut self, request: BroadcastWrite<'_>) -> Result<()> {
+        let request = broadcast_request(request).into_owned();
+        let result = self.enqueue(request, true).await?;
+        result_value_unit(result)
+    }
+}
+
+#[async_trait]
+impl crate::Reader for ClientHandle {
+    async fn read_coils(&mut self, address: u16, count: u16) -> Result<Vec<bool>> {
+        let result = self.call(Request::ReadCoils(address, count)).await?;
+        result_value_bool(result)
+    }
+
+    async fn read_discrete_inputs(&mut self, address: u16, count: u16) -> Result<Vec<bool>> {
+        let result = self
+            .call(Request::ReadDiscreteInputs(address, count))
+            .await?;
+        result_value_bool(result)
+    }
+
+    async fn read_holding_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>> {
+        let result = self
+            .call(Request::ReadHoldingRegisters(address, count))
+            .await?;
+        result_value_u16(result)
+    }
+
+    async fn read_input_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>> {
+        let result = self
+            .call(Request::ReadInputRegisters(address, count))
+            .await?;
+        result_value_u16(result)
+    }
+
+    async fn read_write_multiple_registers(
+        &mut self,
+        read_addr: u16,
+        read_count: u16,
+        write_addr: u16,
+        write_data: &[u16],
+    ) -> Result<Vec<u16>> {
+        let result = self
+            .call(Request::ReadWriteMultipleRegisters(
+                read_addr,
+                read_count,
+                write_addr,
+                Cow::Owned(write_data.to_vec()),
+            ))
+            .await?;
+        result_value_u16(result)
+    }
+}
+
+#[cfg(all(test, feature = "modbus_tcp_client"))]
+mod tests {
+    use super::*;
+    use crate::client::ClientBuilder;
+    use crate::mock::{read_range, registers_response, MockServer, Reply};
+    use crate::Reader;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+    use tokio::task::JoinHandle;
+    use tokio::time::sleep;
+
+    /// 读取地址 0 时等待 `gate` 通知后再响应, 其他地址立即响应, 寄存器的值等于地址
+    async fn gated_server(gate: Arc<Notify>) -> MockServer {
+        MockServer::start(move |_, pdu| {
+            let (address, count) = read_range(pdu);
+            let values: Vec<u16> = (address..address + count).collect();
+            let response = registers_response(pdu[0], &values);
+            match address {
+                0 => Reply::Wait(gate.clone(), response),
+                _ => Reply::Pdu(response),
+            }
+        })
+        .await
+    }
+
+    async fn spawn_handle(server: &MockServer, queue_depth: usize) -> ClientHandle {
+        let client = ClientBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+        ClientHandle::spawn_with_queue_depth(client, queue_depth)
+    }
+
+    fn read(
+        handle: &ClientHandle,
+        priority: Priority,
+        address: u16,
+    ) -> JoinHandle<Result<Vec<u16>>> {
+        let mut handle = handle.with_priority(priority);
+        tokio::spawn(async move { handle.read_holding_registers(address, 1).await })
+    }
+
+    /// 等待从机收到 `count` 个请求
+    async fn wait_requests(server: &MockServer, count: usize) {
+        while server.requests().len() < count {
+            sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    /// 等待队列中有 `count` 个请求
+    async fn wait_queued(queue: &mpsc::Sender<Command>, count: usize) {
+        while queue.max_capacity() - queue.capacity() < count {
+            sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    fn addresses(server: &MockServer) -> Vec<u16> {
+        server
+            .requests()
+            .iter()
+            .map(|(_, pdu)| read_range(pdu).0)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_by_priority() {
+        let gate = Arc::new(Notify::new());
+        let server = gated_server(gate.clone()).await;
+        let handle = spawn_handle(&server, 4).await;
+
+        let first = read(&handle, Priority::Normal, 0);
+        wait_requests(&server, 1).await;
+        let low = read(&handle, Priority::Low, 3);
+        wait_queued(&handle.queues.low, 1).await;
+        let normal = read(&handle, Priority::Normal, 2);
+        wait_queued(&handle.queues.normal, 1).await;
+        let high = read(&handle, Priority::High, 1);
+        wait_queued(&handle.queues.high, 1).await;
+
+        gate.notify_one();
+        assert_eq!(first.await.unwrap().unwrap(), [0]);
+        assert_eq!(high.await.unwrap().unwrap(), [1]);
+        assert_eq!(normal.await.unwrap().unwrap(), [2]);
+        assert_eq!(low.await.unwrap().unwrap(), [3]);
+        assert_eq!(addresses(&server), [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn reject_when_queue_is_full() {
+        let gate = Arc::new(Notify::new());
+        let server = gated_server(gate.clone()).await;
+        let handle = spawn_handle(&server, 1).await;
+
+        let first = read(&handle, Priority::Normal, 0);
+        wait_requests(&server, 1).await;
+        let queued = read(&handle, Priority::Normal, 1);
+        wait_queued(&handle.queues.normal, 1).await;
+
+        let result = handle.clone().read_holding_registers(2, 1).await;
+        assert!(matches!(result, Err(ModbusError::QueueFull)));
+        // 其他优先级的队列不受影响
+        let high = read(&handle, Priority::High, 3);
+        wait_queued(&handle.queues.high, 1).await;
+
+        gate.notify_one();
+        assert!(first.await.unwrap().is_ok());
+        assert!(queued.await.unwrap().is_ok());
+        assert!(high.await.unwrap().is_ok());
+        assert_eq!(addresses(&server), [0, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn skip_abandoned_requests() {
+        let gate = Arc::new(Notify::new());
+        let server = gated_server(gate.clone()).await;
+        let handle = spawn_handle(&server, 4).await;
+
+        let first = read(&handle, Priority::Normal, 0);
+        wait_requests(&server, 1).await;
+        let abandoned = read(&handle, Priority::Normal, 1);
+        wait_queued(&handle.queues.normal, 1).await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        let next = read(&handle, Priority::Normal, 2);
+        wait_queued(&handle.queues.normal, 2).await;
+
+        gate.notify_one();
+        assert!(first.await.unwrap().is_ok());
+        assert_eq!(next.await.unwrap().unwrap(), [2]);
+        assert_eq!(addresses(&server), [0, 2]);
+    }
+
+    #[tokio::test]
+    async fn stop_after_all_handles_dropped() {
+        let server = gated_server(Arc::new(Notify::new())).await;
+        let mut handle = spawn_handle(&server, 4).await;
+        let mut other = handle.with_priority(Priority::High);
+        assert_eq!(handle.read_holding_registers(5, 1).await.unwrap(), [5]);
+        assert_eq!(server.connections(), 1);
+
+        drop(handle);
+        assert_eq!(other.read_holding_registers(6, 1).await.unwrap(), [6]);
+        drop(other);
+        // 后台任务退出后释放 Client, 连接随之关闭
+        while server.connections() > 0 {
+            sleep(Duration::from_millis(1)).await;
+        }
+    }
+}
@@ -14,18 +14,67 @@
 //! - 输入寄存器(Input Register): 16 位的只读数据.
 //! - 保持寄存器(Holding Register): 16 位的读写数据.
 
-use anyhow::Result;
 use async_trait::async_trait;
+use error::Result;
+
+#[cfg(feature = "modbus_rtu_client")]
+pub mod bus;
 
 #[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
 pub mod client;
 
+pub mod codec;
+
+pub mod endpoint;
+
+pub mod error;
+
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+pub mod handle;
+
+#[cfg(all(test, feature = "modbus_tcp_client"))]
+mod mock;
+
+pub mod planner;
+
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+pub mod poller;
+
+#[cfg(feature = "modbus_tcp_client")]
+pub mod pool;
+
+#[cfg(feature = "profile")]
+pub mod profile;
+
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+pub mod quality;
+
+pub mod registers;
+
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+pub mod retry;
+
 #[cfg(any(feature = "modbus_tcp_server", feature = "modbus_rtu_server"))]
 pub mod server;
 
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+pub mod subscription;
+
+pub mod tag;
+
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+mod transport;
+
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+pub mod verify;
+
 #[cfg(any(feature = "modbus_tcp_server", feature = "modbus_rtu_server"))]
 mod common_utils;
 
+#[cfg(feature = "derive")]
+pub use async_modbus_derive::ModbusRegisters;
+pub use error::ModbusError;
+pub use registers::ModbusRegisters;
 pub use tokio_modbus::Exception;
 
 /// 异步读 Modbus 数据
@@ -39,7 +88,7 @@ pub trait Reader {
     ///
     /// # 返回
     /// - 成功: 返回读取的数据
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn read_coils(&mut self, address: u16, count: u16) -> Result<Vec<bool>>;
 
     /// 读取多个离散输入 (0x02)
@@ -50,7 +99,7 @@ pub trait Reader {
     ///
     /// # 返回
     /// - 成功: 返回读取的数据
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn read_discrete_inputs(&mut self, address: u16, count: u16) -> Result<Vec<bool>>;
 
     /// 读取多个保持寄存器 (0x03)
@@ -61,7 +110,7 @@ pub trait Reader {
     ///
     /// # 返回
     /// - 成功: 返回读取的数据
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn read_holding_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>>;
 
     /// 读取多个输入寄存器 (0x04)
@@ -72,7 +121,7 @@ pub trait Reader {
     ///
     /// # 返回
     /// - 成功: 返回读取的数据
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn read_input_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>>;
 
     /// 读取和写入多个保持寄存器 (0x17)
@@ -85,7 +134,7 @@ pub trait Reader {
     ///
     /// # 返回
     /// - 成功: 返回读取的数据
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn read_write_multiple_registers(
         &mut self,
         read_addr: u16,
@@ -106,7 +155,7 @@ pub trait Writer {
     ///
     /// # 返回
     /// - 成功: 返回空
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn write_single_coil(&mut self, address: u16, value: bool) -> Result<()>;
 
     /// 写入单个保持寄存器 (0x06)
@@ -117,7 +166,7 @@ pub trait Writer {
     ///
     /// # 返回
     /// - 成功: 返回空
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn write_single_register(&mut self, address: u16, value: u16) -> Result<()>;
 
     /// 写入多个线圈 (0x0F)
@@ -128,7 +177,7 @@ pub trait Writer {
     ///
     /// # 返回
     /// - 成功: 返回空
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn write_multiple_coils(&mut self, address: u16, value: &[bool]) -> Result<()>;
 
     /// 写入多个保持寄存器 (0x10)
@@ -139,7 +188,7 @@ pub trait Writer {
     ///
     /// # 返回
     /// - 成功: 返回空
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn write_multiple_registers(&mut self, address: u16, value: &[u16]) -> Result<()>;
 
     /// 设置或清除单个保持寄存器的位 (0x16)
@@ -151,13 +200,45 @@ pub trait Writer {
     ///
     /// # 返回
     /// - 成功: 返回空
-    /// - 失败: 返回错误信息
+    /// - 失败: 返回 [`ModbusError`]
     async fn masked_write_register(
         &mut self,
         address: u16,
         and_mask: u16,
         or_mask: u16,
     ) -> Result<()>;
+
+    /// 向所有从机广播写请求 (从机 id 0)
+    ///
+    /// 只有 rtu 支持广播. 从机不响应广播, 请求只发送一次, 发送完成后等待转换延时再返回成功.
+    /// 默认不支持广播.
+    ///
+    /// # 参数
+    /// - request: 写请求
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 不支持广播时返回 [`ModbusError::InvalidRequest`], 其他错误返回 [`ModbusError`]
+    async fn broadcast_write(&mut self, request: BroadcastWrite<'_>) -> Result<()> {
+        Err(ModbusError::InvalidRequest(format!(
+            "{request:?} can not be broadcast, broadcast is only supported by rtu"
+        )))
+    }
+}
+
+/// 广播写请求, 参考 [`Writer::broadcast_write`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastWrite<'a> {
+    /// 写入单个线圈 (0x05), 地址和值
+    SingleCoil(u16, bool),
+    /// 写入单个保持寄存器 (0x06), 地址和值
+    SingleRegister(u16, u16),
+    /// 写入多个线圈 (0x0F), 第一个地址和值
+    MultipleCoils(u16, &'a [bool]),
+    /// 写入多个保持寄存器 (0x10), 第一个地址和值
+    MultipleRegisters(u16, &'a [u16]),
+    /// 设置或清除单个保持寄存器的位 (0x16), 地址, AND 掩码和 OR 掩码
+    MaskWriteRegister(u16, u16, u16),
 }
 
 #[cfg(any(feature = "modbus_tcp_server", feature = "modbus_rtu_server",))]
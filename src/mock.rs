@@ -0,0 +1,171 @@
+//! 测试用的 Modbus TCP 从机.
+//!
+//! 每个请求交给处理函数, 由处理函数决定返回响应, 不响应或者关闭连接, 并且记录收到的所有请求.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// 处理函数的返回值
+pub(crate) enum Reply {
+    /// 返回响应的 PDU
+    Pdu(Vec<u8>),
+    /// 收到通知后再返回响应的 PDU
+    Wait(Arc<Notify>, Vec<u8>),
+    /// 不响应
+    Silent,
+    /// 关闭连接
+    Close,
+}
+
+type Handler = Arc<Mutex<dyn FnMut(u8, &[u8]) -> Reply + Send>>;
+
+/// 收到的请求, 包含从机 id 和 PDU
+type Requests = Arc<Mutex<Vec<(u8, Vec<u8>)>>>;
+
+/// 测试用的 Modbus TCP 从机, drop 时关闭监听和所有连接
+pub(crate) struct MockServer {
+    pub(crate) addr: SocketAddr,
+    requests: Requests,
+    connections: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// 在随机端口上启动从机
+    ///
+    /// # 参数
+    /// - handler: 处理函数, 参数是从机 id 和请求的 PDU
+    pub(crate) async fn start<F>(handler: F) -> MockServer
+    where
+        F: FnMut(u8, &[u8]) -> Reply + Send + 'static,
+    {
+        Self::start_at("127.0.0.1:0".parse().unwrap(), handler).await
+    }
+
+    /// 在指定地址上启动从机, 用来模拟从机重启
+    pub(crate) async fn start_at<F>(addr: SocketAddr, handler: F) -> MockServer
+    where
+        F: FnMut(u8, &[u8]) -> Reply + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Handler = Arc::new(Mutex::new(handler));
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let task = tokio::spawn({
+            let requests = requests.clone();
+            let connections = connections.clone();
+            async move {
+                let mut tasks = JoinSet::new();
+                while let Ok((stream, _)) = listener.accept().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    let serve = serve(stream, handler.clone(), requests.clone());
+                    let connections = connections.clone();
+                    tasks.spawn(async move {
+                        serve.await;
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+            }
+        });
+        MockServer {
+            addr,
+            requests,
+            connections,
+            task,
+        }
+    }
+
+    /// 返回收到的所有请求, 包含从机 id 和 PDU
+    pub(crate) fn requests(&self) -> Vec<(u8, Vec<u8>)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// 返回没有关闭的连接数量
+    pub(crate) fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// 关闭监听和所有连接, 等待关闭完成
+    pub(crate) async fn stop(mut self) {
+        self.task.abort();
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// 处理一个连接上的请求
+async fn serve(mut stream: TcpStream, handler: Handler, requests: Requests) {
+    let mut header = [0; 7];
+    while stream.read_exact(&mut header).await.is_ok() {
+        let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        let mut pdu = vec![0; len.saturating_sub(1)];
+        if stream.read_exact(&mut pdu).await.is_err() {
+            return;
+        }
+        let unit_id = header[6];
+        requests.lock().unwrap().push((unit_id, pdu.clone()));
+
+        let reply = (handler.lock().unwrap())(unit_id, &pdu);
+        let response = match reply {
+            Reply::Pdu(response) => response,
+            Reply::Wait(notify, response) => {
+                notify.notified().await;
+                response
+            }
+            Reply::Silent => continue,
+            Reply::Close => return,
+        };
+        let mut frame = header[..4].to_vec();
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(unit_id);
+        frame.extend_from_slice(&response);
+        if stream.write_all(&frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// 读请求的地址和数量
+pub(crate) fn read_range(pdu: &[u8]) -> (u16, u16) {
+    (
+        u16::from_be_bytes([pdu[1], pdu[2]]),
+        u16::from_be_bytes([pdu[3], pdu[4]]),
+    )
+}
+
+/// 读寄存器的响应
+pub(crate) fn registers_response(function: u8, values: &[u16]) -> Vec<u8> {
+    let mut pdu = vec![function, (values.len() * 2) as u8];
+    for value in values {
+        pdu.extend_from_slice(&value.to_be_bytes());
+    }
+    pdu
+}
+
+/// 读线圈或离散输入的响应, 所有的值都是 false
+pub(crate) fn bits_response(function: u8, count: u16) -> Vec<u8> {
+    let len = usize::from(count).div_ceil(8);
+    let mut pdu = vec![function, len as u8];
+    pdu.resize(len + 2, 0);
+    pdu
+}
+
+/// 写请求的响应
+pub(crate) fn write_response(pdu: &[u8]) -> Vec<u8> {
+    match pdu[0] {
+        0x16 => pdu[..7].to_vec(),
+        _ => pdu[..5].to_vec(),
+    }
+}
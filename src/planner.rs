@@ -0,0 +1,458 @@
+//! 读请求规划.
+//!
+//! 把分散的读取项合并成尽量少的请求, 每个请求都不超过协议限制 (125 个寄存器, 2000 个线圈),
+//! 相邻读取项之间的空隙不超过配置的间隔时才会合并. 一个读取项不会被拆分到两个请求中,
+//! 所以跨多个寄存器的值总是在同一个请求中读取.
+
+use crate::error::{ModbusError, Result};
+use crate::Reader;
+
+/// 读寄存器请求最多读取的寄存器数量 (0x03, 0x04)
+pub const MAX_READ_REGISTERS: u16 = 125;
+
+/// 读线圈请求最多读取的线圈数量 (0x01, 0x02)
+pub const MAX_READ_BITS: u16 = 2000;
+
+/// Modbus 数据表
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Table {
+    /// 线圈 (0x01)
+    #[cfg_attr(feature = "serde", serde(rename = "coil"))]
+    Coil,
+    /// 离散输入 (0x02)
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "discrete", alias = "discrete_input")
+    )]
+    DiscreteInput,
+    /// 输入寄存器 (0x04)
+    #[cfg_attr(feature = "serde", serde(rename = "input", alias = "input_register"))]
+    InputRegister,
+    /// 保持寄存器 (0x03)
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "holding", alias = "holding_register")
+    )]
+    HoldingRegister,
+}
+
+impl Table {
+    /// 是否是位数据 (线圈或离散输入)
+    pub fn is_bit(self) -> bool {
+        matches!(self, Table::Coil | Table::DiscreteInput)
+    }
+}
+
+/// 读取项
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReadItem {
+    /// 数据表
+    pub table: Table,
+    /// 起始地址
+    pub address: u16,
+    /// 寄存器或线圈的数量
+    pub length: u16,
+}
+
+impl ReadItem {
+    /// 创建读取项
+    pub fn new(table: Table, address: u16, length: u16) -> Self {
+        ReadItem {
+            table,
+            address,
+            length,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        u32::from(self.address) + u32::from(self.length)
+    }
+}
+
+/// 读取项的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemValue {
+    /// 寄存器的值
+    Registers(Vec<u16>),
+    /// 线圈或离散输入的值
+    Bits(Vec<bool>),
+}
+
+impl ItemValue {
+    /// 返回寄存器的值, 位数据返回 None
+    pub fn as_registers(&self) -> Option<&[u16]> {
+        match self {
+            ItemValue::Registers(registers) => Some(registers),
+            ItemValue::Bits(_) => None,
+        }
+    }
+
+    /// 返回位数据的值, 寄存器返回 None
+    pub fn as_bits(&self) -> Option<&[bool]> {
+        match self {
+            ItemValue::Bits(bits) => Some(bits),
+            ItemValue::Registers(_) => None,
+        }
+    }
+}
+
+/// 规划后的一个读请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlannedRead {
+    /// 数据表
+    pub table: Table,
+    /// 起始地址
+    pub address: u16,
+    /// 读取数量
+    pub count: u16,
+}
+
+/// 读请求规划器
+#[derive(Debug, Clone)]
+pub struct ReadPlanner {
+    max_register_gap: u16,
+    max_bit_gap: u16,
+    max_registers: u16,
+    max_bits: u16,
+}
+
+impl Default for ReadPlanner {
+    /// 寄存器之间最多空 8 个, 线圈之间最多空 64 个, 使用协议规定的最大数量
+    fn default() -> Self {
+        ReadPlanner {
+            max_register_gap: 8,
+            max_bit_gap: 64,
+            max_registers: MAX_READ_REGISTERS,
+            max_bits: MAX_READ_BITS,
+        }
+    }
+}
+
+impl ReadPlanner {
+    /// 使用默认配置创建规划器
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置合并寄存器时允许多读的寄存器数量
+    pub fn max_register_gap(mut self, gap: u16) -> Self {
+        self.max_register_gap = gap;
+        self
+    }
+
+    /// 设置合并线圈和离散输入时允许多读的数量
+    pub fn max_bit_gap(mut self, gap: u16) -> Self {
+        self.max_bit_gap = gap;
+        self
+    }
+
+    /// 设置一个请求最多读取的寄存器数量, 不超过 [`MAX_READ_REGISTERS`]
+    ///
+    /// 有些设备只支持更少的数量.
+    pub fn max_registers(mut self, max: u16) -> Self {
+        self.max_registers = max.clamp(1, MAX_READ_REGISTERS);
+        self
+    }
+
+    /// 设置一个请求最多读取的线圈或离散输入数量, 不超过 [`MAX_READ_BITS`]
+    pub fn max_bits(mut self, max: u16) -> Self {
+        self.max_bits = max.clamp(1, MAX_READ_BITS);
+        self
+    }
+
+    /// 规划读请求
+    ///
+    /// # 参数
+    /// - items: 读取项, 可以重复, 可以重叠
+    ///
+    /// # 返回
+    /// - 成功: 返回读请求计划
+    /// - 失败: 读取项的长度为 0, 超过一个请求的最大数量或者地址越界
+    pub fn plan(&self, items: &[ReadItem]) -> Result<ReadPlan> {
+        for item in items {
+            let max = self.max_count(item.table);
+            if item.length == 0 || item.length > max || item.end() > 0x1_0000 {
+                return Err(ModbusError::InvalidRequest(format!(
+                    "{item:?} must have 1..={max} elements within the address space"
+                )));
+            }
+        }
+
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        order.sort_by_key(|&i| (items[i].table, items[i].address, items[i].length));
+
+        let mut requests: Vec<PlannedRead> = Vec::new();
+        let mut placements = vec![(0, 0); items.len()];
+        for i in order {
+            let item = &items[i];
+            let merged = requests.last_mut().filter(|request| {
+                let start = u32::from(request.address);
+                let end = start + u32::from(request.count);
+                request.table == item.table
+                    && u32::from(item.address) <= end + u32::from(self.max_gap(item.table))
+                    && item.end().max(end) - start <= u32::from(self.max_count(item.table))
+            });
+
+            match merged {
+                Some(request) => {
+                    let end = u32::from(request.address) + u32::from(request.count);
+                    // 上面已经检查过不超过最大数量
+                    request.count = (item.end().max(end) - u32::from(request.address)) as u16;
+                }
+                None => requests.push(PlannedRead {
+                    table: item.table,
+                    address: item.address,
+                    count: item.length,
+                }),
+            }
+
+            let index = requests.len() - 1;
+            placements[i] = (index, item.address - requests[index].address);
+        }
+
+        Ok(ReadPlan {
+            items: items.to_vec(),
+            requests,
+            placements,
+        })
+    }
+
+    fn max_gap(&self, table: Table) -> u16 {
+        if table.is_bit() {
+            self.max_bit_gap
+        } else {
+            self.max_register_gap
+        }
+    }
+
+    fn max_count(&self, table: Table) -> u16 {
+        if table.is_bit() {
+            self.max_bits
+        } else {
+            self.max_registers
+        }
+    }
+}
+
+/// 读请求计划
+#[derive(Debug, Clone)]
+pub struct ReadPlan {
+    items: Vec<ReadItem>,
+    requests: Vec<PlannedRead>,
+    /// 每个读取项所在的请求和在请求中的偏移
+    placements: Vec<(usize, u16)>,
+}
+
+impl ReadPlan {
+    /// 返回读取项
+    pub fn items(&self) -> &[ReadItem] {
+        &self.items
+    }
+
+    /// 返回合并后的读请求
+    pub fn requests(&self) -> &[PlannedRead] {
+        &self.requests
+    }
+
+    /// 执行读请求计划
+    ///
+    /// # 参数
+    /// - reader: 用来发送请求的客户端
+    ///
+    /// # 返回
+    /// - 成功: 按照读取项的顺序返回每个读取项的值
+    /// - 失败: 任意一个请求失败时返回错误信息
+    pub async fn execute<R>(&self, reader: &mut R) -> Result<Vec<ItemValue>>
+    where
+        R: Reader + Send + ?Sized,
+    {
+        let mut responses = Vec::with_capacity(self.requests.len());
+        for request in &self.requests {
+            let (address, count) = (request.address, request.count);
+            let response = match request.table {
+                Table::Coil => ItemValue::Bits(reader.read_coils(address, count).await?),
+                Table::DiscreteInput => {
+                    ItemValue::Bits(reader.read_discrete_inputs(address, count).await?)
+                }
+                Table::InputRegister => {
+                    ItemValue::Registers(reader.read_input_registers(address, count).await?)
+                }
+                Table::HoldingRegister => {
+                    ItemValue::Registers(reader.read_holding_registers(address, count).await?)
+                }
+            };
+            responses.push(response);
+        }
+        self.split(&responses)
+    }
+
+    /// 把每个请求的响应拆分成读取项的值
+    fn split(&self, responses: &[ItemValue]) -> Result<Vec<ItemValue>> {
+        self.items
+            .iter()
+            .zip(&self.placements)
+            .map(|(item, &(index, offset))| {
+                let range = usize::from(offset)..usize::from(offset) + usize::from(item.length);
+                let value = match &responses[index] {
+                    ItemValue::Registers(registers) => registers
+                        .get(range)
+                        .map(|v| ItemValue::Registers(v.to_vec())),
+                    ItemValue::Bits(bits) => bits.get(range).map(|v| ItemValue::Bits(v.to_vec())),
+                };
+                value.ok_or_else(|| {
+                    ModbusError::Protocol(format!("Response is too short for {item:?}"))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(table: Table, address: u16, count: u16) -> PlannedRead {
+        PlannedRead {
+            table,
+            address,
+            count,
+        }
+    }
+
+    #[test]
+    fn merge_within_gap() {
+        let items = [
+            ReadItem::new(Table::HoldingRegister, 100, 2),
+            ReadItem::new(Table::HoldingRegister, 110, 1),
+            ReadItem::new(Table::HoldingRegister, 104, 4),
+        ];
+        let plan = ReadPlanner::new().plan(&items).unwrap();
+        assert_eq!(plan.requests(), [read(Table::HoldingRegister, 100, 11)]);
+    }
+
+    #[test]
+    fn split_on_gap() {
+        let items = [
+            ReadItem::new(Table::HoldingRegister, 0, 2),
+            ReadItem::new(Table::HoldingRegister, 11, 1),
+            ReadItem::new(Table::HoldingRegister, 20, 1),
+        ];
+        let plan = ReadPlanner::new().plan(&items).unwrap();
+        assert_eq!(
+            plan.requests(),
+            [
+                read(Table::HoldingRegister, 0, 2),
+                read(Table::HoldingRegister, 11, 10),
+            ]
+        );
+
+        let plan = ReadPlanner::new().max_register_gap(0).plan(&items).unwrap();
+        assert_eq!(plan.requests().len(), 3);
+    }
+
+    #[test]
+    fn split_on_limit() {
+        let items = [
+            ReadItem::new(Table::InputRegister, 0, 100),
+            ReadItem::new(Table::InputRegister, 100, 30),
+        ];
+        let plan = ReadPlanner::new().plan(&items).unwrap();
+        assert_eq!(
+            plan.requests(),
+            [
+                read(Table::InputRegister, 0, 100),
+                read(Table::InputRegister, 100, 30),
+            ]
+        );
+
+        let items = [
+            ReadItem::new(Table::HoldingRegister, 0, 10),
+            ReadItem::new(Table::HoldingRegister, 10, 10),
+            ReadItem::new(Table::HoldingRegister, 20, 10),
+        ];
+        let plan = ReadPlanner::new().max_registers(20).plan(&items).unwrap();
+        assert_eq!(
+            plan.requests(),
+            [
+                read(Table::HoldingRegister, 0, 20),
+                read(Table::HoldingRegister, 20, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_bits_on_limit() {
+        let items = [
+            ReadItem::new(Table::Coil, 0, 1500),
+            ReadItem::new(Table::Coil, 1500, 501),
+            ReadItem::new(Table::DiscreteInput, 0, 1),
+        ];
+        let plan = ReadPlanner::new().plan(&items).unwrap();
+        assert_eq!(
+            plan.requests(),
+            [
+                read(Table::Coil, 0, 1500),
+                read(Table::Coil, 1500, 501),
+                read(Table::DiscreteInput, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_tables_apart() {
+        let items = [
+            ReadItem::new(Table::HoldingRegister, 0, 1),
+            ReadItem::new(Table::InputRegister, 1, 1),
+        ];
+        let plan = ReadPlanner::new().plan(&items).unwrap();
+        assert_eq!(plan.requests().len(), 2);
+    }
+
+    #[test]
+    fn reject_invalid_items() {
+        let planner = ReadPlanner::new();
+        assert!(planner
+            .plan(&[ReadItem::new(Table::HoldingRegister, 0, 0)])
+            .is_err());
+        assert!(planner
+            .plan(&[ReadItem::new(Table::HoldingRegister, 0, 126)])
+            .is_err());
+        assert!(planner
+            .plan(&[ReadItem::new(Table::Coil, 0, 2001)])
+            .is_err());
+        assert!(planner
+            .plan(&[ReadItem::new(Table::HoldingRegister, 0xFFFF, 2)])
+            .is_err());
+    }
+
+    #[test]
+    fn split_responses_in_item_order() {
+        let items = [
+            ReadItem::new(Table::HoldingRegister, 4, 2),
+            ReadItem::new(Table::Coil, 1, 2),
+            ReadItem::new(Table::HoldingRegister, 1, 1),
+        ];
+        let plan = ReadPlanner::new().plan(&items).unwrap();
+        let values = plan
+            .split(&[
+                ItemValue::Bits(vec![true, false, true]),
+                ItemValue::Registers(vec![1, 2, 3, 4, 5]),
+            ])
+            .unwrap();
+        assert_eq!(
+            values,
+            [
+                ItemValue::Registers(vec![4, 5]),
+                ItemValue::Bits(vec![true, false]),
+                ItemValue::Registers(vec![1]),
+            ]
+        );
+        assert!(plan
+            .split(&[
+                ItemValue::Bits(vec![true]),
+                ItemValue::Registers(vec![1, 2, 3, 4, 5]),
+            ])
+            .is_err());
+    }
+}
@@ -0,0 +1,447 @@
+//! 周期轮询.
+//!
+//! [`Poller`] 为每个 [`PollGroup`] 启动一个后台任务, 按照各自的周期读取数据点,
+//! 每次读取的结果 [`Sample`] 同时发布到:
+//! - `watch` 通道: 每个轮询组只保留最新的结果, 适合随时查询当前值
+//! - `broadcast` 通道: 所有轮询组的每一次结果, 适合转发到 MQTT 等下游
+//!
+//! 设备响应慢时错过的周期会被跳过, 不会在恢复后连续补发请求.
+
+use crate::error::{ModbusError, Result};
+use crate::planner::{ReadPlan, ReadPlanner};
+use crate::retry::random_unit;
+use crate::tag::{Tag, Value};
+use crate::Reader;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// 默认 `broadcast` 通道的容量
+pub const DEFAULT_BROADCAST_CAPACITY: usize = 64;
+
+/// 轮询组
+///
+/// 同一个组中的数据点按照相同的周期读取, 读请求会合并成尽量少的请求.
+#[derive(Debug, Clone)]
+pub struct PollGroup {
+    name: String,
+    interval: Duration,
+    jitter: Duration,
+    tags: Vec<Tag>,
+    planner: ReadPlanner,
+}
+
+impl PollGroup {
+    /// 创建轮询组
+    ///
+    /// # 参数
+    /// - name: 名字, 同一个 [`Poller`] 中唯一
+    /// - interval: 轮询周期, 最小 1 毫秒
+    pub fn new(name: impl Into<String>, interval: Duration) -> Self {
+        PollGroup {
+            name: name.into(),
+            interval: interval.max(Duration::from_millis(1)),
+            jitter: Duration::ZERO,
+            tags: Vec::new(),
+            planner: ReadPlanner::new(),
+        }
+    }
+
+    /// 设置抖动, 每次轮询在周期开始后随机延迟 `[0, jitter)`, 避免多个组同时发送请求
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// 增加一个数据点
+    pub fn tag(mut self, tag: Tag) -> Self {
+        self.tags.push(tag);
+        self
+    }
+
+    /// 增加多个数据点
+    pub fn tags<I: IntoIterator<Item = Tag>>(mut self, tags: I) -> Self {
+        self.tags.extend(tags);
+        self
+    }
+
+    /// 设置合并读请求使用的规划器
+    pub fn planner(mut self, planner: ReadPlanner) -> Self {
+        self.planner = planner;
+        self
+    }
+
+    /// 返回名字
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 检查数据点并生成读请求计划
+    fn plan(&self) -> Result<ReadPlan> {
+        for tag in &self.tags {
+            tag.validate()?;
+            if !tag.access.is_readable() {
+                return Err(ModbusError::InvalidRequest(format!(
+                    "poll group `{}`: tag `{}` is not readable",
+                    self.name, tag.name
+                )));
+            }
+        }
+        let items: Vec<_> = self.tags.iter().map(Tag::read_item).collect();
+        self.planner.plan(&items)
+    }
+}
+
+/// 一次轮询的结果
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// 轮询组的名字
+    pub group: Arc<str>,
+    /// 开始读取的时间
+    pub timestamp: SystemTime,
+    /// 读取花费的时间
+    pub elapsed: Duration,
+    /// 成功时按照轮询组中的顺序返回名字和工程值, 失败时返回错误信息
+    ///
+    /// 结果是整体的: 任意一个读请求失败或者任意一个数据点解码失败时都返回错误,
+    /// 不会返回其他数据点的值. 需要互不影响的数据点应该放在不同的轮询组中.
+    pub result: std::result::Result<Vec<(String, Value)>, Arc<ModbusError>>,
+}
+
+impl Sample {
+    /// 按照名字查找数据点的值, 读取或者解码失败时返回 None
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.result
+            .as_ref()
+            .ok()?
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// 周期轮询引擎
+///
+/// 释放后停止所有后台任务.
+pub struct Poller {
+    latest: HashMap<String, watch::Receiver<Option<Arc<Sample>>>>,
+    samples: broadcast::Sender<Arc<Sample>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Poller {
+    /// 为每个轮询组启动一个后台任务, `broadcast` 通道的容量为 [`DEFAULT_BROADCAST_CAPACITY`]
+    ///
+    /// 必须在 tokio 运行时中调用.
+    ///
+    /// # 参数
+    /// - client: 用来发送请求的客户端, 每个后台任务使用一个 `clone`
+    /// - groups: 轮询组
+    ///
+    /// # 返回
+    /// - 成功: 返回轮询引擎
+    /// - 失败: 轮询组的名字重复或者数据点定义不合法
+    pub fn spawn<C>(client: C, groups: Vec<PollGroup>) -> Result<Poller>
+    where
+        C: Reader + Clone + Send + 'static,
+    {
+        Self::spawn_with_capacity(client, groups, DEFAULT_BROADCAST_CAPACITY)
+    }
+
+    /// 为每个轮询组启动一个后台任务
+    ///
+    /// # 参数
+    /// - client: 用来发送请求的客户端, 每个后台任务使用一个 `clone`
+    /// - groups: 轮询组
+    /// - capacity: `broadcast` 通道的容量, 接收方处理太慢时会丢失最早的结果, 最小为 1
+    ///
+    /// # 返回
+    /// - 成功: 返回轮询引擎
+    /// - 失败: 轮询组的名字重复或者数据点定义不合法
+    pub fn spawn_with_capacity<C>(
+        client: C,
+        groups: Vec<PollGroup>,
+        capacity: usize,
+    ) -> Result<Poller>
+    where
+        C: Reader + Clone + Send + 'static,
+    {
+        let mut plans = Vec::with_capacity(groups.len());
+        for group in &groups {
+            if groups.iter().filter(|g| g.name == group.name).count() > 1 {
+                return Err(ModbusError::InvalidRequest(format!(
+                    "duplicate poll group `{}`",
+                    group.name
+                )));
+            }
+            plans.push(group.plan()?);
+        }
+
+        let (samples, _) = broadcast::channel(capacity.max(1));
+        let mut latest = HashMap::new();
+        let mut tasks = Vec::new();
+        for (group, plan) in groups.into_iter().zip(plans) {
+            let (sender, receiver) = watch::channel(None);
+            latest.insert(group.name.clone(), receiver);
+            tasks.push(tokio::spawn(run(
+                client.clone(),
+                group,
+                plan,
+                sender,
+                samples.clone(),
+            )));
+        }
+
+        Ok(Poller {
+            latest,
+            samples,
+            tasks,
+        })
+    }
+
+    /// 订阅所有轮询组的结果
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Sample>> {
+        self.samples.subscribe()
+    }
+
+    /// 返回轮询组最新结果的 `watch` 接收端, 轮询组不存在时返回 None
+    pub fn watch(&self, group: &str) -> Option<watch::Receiver<Option<Arc<Sample>>>> {
+        self.latest.get(group).cloned()
+    }
+
+    /// 返回轮询组最新的结果, 还没有完成第一次轮询或者轮询组不存在时返回 None
+    pub fn latest(&self, group: &str) -> Option<Arc<Sample>> {
+        self.latest.get(group)?.borrow().clone()
+    }
+}
+
+impl fmt::Debug for Poller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Poller")
+            .field("groups", &self.latest.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// 后台任务, 按照周期轮询一个组
+async fn run<C>(
+    mut client: C,
+    group: PollGroup,
+    plan: ReadPlan,
+    latest: watch::Sender<Option<Arc<Sample>>>,
+    samples: broadcast::Sender<Arc<Sample>>,
+) where
+    C: Reader + Send,
+{
+    let name: Arc<str> = Arc::from(group.name.as_str());
+    let mut interval = tokio::time::interval(group.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+        if !group.jitter.is_zero() {
+            tokio::time::sleep(group.jitter.mul_f64(random_unit())).await;
+        }
+
+        let timestamp = SystemTime::now();
+        let started = Instant::now();
+        let result = match plan.execute(&mut client).await {
+            Ok(values) => group
+                .tags
+                .iter()
+                .zip(&values)
+                .map(|(tag, value)| Ok((tag.name.clone(), tag.decode(value)?)))
+                .collect::<Result<Vec<_>>>(),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            log::debug!("POLLER: Group `{name}` failed: {e}");
+        }
+
+        let sample = Arc::new(Sample {
+            group: Arc::clone(&name),
+            timestamp,
+            elapsed: started.elapsed(),
+            result: result.map_err(Arc::new),
+        });
+        latest.send_replace(Some(Arc::clone(&sample)));
+        // 没有订阅者时发送失败, 忽略
+        let _ = samples.send(sample);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planner::Table;
+    use crate::tag::{Access, DataType};
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// 按照地址返回固定寄存器值的设备, 前几次读取依次花费 `delays` 中的时间, 之后立即返回
+    #[derive(Clone)]
+    struct Device {
+        registers: Arc<Vec<u16>>,
+        delays: Arc<Mutex<VecDeque<Duration>>>,
+        reads: Arc<Mutex<Vec<Instant>>>,
+    }
+
+    impl Device {
+        fn new(registers: Vec<u16>, delays: Vec<Duration>) -> Self {
+            Device {
+                registers: Arc::new(registers),
+                delays: Arc::new(Mutex::new(delays.into())),
+                reads: Arc::default(),
+            }
+        }
+
+        fn reads(&self) -> Vec<Instant> {
+            self.reads.lock().unwrap().clone()
+        }
+
+        async fn read(&self, address: u16, count: u16) -> Result<Vec<u16>> {
+            self.reads.lock().unwrap().push(Instant::now());
+            let delay = self.delays.lock().unwrap().pop_front();
+            tokio::time::sleep(delay.unwrap_or_default()).await;
+            let range = usize::from(address)..usize::from(address) + usize::from(count);
+            Ok(self.registers[range].to_vec())
+        }
+    }
+
+    #[async_trait]
+    impl Reader for Device {
+        async fn read_coils(&mut self, _: u16, count: u16) -> Result<Vec<bool>> {
+            Ok(vec![false; usize::from(count)])
+        }
+
+        async fn read_discrete_inputs(&mut self, _: u16, count: u16) -> Result<Vec<bool>> {
+            Ok(vec![false; usize::from(count)])
+        }
+
+        async fn read_holding_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>> {
+            self.read(address, count).await
+        }
+
+        async fn read_input_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>> {
+            self.read(address, count).await
+        }
+
+        async fn read_write_multiple_registers(
+            &mut self,
+            _: u16,
+            _: u16,
+            _: u16,
+            _: &[u16],
+        ) -> Result<Vec<u16>> {
+            // 轮询只使用读请求
+            Err(ModbusError::InvalidRequest(
+                "not supported by the test device".to_string(),
+            ))
+        }
+    }
+
+    fn register(name: &str, address: u16) -> Tag {
+        Tag::new(name, Table::HoldingRegister, address, DataType::U16)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_device_skips_ticks() {
+        let device = Device::new(vec![0; 4], vec![Duration::from_millis(1050)]);
+        let group = PollGroup::new("slow", Duration::from_millis(100)).tag(register("a", 0));
+        let start = Instant::now();
+        let poller = Poller::spawn(device.clone(), vec![group]).unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1350)).await;
+        // 设备恢复后只补一次, 之后回到原来的周期, 不会连续补发错过的 10 个周期
+        let reads: Vec<_> = device.reads().iter().map(|read| *read - start).collect();
+        assert_eq!(
+            reads,
+            [0, 1050, 1100, 1200, 1300].map(Duration::from_millis)
+        );
+        assert_eq!(poller.latest("slow").unwrap().elapsed, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_groups() {
+        let device = Device::new(vec![0; 4], Vec::new());
+        let groups = vec![
+            PollGroup::new("meter", Duration::from_secs(1)).tag(register("a", 0)),
+            PollGroup::new("meter", Duration::from_secs(2)).tag(register("b", 1)),
+        ];
+        let e = Poller::spawn(device.clone(), groups).unwrap_err();
+        assert!(matches!(e, ModbusError::InvalidRequest(_)), "{e}");
+
+        let command = Tag {
+            access: Access::Write,
+            ..register("command", 2)
+        };
+        let groups = vec![PollGroup::new("meter", Duration::from_secs(1)).tag(command)];
+        let e = Poller::spawn(device.clone(), groups).unwrap_err();
+        assert!(matches!(e, ModbusError::InvalidRequest(_)), "{e}");
+        assert!(device.reads().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn publish_samples() {
+        let device = Device::new(vec![7, 0x4142], Vec::new());
+        let groups = vec![
+            PollGroup::new("fast", Duration::from_millis(100)).tag(register("a", 0)),
+            PollGroup::new("slow", Duration::from_millis(300)).tag(register("b", 1)),
+        ];
+        let poller = Poller::spawn(device, groups).unwrap();
+        let mut samples = poller.subscribe();
+        let mut fast = poller.watch("fast").unwrap();
+        assert!(poller.watch("missing").is_none());
+
+        fast.changed().await.unwrap();
+        let latest = fast.borrow_and_update().clone().unwrap();
+        assert_eq!(latest.value("a"), Some(&Value::UInt(7)));
+        assert_eq!(
+            poller.latest("slow").unwrap().value("b"),
+            Some(&Value::UInt(0x4142))
+        );
+        assert!(poller.latest("missing").is_none());
+
+        // broadcast 通道收到每一次结果
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        let mut groups = Vec::new();
+        while let Ok(sample) = samples.try_recv() {
+            groups.push(sample.group.to_string());
+        }
+        groups.sort();
+        assert_eq!(groups, ["fast", "fast", "fast", "fast", "slow", "slow"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decode_failure_fails_sample() {
+        let device = Device::new(vec![7, 0xFFFE], Vec::new());
+        let name = Tag {
+            length: Some(1),
+            ..Tag::new("name", Table::HoldingRegister, 1, DataType::String)
+        };
+        let group = PollGroup::new("meter", Duration::from_millis(100))
+            .tag(register("a", 0))
+            .tag(name);
+        let poller = Poller::spawn(device, vec![group]).unwrap();
+
+        let mut meter = poller.watch("meter").unwrap();
+        meter.changed().await.unwrap();
+        let sample = meter.borrow().clone().unwrap();
+        let e = sample.result.as_ref().unwrap_err();
+        assert!(matches!(e.as_ref(), ModbusError::Protocol(_)), "{e}");
+        assert_eq!(sample.value("a"), None);
+    }
+}
@@ -0,0 +1,507 @@
+//! Modbus TCP 连接池.
+//!
+//! 有些 Modbus TCP 设备可以同时接受多个连接, 多个任务并行轮询时可以通过连接池各自使用一个连接,
+//! 不需要在同一个连接上排队.
+
+use crate::client::{Client, ClientBuilder};
+use crate::error::{ModbusError, Result};
+use crate::{BroadcastWrite, Reader, Writer};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_modbus::Request;
+
+/// 连接池构建器
+#[derive(Debug, Clone)]
+pub struct PoolBuilder {
+    client_builder: ClientBuilder,
+    min_size: usize,
+    max_size: usize,
+    idle_timeout: Duration,
+    health_check_interval: Option<Duration>,
+    health_check_address: u16,
+}
+
+impl Default for PoolBuilder {
+    fn default() -> Self {
+        PoolBuilder {
+            client_builder: ClientBuilder::new(),
+            min_size: 1,
+            max_size: 4,
+            idle_timeout: Duration::from_secs(300),
+            health_check_interval: Some(Duration::from_secs(30)),
+            health_check_address: 0,
+        }
+    }
+}
+
+impl PoolBuilder {
+    /// 使用默认配置创建构建器
+    ///
+    /// 默认最少 1 个连接, 最多 4 个连接, 空闲 5 分钟后关闭, 空闲超过 30 秒的连接在使用前做健康检查.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置创建连接时使用的 Client 构建器
+    pub fn client_builder(mut self, client_builder: ClientBuilder) -> Self {
+        self.client_builder = client_builder;
+        self
+    }
+
+    /// 设置最少保持的连接数量
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// 设置最多同时存在的连接数量, 最小为 1
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size.max(1);
+        self
+    }
+
+    /// 设置连接空闲多久后关闭, 连接数量不会少于最少连接数量
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// 设置健康检查
+    ///
+    /// 空闲超过 `interval` 的连接在使用前读取 `address` 处的一个保持寄存器,
+    /// 收到响应 (包括异常响应) 表示连接可用, 否则关闭连接. 健康检查只发送一次, 不重试也不重连,
+    /// 最多等待一次请求超时时间.
+    pub fn health_check(mut self, interval: Duration, address: u16) -> Self {
+        self.health_check_interval = Some(interval);
+        self.health_check_address = address;
+        self
+    }
+
+    /// 关闭健康检查
+    pub fn disable_health_check(mut self) -> Self {
+        self.health_check_interval = None;
+        self
+    }
+
+    /// 创建连接池并建立最少数量的连接
+    ///
+    /// 必须在 tokio 运行时中调用, 连接池会启动一个后台任务关闭空闲连接并补充连接.
+    ///
+    /// # 参数
+    /// - socket_addrs: 设备的 socket 地址列表, 建立连接时依次尝试
+    ///
+    /// # 返回
+    /// - 成功: 返回连接池
+    /// - 失败: 建立最少数量的连接失败
+    pub async fn build(self, socket_addrs: &[SocketAddr]) -> Result<Pool> {
+        let min_size = self.min_size.min(self.max_size);
+        let inner = Arc::new(PoolInner {
+            socket_addrs: socket_addrs.to_vec(),
+            permits: Arc::new(Semaphore::new(self.max_size)),
+            idle: Mutex::new(VecDeque::new()),
+            config: PoolBuilder { min_size, ..self },
+        });
+
+        for _ in 0..min_size {
+            let client = inner.connect().await?;
+            inner.push_idle(client);
+        }
+        tokio::spawn(maintain(Arc::downgrade(&inner)));
+
+        Ok(Pool { inner })
+    }
+}
+
+/// Modbus TCP 连接池
+///
+/// `clone` 得到的连接池共享同一组连接.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+impl Pool {
+    /// 创建连接池构建器
+    pub fn builder() -> PoolBuilder {
+        PoolBuilder::new()
+    }
+
+    /// 取出一个连接, 没有空闲连接并且连接数量达到上限时等待其他任务归还
+    ///
+    /// 连接在 [`PooledClient`] 释放时归还到连接池.
+    pub async fn get(&self) -> Result<PooledClient> {
+        let permit = Arc::clone(&self.inner.permits)
+            .acquire_owned()
+            .await
+            .expect("pool semaphore is never closed");
+
+        while let Some(idle) = self.inner.pop_idle() {
+            let mut client = idle.client;
+            if self.inner.needs_health_check(idle.since)
+                && !self.inner.is_healthy(&mut client).await
+            {
+                log::warn!("POOL: Drop unhealthy connection");
+                continue;
+            }
+            return Ok(self.pooled(client, permit));
+        }
+
+        let client = self.inner.connect().await?;
+        Ok(self.pooled(client, permit))
+    }
+
+    /// 返回当前空闲连接的数量
+    pub fn idle_count(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+
+    /// 返回正在使用的连接数量, 包括后台任务正在建立的连接
+    pub fn in_use_count(&self) -> usize {
+        self.inner.config.max_size - self.inner.permits.available_permits()
+    }
+
+    fn pooled(&self, client: Client, permit: OwnedSemaphorePermit) -> PooledClient {
+        PooledClient {
+            client: Some(client),
+            pool: Arc::clone(&self.inner),
+            _permit: permit,
+        }
+    }
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("socket_addrs", &self.inner.socket_addrs)
+            .field("idle", &self.idle_count())
+            .field("in_use", &self.in_use_count())
+            .finish()
+    }
+}
+
+/// 从连接池取出的连接
+///
+/// 实现了 [`Reader`] 和 [`Writer`], 释放时归还到连接池. 不提供内部的 [`Client`],
+/// 因为复制出来的 Client 共享连接并且不受最大连接数量限制.
+pub struct PooledClient {
+    client: Option<Client>,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledClient {
+    /// 关闭连接, 不再归还到连接池
+    ///
+    /// 连接出现无法恢复的错误时使用.
+    pub fn discard(mut self) {
+        self.client.take();
+    }
+
+    fn client(&mut self) -> &mut Client {
+        self.client.as_mut().expect("client is only taken on drop")
+    }
+}
+
+#[async_trait]
+impl Writer for PooledClient {
+    async fn write_single_coil(&mut self, address: u16, value: bool) -> Result<()> {
+        self.client().write_single_coil(address, value).await
+    }
+
+    async fn write_single_register(&mut self, address: u16, value: u16) -> Result<()> {
+        self.client().write_single_register(address, value).await
+    }
+
+    async fn write_multiple_coils(&mut self, address: u16, value: &[bool]) -> Result<()> {
+        self.client().write_multiple_coils(address, value).await
+    }
+
+    async fn write_multiple_registers(&mut self, address: u16, value: &[u16]) -> Result<()> {
+        self.client().write_multiple_registers(address, value).await
+    }
+
+    async fn masked_write_register(
+        &mut self,
+        address: u16,
+        and_mask: u16,
+        or_mask: u16,
+    ) -> Result<()> {
+        self.client()
+            .masked_write_register(address, and_mask, or_mask)
+            .await
+    }
+
+    async fn broadcast_write(&mut self, request: BroadcastWrite<'_>) -> Result<()> {
+        self.client().broadcast_write(request).await
+    }
+}
+
+#[async_trait]
+impl Reader for PooledClient {
+    async fn read_coils(&mut self, address: u16, count: u16) -> Result<Vec<bool>> {
+        self.client().read_coils(address, count).await
+    }
+
+    async fn read_discrete_inputs(&mut self, address: u16, count: u16) -> Result<Vec<bool>> {
+        self.client().read_discrete_inputs(address, count).await
+    }
+
+    async fn read_holding_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>> {
+        self.client().read_holding_registers(address, count).await
+    }
+
+    async fn read_input_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>> {
+        self.client().read_input_registers(address, count).await
+    }
+
+    async fn read_write_multiple_registers(
+        &mut self,
+        read_addr: u16,
+        read_count: u16,
+        write_addr: u16,
+        write_data: &[u16],
+    ) -> Result<Vec<u16>> {
+        self.client()
+            .read_write_multiple_registers(read_addr, read_count, write_addr, write_data)
+            .await
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.push_idle(client);
+        }
+    }
+}
+
+struct Idle {
+    client: Client,
+    since: Instant,
+}
+
+struct PoolInner {
+    config: PoolBuilder,
+    socket_addrs: Vec<SocketAddr>,
+    permits: Arc<Semaphore>,
+    idle: Mutex<VecDeque<Idle>>,
+}
+
+impl PoolInner {
+    async fn connect(&self) -> Result<Client> {
+        if self.socket_addrs.is_empty() {
+            return Err(ModbusError::InvalidRequest(
+                "Pool has no socket address".to_string(),
+            ));
+        }
+        self.config
+            .client_builder
+            .clone()
+            .build_tcp_addrs(&self.socket_addrs)
+            .await
+    }
+
+    /// 取出最近归还的连接, 让其他连接逐渐空闲超时
+    fn pop_idle(&self) -> Option<Idle> {
+        self.idle.lock().unwrap().pop_back()
+    }
+
+    fn push_idle(&self, client: Client) {
+        self.idle.lock().unwrap().push_back(Idle {
+            client,
+            since: Instant::now(),
+        });
+    }
+
+    fn needs_health_check(&self, since: Instant) -> bool {
+        self.config
+            .health_check_interval
+            .is_some_and(|interval| since.elapsed() >= interval)
+    }
+
+    /// 只发送一次请求, 不按照 Client 的重试和重连策略, 避免一个断开的连接长时间阻塞 `get`
+    async fn is_healthy(&self, client: &mut Client) -> bool {
+        let request = Request::ReadHoldingRegisters(self.config.health_check_address, 1);
+        match client.probe(&request).await {
+            Ok(_) | Err(ModbusError::Exception(_)) => true,
+            Err(e) => {
+                log::debug!("POOL: Health check failed: {e}");
+                false
+            }
+        }
+    }
+
+    /// 关闭空闲超时的连接, 并把连接数量补充到最少连接数量
+    async fn maintain(&self) {
+        let in_use = self.config.max_size - self.permits.available_permits();
+        {
+            let mut idle = self.idle.lock().unwrap();
+            // 队头是最早归还的连接
+            while in_use + idle.len() > self.config.min_size
+                && idle
+                    .front()
+                    .is_some_and(|conn| conn.since.elapsed() >= self.config.idle_timeout)
+            {
+                idle.pop_front();
+                log::debug!("POOL: Close idle connection");
+            }
+        }
+
+        loop {
+            // 建立连接之前先占用一个名额, 和同时调用的 `get` 一起不会超过最大连接数量,
+            // 连接失败时名额随 permit 一起释放
+            let Ok(permit) = Arc::clone(&self.permits).try_acquire_owned() else {
+                break;
+            };
+            let in_use = self.config.max_size - self.permits.available_permits() - 1;
+            if in_use + self.idle.lock().unwrap().len() >= self.config.min_size {
+                break;
+            }
+            match self.connect().await {
+                Ok(client) => self.push_idle(client),
+                Err(e) => {
+                    log::warn!("POOL: Failed to refill connection: {e}");
+                    break;
+                }
+            }
+            drop(permit);
+        }
+    }
+}
+
+/// 后台任务, 连接池释放后退出
+async fn maintain(pool: Weak<PoolInner>) {
+    let period = match pool.upgrade() {
+        Some(pool) => (pool.config.idle_timeout / 2).max(Duration::from_secs(1)),
+        None => return,
+    };
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match pool.upgrade() {
+            Some(pool) => pool.maintain().await,
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{read_range, registers_response, MockServer, Reply};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::time::{sleep, timeout};
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn respond(_: u8, pdu: &[u8]) -> Reply {
+        let (_, count) = read_range(pdu);
+        Reply::Pdu(registers_response(pdu[0], &vec![0; usize::from(count)]))
+    }
+
+    fn builder() -> PoolBuilder {
+        Pool::builder().client_builder(ClientBuilder::new().timeout(TIMEOUT))
+    }
+
+    /// 等待从机上没有关闭的连接数量变为 `count`
+    async fn wait_connections(server: &MockServer, count: usize) {
+        while server.connections() != count {
+            sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn block_at_max_size() {
+        let server = MockServer::start(respond).await;
+        let pool = builder()
+            .min_size(0)
+            .max_size(2)
+            .build(&[server.addr])
+            .await
+            .unwrap();
+
+        let mut first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        assert_eq!(pool.in_use_count(), 2);
+        assert!(timeout(TIMEOUT, pool.get()).await.is_err());
+        wait_connections(&server, 2).await;
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.get().await.map(|_| ()) }
+        });
+        assert!(first.read_holding_registers(0, 1).await.is_ok());
+        drop(first);
+        waiting.await.unwrap().unwrap();
+        drop(second);
+        // 归还的连接被复用, 没有建立新的连接
+        assert_eq!(pool.idle_count(), 2);
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn evict_idle_connections() {
+        let server = MockServer::start(respond).await;
+        let pool = builder()
+            .min_size(1)
+            .max_size(3)
+            .idle_timeout(TIMEOUT)
+            .build(&[server.addr])
+            .await
+            .unwrap();
+
+        let clients = [
+            pool.get().await.unwrap(),
+            pool.get().await.unwrap(),
+            pool.get().await.unwrap(),
+        ];
+        drop(clients);
+        assert_eq!(pool.idle_count(), 3);
+        wait_connections(&server, 3).await;
+
+        sleep(TIMEOUT * 2).await;
+        pool.inner.maintain().await;
+        // 不会少于最少连接数量
+        assert_eq!(pool.idle_count(), 1);
+        wait_connections(&server, 1).await;
+    }
+
+    #[tokio::test]
+    async fn drop_unhealthy_connection() {
+        let silent = Arc::new(AtomicBool::new(false));
+        let server = MockServer::start({
+            let silent = silent.clone();
+            move |unit_id, pdu| match silent.load(Ordering::SeqCst) {
+                true => Reply::Silent,
+                false => respond(unit_id, pdu),
+            }
+        })
+        .await;
+        let pool = builder()
+            .min_size(1)
+            .health_check(Duration::ZERO, 7)
+            .build(&[server.addr])
+            .await
+            .unwrap();
+        assert_eq!(pool.idle_count(), 1);
+
+        silent.store(true, Ordering::SeqCst);
+        let started = Instant::now();
+        let client = pool.get().await.unwrap();
+        // 健康检查只发送一次, 不按照 Client 的重试策略重新发送
+        assert!(started.elapsed() < TIMEOUT * 3);
+        assert_eq!(server.requests(), [(1, vec![0x03, 0x00, 0x07, 0x00, 0x01])]);
+        drop(client);
+        assert_eq!(pool.idle_count(), 1);
+        // 没有通过健康检查的连接被关闭
+        wait_connections(&server, 1).await;
+    }
+}
@@ -0,0 +1,590 @@
+//! 设备描述文件.
+//!
+//! 设备描述文件用 TOML 或 JSON 列出设备的数据点, 接入新型号的设备时只需要编写描述文件:
+//!
+//! ```toml
+//! name = "DTSU666"
+//!
+//! [[tags]]
+//! name = "voltage_a"
+//! table = "holding"
+//! address = 0x2006
+//! type = "f32"
+//! scale = 0.1
+//! unit = "V"
+//!
+//! [[tags]]
+//! name = "serial"
+//! table = "holding"
+//! address = 0x0100
+//! type = "string"
+//! length = 8
+//!
+//! [[tags]]
+//! name = "relay"
+//! table = "coil"
+//! address = 0
+//! type = "bool"
+//! access = "rw"
+//! ```
+//!
+//! 数据点的字段:
+//! - name: 名字, 同一个文件中唯一
+//! - table: 数据表, `coil`, `discrete`, `input` 或 `holding`
+//! - address: 第一个寄存器或线圈的地址
+//! - type: 数据类型, `bool`, `u16`, `i16`, `u32`, `i32`, `f32`, `u64`, `i64`, `f64`, `string` 或 `bcd`
+//! - length: `string` 和 `bcd` 占用的寄存器数量, `string` 最多 125 个, `bcd` 最多 5 个
+//! - order: 字节序, `ABCD`, `CDAB`, `BADC` 或 `DCBA`, 默认 `ABCD`
+//! - swap_bytes: `string` 交换每个寄存器中的两个字符, 默认 `false`
+//! - scale, offset: 工程值 = 原始值 * scale + offset, 默认 1 和 0
+//! - unit: 工程单位
+//! - access: `r`, `rw` 或 `w`, 默认 `r`
+//! - description: 说明
+
+use crate::error::{ModbusError, Result};
+use crate::planner::ReadPlanner;
+use crate::registers::WriteItem;
+use crate::tag::{Tag, Value};
+use crate::{Reader, Writer};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// 设备描述
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// 设备型号
+    #[serde(default)]
+    pub name: Option<String>,
+    /// 说明
+    #[serde(default)]
+    pub description: Option<String>,
+    /// 数据点
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+}
+
+impl Profile {
+    /// 从 TOML 解析设备描述并检查
+    pub fn from_toml(s: &str) -> Result<Profile> {
+        let profile: Profile = toml::from_str(s).map_err(|e| invalid(&e.to_string()))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// 从 JSON 解析设备描述并检查
+    pub fn from_json(s: &str) -> Result<Profile> {
+        let profile: Profile = serde_json::from_str(s).map_err(|e| invalid(&e.to_string()))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// 读取设备描述文件
+    ///
+    /// # 参数
+    /// - path: 文件路径, 扩展名为 `.json` 时按照 JSON 解析, 否则按照 TOML 解析
+    ///
+    /// # 返回
+    /// - 成功: 返回设备描述
+    /// - 失败: 读取文件失败, 格式错误或者数据点定义不合法
+    pub fn load(path: impl AsRef<Path>) -> Result<Profile> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(ModbusError::Io)?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        if is_json {
+            Self::from_json(&content)
+        } else {
+            Self::from_toml(&content)
+        }
+    }
+
+    /// 检查所有数据点的定义, 并且名字不能重复
+    pub fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for tag in &self.tags {
+            tag.validate()?;
+            if !names.insert(tag.name.as_str()) {
+                return Err(invalid(&format!("duplicate tag `{}`", tag.name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// 按照名字查找数据点
+    pub fn tag(&self, name: &str) -> Option<&Tag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+}
+
+/// 按照设备描述读写数据点
+///
+/// `C` 可以是 [`crate::client::Client`], [`crate::handle::ClientHandle`] 或者其他实现了
+/// [`Reader`] 和 [`Writer`] 的类型.
+#[derive(Debug)]
+pub struct Device<C> {
+    client: C,
+    profile: Profile,
+    planner: ReadPlanner,
+    index: HashMap<String, usize>,
+}
+
+impl<C> Device<C> {
+    /// 创建设备
+    ///
+    /// # 参数
+    /// - client: 用来发送请求的客户端
+    /// - profile: 设备描述
+    ///
+    /// # 返回
+    /// - 成功: 返回设备
+    /// - 失败: 设备描述不合法
+    pub fn new(client: C, profile: Profile) -> Result<Self> {
+        profile.validate()?;
+        let index = profile
+            .tags
+            .iter()
+            .enumerate()
+            .map(|(i, tag)| (tag.name.clone(), i))
+            .collect();
+        Ok(Device {
+            client,
+            profile,
+            planner: ReadPlanner::new(),
+            index,
+        })
+    }
+
+    /// 设置合并读请求使用的规划器
+    pub fn planner(mut self, planner: ReadPlanner) -> Self {
+        self.planner = planner;
+        self
+    }
+
+    /// 返回设备描述
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// 返回客户端
+    pub fn client(&mut self) -> &mut C {
+        &mut self.client
+    }
+
+    /// 释放设备并返回客户端
+    pub fn into_client(self) -> C {
+        self.client
+    }
+
+    fn tag(&self, name: &str) -> Result<&Tag> {
+        self.index
+            .get(name)
+            .map(|&i| &self.profile.tags[i])
+            .ok_or_else(|| invalid(&format!("unknown tag `{name}`")))
+    }
+}
+
+impl<C: Reader + Send> Device<C> {
+    /// 读取一个数据点
+    ///
+    /// # 返回
+    /// - 成功: 返回工程值
+    /// - 失败: 数据点不存在, 不可读或者读取失败
+    pub async fn read(&mut self, name: &str) -> Result<Value> {
+        let mut values = self.read_many(&[name]).await?;
+        Ok(values.remove(0))
+    }
+
+    /// 读取多个数据点, 读请求会合并成尽量少的请求
+    ///
+    /// # 返回
+    /// - 成功: 按照 `names` 的顺序返回工程值
+    /// - 失败: 任意一个数据点不存在, 不可读或者读取失败
+    pub async fn read_many(&mut self, names: &[&str]) -> Result<Vec<Value>> {
+        let tags = names
+            .iter()
+            .map(|name| {
+                let tag = self.tag(name)?;
+                if !tag.access.is_readable() {
+                    return Err(invalid(&format!("tag `{name}` is not readable")));
+                }
+                Ok(tag.clone())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        read_tags(&mut self.client, &self.planner, &tags).await
+    }
+
+    /// 读取所有可读的数据点
+    ///
+    /// # 返回
+    /// - 成功: 按照设备描述中的顺序返回名字和工程值
+    /// - 失败: 任意一个请求失败
+    pub async fn read_all(&mut self) -> Result<Vec<(String, Value)>> {
+        let tags: Vec<Tag> = self
+            .profile
+            .tags
+            .iter()
+            .filter(|tag| tag.access.is_readable())
+            .cloned()
+            .collect();
+        let values = read_tags(&mut self.client, &self.planner, &tags).await?;
+        Ok(tags.into_iter().map(|tag| tag.name).zip(values).collect())
+    }
+}
+
+impl<C: Writer + Send> Device<C> {
+    /// 写入一个数据点
+    ///
+    /// # 参数
+    /// - name: 数据点的名字
+    /// - value: 工程值, 按照缩放系数转换成原始值后写入
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 数据点不存在, 不可写, 值不合法或者写入失败
+    pub async fn write(&mut self, name: &str, value: impl Into<Value>) -> Result<()> {
+        let tag = self.tag(name)?;
+        if !tag.access.is_writable() {
+            return Err(invalid(&format!("tag `{name}` is not writable")));
+        }
+        match tag.encode(&value.into())? {
+            WriteItem::Registers { address, values } => {
+                self.client.write_multiple_registers(address, &values).await
+            }
+            WriteItem::Coil { address, value } => {
+                self.client.write_single_coil(address, value).await
+            }
+        }
+    }
+}
+
+/// 合并读取多个数据点并转换成工程值
+async fn read_tags<R>(reader: &mut R, planner: &ReadPlanner, tags: &[Tag]) -> Result<Vec<Value>>
+where
+    R: Reader + Send + ?Sized,
+{
+    let items: Vec<_> = tags.iter().map(Tag::read_item).collect();
+    let values = planner.plan(&items)?.execute(reader).await?;
+    tags.iter()
+        .zip(&values)
+        .map(|(tag, value)| tag.decode(value))
+        .collect()
+}
+
+fn invalid(reason: &str) -> ModbusError {
+    ModbusError::InvalidRequest(format!("invalid profile: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ByteOrder;
+    use crate::planner::Table;
+    use crate::tag::{Access, DataType};
+
+    const TOML: &str = r#"
+name = "meter"
+description = "三相电表"
+
+[[tags]]
+name = "voltage"
+table = "holding"
+address = 0x2006
+type = "f32"
+order = "CDAB"
+scale = 0.1
+unit = "V"
+access = "rw"
+
+[[tags]]
+name = "temperature"
+table = "input"
+address = 0x0001
+type = "i16"
+scale = 0.1
+
+[[tags]]
+name = "setpoint"
+table = "holding"
+address = 0x0010
+type = "u16"
+scale = 0.5
+offset = 10
+access = "rw"
+
+[[tags]]
+name = "serial"
+table = "holding"
+address = 0x0100
+type = "string"
+length = 4
+access = "rw"
+
+[[tags]]
+name = "relay"
+table = "coil"
+address = 0
+type = "bool"
+access = "rw"
+
+[[tags]]
+name = "command"
+table = "holding"
+address = 0x0020
+type = "u16"
+access = "w"
+"#;
+
+    const JSON: &str = r#"{
+    "name": "meter",
+    "description": "三相电表",
+    "tags": [
+        { "name": "voltage", "table": "holding", "address": 8198, "type": "f32",
+          "order": "CDAB", "scale": 0.1, "unit": "V", "access": "rw" },
+        { "name": "temperature", "table": "input", "address": 1, "type": "i16", "scale": 0.1 },
+        { "name": "setpoint", "table": "holding", "address": 16, "type": "u16",
+          "scale": 0.5, "offset": 10, "access": "rw" },
+        { "name": "serial", "table": "holding", "address": 256, "type": "string",
+          "length": 4, "access": "rw" },
+        { "name": "relay", "table": "coil", "address": 0, "type": "bool", "access": "rw" },
+        { "name": "command", "table": "holding", "address": 32, "type": "u16", "access": "w" }
+    ]
+}"#;
+
+    fn assert_invalid(result: Result<Profile>, message: &str) {
+        match result {
+            Err(ModbusError::InvalidRequest(e)) => assert!(e.contains(message), "{e}"),
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_toml_and_json() {
+        let profile = Profile::from_toml(TOML).unwrap();
+        assert_eq!(profile.name.as_deref(), Some("meter"));
+        assert_eq!(profile.tags.len(), 6);
+
+        let voltage = profile.tag("voltage").unwrap();
+        assert_eq!(voltage.table, Table::HoldingRegister);
+        assert_eq!(voltage.address, 0x2006);
+        assert_eq!(voltage.order, ByteOrder::Cdab);
+        assert_eq!(voltage.scale, 0.1);
+        assert_eq!(voltage.unit.as_deref(), Some("V"));
+        assert_eq!(voltage.access, Access::ReadWrite);
+
+        let setpoint = profile.tag("setpoint").unwrap();
+        assert_eq!((setpoint.scale, setpoint.offset), (0.5, 10.0));
+        assert_eq!(setpoint.order, ByteOrder::Abcd);
+        assert_eq!(setpoint.access, Access::ReadWrite);
+        let temperature = profile.tag("temperature").unwrap();
+        assert_eq!(temperature.table, Table::InputRegister);
+        assert_eq!(temperature.access, Access::Read);
+        assert_eq!(profile.tag("serial").unwrap().length, Some(4));
+        assert_eq!(profile.tag("relay").unwrap().table, Table::Coil);
+        assert_eq!(profile.tag("command").unwrap().access, Access::Write);
+        assert!(profile.tag("current").is_none());
+
+        assert_eq!(Profile::from_json(JSON).unwrap(), profile);
+    }
+
+    #[test]
+    fn reject_duplicate_and_unknown_fields() {
+        let tag =
+            "[[tags]]\nname = \"voltage\"\ntable = \"holding\"\naddress = 0x2006\ntype = \"f32\"\n";
+        assert!(Profile::from_toml(tag).is_ok());
+
+        assert_invalid(
+            Profile::from_toml(&format!("{tag}address = 1\n")),
+            "address",
+        );
+        assert_invalid(Profile::from_toml(&format!("{tag}scael = 0.1\n")), "scael");
+        assert_invalid(
+            Profile::from_toml(&format!("vendor = \"acme\"\n{tag}")),
+            "vendor",
+        );
+
+        let tag = r#""name": "voltage", "table": "holding", "address": 8198, "type": "f32""#;
+        assert!(Profile::from_json(&format!(r#"{{ "tags": [{{ {tag} }}] }}"#)).is_ok());
+        assert_invalid(
+            Profile::from_json(&format!(r#"{{ "tags": [{{ {tag}, "address": 1 }}] }}"#)),
+            "duplicate field `address`",
+        );
+        assert_invalid(
+            Profile::from_json(&format!(r#"{{ "tags": [{{ {tag}, "scael": 0.1 }}] }}"#)),
+            "unknown field `scael`",
+        );
+    }
+
+    #[test]
+    fn reject_invalid_tags() {
+        let voltage =
+            "[[tags]]\nname = \"voltage\"\ntable = \"holding\"\naddress = 0\ntype = \"u16\"\n";
+        assert_invalid(
+            Profile::from_toml(&format!("{voltage}{voltage}")),
+            "duplicate tag `voltage`",
+        );
+        assert_invalid(
+            Profile::from_toml(
+                "[[tags]]\nname = \"alarm\"\ntable = \"input\"\naddress = 0\ntype = \"u16\"\naccess = \"rw\"\n",
+            ),
+            "input tables are read only",
+        );
+
+        // 手动构造的设备描述在创建设备时检查
+        let tag = Tag::new("voltage", Table::HoldingRegister, 0, DataType::U16);
+        let profile = Profile {
+            tags: vec![tag.clone(), tag],
+            ..Profile::default()
+        };
+        assert!(matches!(
+            profile.validate(),
+            Err(ModbusError::InvalidRequest(e)) if e.contains("duplicate tag `voltage`")
+        ));
+        assert!(Device::new((), profile).is_err());
+    }
+
+    #[test]
+    fn load_by_extension() {
+        let dir = std::env::temp_dir().join(format!("async-modbus-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected = Profile::from_toml(TOML).unwrap();
+
+        for (file, content) in [
+            ("meter.toml", TOML),
+            ("meter.json", JSON),
+            ("meter.JSON", JSON),
+            ("meter", TOML),
+        ] {
+            let path = dir.join(file);
+            std::fs::write(&path, content).unwrap();
+            assert_eq!(Profile::load(&path).unwrap(), expected, "{file}");
+        }
+
+        // 不是 .json 的文件都按照 TOML 解析
+        let path = dir.join("meter.txt");
+        std::fs::write(&path, JSON).unwrap();
+        assert!(matches!(
+            Profile::load(&path),
+            Err(ModbusError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            Profile::load(dir.join("missing.toml")),
+            Err(ModbusError::Io(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    mod device {
+        use super::*;
+        use crate::client::ClientBuilder;
+        use crate::mock::{read_range, registers_response, write_response, MockServer, Reply};
+
+        /// 保存线圈和寄存器的从机, 写入的值可以读回, 输入寄存器 1 的值是 -123
+        fn memory() -> impl FnMut(u8, &[u8]) -> Reply {
+            let mut coils = vec![false; 0x1_0000];
+            let mut holding = vec![0u16; 0x1_0000];
+            let mut input = vec![0u16; 0x1_0000];
+            input[1] = -123i16 as u16;
+            move |_, pdu| {
+                let (address, count) = read_range(pdu);
+                let range = usize::from(address)..usize::from(address) + usize::from(count);
+                let response = match pdu[0] {
+                    0x01 => {
+                        let mut response = vec![0x01, count.div_ceil(8) as u8];
+                        for (i, &coil) in coils[range].iter().enumerate() {
+                            if i % 8 == 0 {
+                                response.push(0);
+                            }
+                            *response.last_mut().unwrap() |= u8::from(coil) << (i % 8);
+                        }
+                        response
+                    }
+                    0x03 => registers_response(0x03, &holding[range]),
+                    0x04 => registers_response(0x04, &input[range]),
+                    0x05 => {
+                        coils[usize::from(address)] = count == 0xFF00;
+                        write_response(pdu)
+                    }
+                    0x10 => {
+                        for (register, value) in holding[range].iter_mut().zip(pdu[6..].chunks(2)) {
+                            *register = u16::from_be_bytes([value[0], value[1]]);
+                        }
+                        write_response(pdu)
+                    }
+                    function => vec![function | 0x80, 0x01],
+                };
+                Reply::Pdu(response)
+            }
+        }
+
+        fn assert_float(value: Value, expected: f64) {
+            match value {
+                Value::Float(value) => assert!((value - expected).abs() < 1e-4, "{value}"),
+                value => panic!("unexpected value: {value:?}"),
+            }
+        }
+
+        #[tokio::test]
+        async fn read_and_write_by_name() {
+            let server = MockServer::start(memory()).await;
+            let client = ClientBuilder::new().build_tcp(server.addr).await.unwrap();
+            let mut device = Device::new(client, Profile::from_toml(TOML).unwrap()).unwrap();
+
+            device.write("voltage", 230.0).await.unwrap();
+            device.write("setpoint", 25.0).await.unwrap();
+            device.write("serial", "AB12").await.unwrap();
+            device.write("relay", true).await.unwrap();
+            device.write("command", 7u64).await.unwrap();
+            // 反向缩放后按照字节序写入原始值, 2300.0f32 是 0x450FC000
+            let requests = server.requests();
+            assert_eq!(
+                requests[0].1,
+                [0x10, 0x20, 0x06, 0x00, 0x02, 0x04, 0xC0, 0x00, 0x45, 0x0F]
+            );
+            assert_eq!(
+                requests[1].1,
+                [0x10, 0x00, 0x10, 0x00, 0x01, 0x02, 0x00, 30]
+            );
+            assert_eq!(requests[3].1, [0x05, 0x00, 0x00, 0xFF, 0x00]);
+
+            assert_float(device.read("voltage").await.unwrap(), 230.0);
+            assert_float(device.read("setpoint").await.unwrap(), 25.0);
+            assert_float(device.read("temperature").await.unwrap(), -12.3);
+            assert_eq!(
+                device.read("serial").await.unwrap(),
+                Value::String("AB12".to_string())
+            );
+            let values = device.read_many(&["relay", "setpoint"]).await.unwrap();
+            assert_eq!(values[0], Value::Bool(true));
+            assert_float(values[1].clone(), 25.0);
+
+            let values = device.read_all().await.unwrap();
+            let names: Vec<_> = values.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(
+                names,
+                ["voltage", "temperature", "setpoint", "serial", "relay"]
+            );
+            assert_float(values[0].1.clone(), 230.0);
+
+            // 按照访问权限和名字检查, 不发送请求
+            let sent = server.requests().len();
+            assert!(matches!(
+                device.read("command").await,
+                Err(ModbusError::InvalidRequest(_))
+            ));
+            assert!(matches!(
+                device.read_many(&["relay", "current"]).await,
+                Err(ModbusError::InvalidRequest(_))
+            ));
+            assert!(matches!(
+                device.write("temperature", 1.0).await,
+                Err(ModbusError::InvalidRequest(_))
+            ));
+            assert_eq!(server.requests().len(), sent);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! 带数据质量的读取结果.
+//!
+//! `Client::read_*_with_quality` 返回 [`Reading`], 除了值以外还包含采样时间, 往返时间,
+//! 实际发送的次数和数据质量 [`Quality`], 读取失败时不返回错误, 而是返回 [`Quality::Bad`] 和原因.
+//!
+//! 设备停止响应时, 可以用 [`LastKnown`] 保留最后一次成功读取的值:
+//! - 没有超过最大时间: 返回最后的值, 质量为 [`Quality::Uncertain`]
+//! - 超过最大时间: 返回最后的值, 质量为 [`Quality::Bad`]
+
+use crate::client::{result_value_bool, result_value_u16, Client, ResultValue};
+use crate::error::Result;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+use tokio_modbus::prelude::Request;
+
+/// 数据质量
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "quality", content = "reason", rename_all = "lowercase")
+)]
+pub enum Quality {
+    /// 从设备读取成功
+    Good,
+    /// 值可能已经不是设备当前的值, 比如设备没有响应时返回的最后一次读取的值
+    Uncertain(String),
+    /// 值不可用或者已经过期
+    Bad(String),
+}
+
+impl Quality {
+    /// 是否是 [`Quality::Good`]
+    pub fn is_good(&self) -> bool {
+        matches!(self, Quality::Good)
+    }
+
+    /// 是否是 [`Quality::Bad`]
+    pub fn is_bad(&self) -> bool {
+        matches!(self, Quality::Bad(_))
+    }
+
+    /// 返回不是 [`Quality::Good`] 的原因
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Quality::Good => None,
+            Quality::Uncertain(reason) | Quality::Bad(reason) => Some(reason),
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quality::Good => write!(f, "good"),
+            Quality::Uncertain(reason) => write!(f, "uncertain: {reason}"),
+            Quality::Bad(reason) => write!(f, "bad: {reason}"),
+        }
+    }
+}
+
+/// 一次读取的结果
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reading<T> {
+    /// 读取的值, 没有可用的值时为 None
+    pub value: Option<T>,
+    /// 采样时间, 也就是开始读取 `value` 的时间
+    pub timestamp: SystemTime,
+    /// 从发送第一个请求到收到最后一个响应的时间, 包含重试的等待时间
+    pub latency: Duration,
+    /// 实际发送请求的次数, 包含拆分的请求和重试
+    pub attempts: u32,
+    /// 数据质量
+    pub quality: Quality,
+}
+
+impl<T> Reading<T> {
+    /// 距离采样时间过去了多久, 系统时间被调回时返回 0
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.timestamp)
+            .unwrap_or_default()
+    }
+
+    /// 采样时间是否已经超过 `max_age`
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.age() > max_age
+    }
+
+    /// 转换值, 其他字段不变
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Reading<U> {
+        Reading {
+            value: self.value.map(f),
+            timestamp: self.timestamp,
+            latency: self.latency,
+            attempts: self.attempts,
+            quality: self.quality,
+        }
+    }
+}
+
+/// 保留最后一次成功读取的值, 检测值是否过期
+#[derive(Debug, Clone)]
+pub struct LastKnown<T> {
+    max_age: Duration,
+    last: Option<Reading<T>>,
+}
+
+impl<T: Clone> LastKnown<T> {
+    /// 创建
+    ///
+    /// # 参数
+    /// - max_age: 最后一次成功读取的值最多可以使用多久
+    pub fn new(max_age: Duration) -> Self {
+        LastKnown {
+            max_age,
+            last: None,
+        }
+    }
+
+    /// 用新的读取结果更新
+    ///
+    /// # 返回
+    /// - 读取成功: 原样返回
+    /// - 读取失败: 有最后一次成功读取的值时返回这个值, 按照是否过期设置为 `Uncertain` 或者 `Bad`,
+    ///   `latency` 和 `attempts` 是本次读取的; 否则原样返回
+    pub fn update(&mut self, reading: Reading<T>) -> Reading<T> {
+        if reading.quality.is_good() {
+            self.last = Some(reading.clone());
+            return reading;
+        }
+        let Some(last) = &self.last else {
+            return reading;
+        };
+        let reason = reading.quality.reason().unwrap_or_default();
+        let quality = if last.is_stale(self.max_age) {
+            Quality::Bad(format!("stale value, {reason}"))
+        } else {
+            Quality::Uncertain(format!("last known value, {reason}"))
+        };
+        Reading {
+            value: last.value.clone(),
+            timestamp: last.timestamp,
+            latency: reading.latency,
+            attempts: reading.attempts,
+            quality,
+        }
+    }
+
+    /// 返回最后一次成功读取的结果, 过期时质量为 `Bad`
+    pub fn get(&self) -> Option<Reading<T>> {
+        let mut last = self.last.clone()?;
+        if last.is_stale(self.max_age) {
+            last.quality = Quality::Bad("stale value".to_string());
+        }
+        Some(last)
+    }
+
+    /// 清除最后一次成功读取的值
+    pub fn clear(&mut self) {
+        self.last = None;
+    }
+}
+
+impl Client {
+    /// 读线圈, 返回带数据质量的结果
+    pub async fn read_coils_with_quality(
+        &mut self,
+        address: u16,
+        count: u16,
+    ) -> Reading<Vec<bool>> {
+        let request = Request::ReadCoils(address, count);
+        self.read_with_quality(request, result_value_bool).await
+    }
+
+    /// 读离散输入, 返回带数据质量的结果
+    pub async fn read_discrete_inputs_with_quality(
+        &mut self,
+        address: u16,
+        count: u16,
+    ) -> Reading<Vec<bool>> {
+        let request = Request::ReadDiscreteInputs(address, count);
+        self.read_with_quality(request, result_value_bool).await
+    }
+
+    /// 读保持寄存器, 返回带数据质量的结果
+    pub async fn read_holding_registers_with_quality(
+        &mut self,
+        address: u16,
+        count: u16,
+    ) -> Reading<Vec<u16>> {
+        let request = Request::ReadHoldingRegisters(address, count);
+        self.read_with_quality(request, result_value_u16).await
+    }
+
+    /// 读输入寄存器, 返回带数据质量的结果
+    pub async fn read_input_registers_with_quality(
+        &mut self,
+        address: u16,
+        count: u16,
+    ) -> Reading<Vec<u16>> {
+        let request = Request::ReadInputRegisters(address, count);
+        self.read_with_quality(request, result_value_u16).await
+    }
+
+    async fn read_with_quality<T>(
+        &mut self,
+        request: Request<'_>,
+        convert: fn(ResultValue) -> Result<T>,
+    ) -> Reading<T> {
+        let timestamp = SystemTime::now();
+        let started = Instant::now();
+        let mut attempts = 0;
+        let result = self.execute_counted(&request, &mut attempts).await;
+        let (value, quality) = match result.and_then(convert) {
+            Ok(value) => (Some(value), Quality::Good),
+            Err(e) => (None, Quality::Bad(e.to_string())),
+        };
+        Reading {
+            value,
+            timestamp,
+            latency: started.elapsed(),
+            attempts,
+            quality,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "modbus_tcp_client")]
+    use crate::client::{ClientBuilder, RequestLimits};
+    #[cfg(feature = "modbus_tcp_client")]
+    use crate::mock::{read_range, registers_response, MockServer, Reply};
+
+    fn reading(value: Option<u16>, attempts: u32, quality: Quality) -> Reading<u16> {
+        Reading {
+            value,
+            timestamp: SystemTime::now(),
+            latency: Duration::from_millis(u64::from(attempts)),
+            attempts,
+            quality,
+        }
+    }
+
+    #[test]
+    fn last_known_value_becomes_stale() {
+        let mut last = LastKnown::new(Duration::from_millis(100));
+        let good = last.update(reading(Some(7), 1, Quality::Good));
+        assert_eq!(good.quality, Quality::Good);
+
+        let failed = || reading(None, 3, Quality::Bad("timeout".to_string()));
+        let uncertain = last.update(failed());
+        assert_eq!(uncertain.value, Some(7));
+        assert_eq!(uncertain.timestamp, good.timestamp);
+        assert_eq!(uncertain.attempts, 3);
+        assert_eq!(
+            uncertain.quality,
+            Quality::Uncertain("last known value, timeout".to_string())
+        );
+
+        std::thread::sleep(Duration::from_millis(150));
+        let stale = last.update(failed());
+        assert_eq!(stale.value, Some(7));
+        assert_eq!(
+            stale.quality,
+            Quality::Bad("stale value, timeout".to_string())
+        );
+        assert!(last.get().unwrap().quality.is_bad());
+
+        last.clear();
+        assert!(last.get().is_none());
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn count_retries_and_chunks() {
+        let mut busy = true;
+        let server = MockServer::start(move |_, pdu| {
+            if std::mem::take(&mut busy) {
+                return Reply::Pdu(vec![pdu[0] | 0x80, 0x06]);
+            }
+            let (address, count) = read_range(pdu);
+            Reply::Pdu(registers_response(
+                pdu[0],
+                &vec![address; usize::from(count)],
+            ))
+        })
+        .await;
+        let mut client = ClientBuilder::new()
+            .retry_count(3)
+            .request_limits(RequestLimits::new().read_registers(10))
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+
+        // 第一次 ServerDeviceBusy 后重试
+        let reading = client.read_holding_registers_with_quality(0, 2).await;
+        assert_eq!(reading.quality, Quality::Good);
+        assert_eq!(reading.value, Some(vec![0, 0]));
+        assert_eq!(reading.attempts, 2);
+
+        // 拆分成 3 个请求
+        let reading = client.read_input_registers_with_quality(100, 25).await;
+        assert_eq!(reading.quality, Quality::Good);
+        assert_eq!(reading.value.unwrap().len(), 25);
+        assert_eq!(reading.attempts, 3);
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn failed_read_without_last_known_value() {
+        let server = MockServer::start(|_, pdu| Reply::Pdu(vec![pdu[0] | 0x80, 0x02])).await;
+        let mut client = ClientBuilder::new()
+            .retry_count(3)
+            .build_tcp(server.addr)
+            .await
+            .unwrap();
+
+        let reading = client.read_holding_registers_with_quality(0, 1).await;
+        assert!(reading.quality.is_bad());
+        assert!(reading.value.is_none());
+        // IllegalDataAddress 不重试
+        assert_eq!(reading.attempts, 1);
+
+        let mut last = LastKnown::new(Duration::from_secs(60));
+        let reading = last.update(reading);
+        assert!(reading.quality.is_bad());
+        assert!(reading.value.is_none());
+        assert!(last.get().is_none());
+    }
+}
@@ -0,0 +1,203 @@
+//! 结构体和寄存器块之间的映射.
+//!
+//! 实现了 [`ModbusRegisters`] 的结构体可以通过 [`ModbusRegisters::read`] 一次读取所有字段,
+//! 读请求由 [`ReadPlanner`] 合并成尽量少的请求. 通常使用 `derive` 特性提供的
+//! `#[derive(ModbusRegisters)]` 生成实现, 不需要手动维护地址表:
+//!
+//! ```ignore
+//! use async_modbus::ModbusRegisters;
+//!
+//! #[derive(ModbusRegisters)]
+//! struct Meter {
+//!     #[modbus(addr = 100, ty = "f32", order = "CDAB")]
+//!     voltage: f32,
+//!     #[modbus(addr = 102, table = "input")]
+//!     status: u16,
+//!     #[modbus(addr = 200, ty = "string", len = 8)]
+//!     serial: String,
+//!     #[modbus(addr = 0)]
+//!     running: bool,
+//! }
+//!
+//! let meter = Meter::read(&mut client).await?;
+//! meter.write(&mut client).await?;
+//! ```
+//!
+//! 字段属性:
+//! - addr: 第一个寄存器或线圈的地址, 必须设置
+//! - ty: 数据类型, `u16`, `i16`, `u32`, `i32`, `f32`, `u64`, `i64`, `f64`, `bool`, `string` 或 `bcd`,
+//!   默认使用字段的类型
+//! - order: 字节序, `ABCD`, `CDAB`, `BADC` 或 `DCBA`, 默认 `ABCD`
+//! - table: 数据表, `holding`, `input`, `coil` 或 `discrete`, 寄存器默认 `holding`, `bool` 默认 `coil`
+//! - len: `string` 和 `bcd` 占用的寄存器数量, `string` 最多 125 个, `bcd` 最多 5 个
+//! - swap_bytes: `string` 交换每个寄存器中的两个字符
+//! - read_only: 写入时跳过这个字段, 输入寄存器和离散输入总是只读的
+//! - skip: 不读写这个字段, 读取时使用 `Default::default()`
+
+use crate::error::{ModbusError, Result};
+use crate::planner::{ItemValue, ReadItem, ReadPlanner};
+use crate::{Reader, Writer};
+use async_trait::async_trait;
+
+/// 一个字段要写入的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteItem {
+    /// 写入从 `address` 开始的保持寄存器
+    Registers {
+        /// 第一个寄存器的地址
+        address: u16,
+        /// 要写入的值
+        values: Vec<u16>,
+    },
+
+    /// 写入 `address` 处的线圈
+    Coil {
+        /// 线圈的地址
+        address: u16,
+        /// 要写入的值
+        value: bool,
+    },
+}
+
+/// 结构体和寄存器块之间的映射
+#[async_trait]
+pub trait ModbusRegisters: Sized {
+    /// 返回所有要读取的字段, 顺序和 [`ModbusRegisters::from_values`] 的参数一致
+    fn read_items() -> Vec<ReadItem>;
+
+    /// 从读取的值创建结构体
+    ///
+    /// # 参数
+    /// - values: 按照 [`ModbusRegisters::read_items`] 的顺序排列的值
+    ///
+    /// # 返回
+    /// - 成功: 返回结构体
+    /// - 失败: 值的数量或类型不对, 或者解码失败
+    fn from_values(values: Vec<ItemValue>) -> Result<Self>;
+
+    /// 返回所有可写字段要写入的值
+    ///
+    /// # 返回
+    /// - 成功: 返回要写入的值
+    /// - 失败: 字段的值无法编码, 比如字符串太长
+    fn write_items(&self) -> Result<Vec<WriteItem>>;
+
+    /// 使用默认的 [`ReadPlanner`] 读取所有字段
+    ///
+    /// # 参数
+    /// - reader: 用来发送请求的客户端
+    ///
+    /// # 返回
+    /// - 成功: 返回读取的结构体
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Reader + Send + ?Sized,
+    {
+        Self::read_with(reader, &ReadPlanner::new()).await
+    }
+
+    /// 使用指定的 [`ReadPlanner`] 读取所有字段
+    ///
+    /// 设备支持的单次读取数量较少或者不允许读取未定义的地址时使用.
+    ///
+    /// # 参数
+    /// - reader: 用来发送请求的客户端
+    /// - planner: 读请求规划器
+    ///
+    /// # 返回
+    /// - 成功: 返回读取的结构体
+    /// - 失败: 返回 [`ModbusError`]
+    async fn read_with<R>(reader: &mut R, planner: &ReadPlanner) -> Result<Self>
+    where
+        R: Reader + Send + ?Sized,
+    {
+        let plan = planner.plan(&Self::read_items())?;
+        let values = plan.execute(reader).await?;
+        Self::from_values(values)
+    }
+
+    /// 写入所有可写字段
+    ///
+    /// 地址连续的寄存器合并成一个写多个寄存器请求 (0x10), 地址连续的线圈合并成一个写多个线圈请求 (0x0F).
+    /// 多个请求不是原子操作, 中途失败时前面的请求已经写入.
+    ///
+    /// # 参数
+    /// - writer: 用来发送请求的客户端
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`]
+    async fn write<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Writer + Send + ?Sized,
+        Self: Sync,
+    {
+        for write in merge_writes(self.write_items()?)? {
+            match write {
+                MergedWrite::Registers(address, values) => {
+                    writer.write_multiple_registers(address, &values).await?
+                }
+                MergedWrite::Coils(address, values) if values.len() == 1 => {
+                    writer.write_single_coil(address, values[0]).await?
+                }
+                MergedWrite::Coils(address, values) => {
+                    writer.write_multiple_coils(address, &values).await?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 合并后的写请求
+enum MergedWrite {
+    Registers(u16, Vec<u16>),
+    Coils(u16, Vec<bool>),
+}
+
+/// 把地址连续的写入合并, 地址重叠时返回错误
+fn merge_writes(mut items: Vec<WriteItem>) -> Result<Vec<MergedWrite>> {
+    items.sort_by_key(|item| match item {
+        WriteItem::Registers { address, .. } => (0, *address),
+        WriteItem::Coil { address, .. } => (1, *address),
+    });
+
+    let mut merged: Vec<MergedWrite> = Vec::new();
+    for item in items {
+        match (merged.last_mut(), item) {
+            (
+                Some(MergedWrite::Registers(start, values)),
+                WriteItem::Registers {
+                    address,
+                    values: next,
+                },
+            ) if u32::from(*start) + values.len() as u32 >= u32::from(address) => {
+                if u32::from(*start) + values.len() as u32 > u32::from(address) {
+                    return Err(overlap(address));
+                }
+                values.extend(next);
+            }
+            (Some(MergedWrite::Coils(start, values)), WriteItem::Coil { address, value })
+                if u32::from(*start) + values.len() as u32 >= u32::from(address) =>
+            {
+                if u32::from(*start) + values.len() as u32 > u32::from(address) {
+                    return Err(overlap(address));
+                }
+                values.push(value);
+            }
+            (_, WriteItem::Registers { address, values }) => {
+                merged.push(MergedWrite::Registers(address, values))
+            }
+            (_, WriteItem::Coil { address, value }) => {
+                merged.push(MergedWrite::Coils(address, vec![value]))
+            }
+        }
+    }
+
+    Ok(merged)
+}
+
+fn overlap(address: u16) -> ModbusError {
+    ModbusError::InvalidRequest(format!("Fields overlap at address {address}"))
+}
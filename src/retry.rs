@@ -0,0 +1,412 @@
+//! 重试策略.
+//!
+//! 请求失败后先由 [`RetryOn`] 判断错误是否可以重试, 再由重试策略决定是否再次发送, 以及再次发送之前需要等待多久.
+
+use crate::error::ModbusError;
+use std::collections::hash_map::RandomState;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_modbus::Exception;
+
+/// 可以重试的错误分类
+///
+/// 默认重试超时, 以及协议规定为暂时性的异常: `Acknowledge`, `ServerDeviceBusy`,
+/// `GatewayPathUnavailable` 和 `GatewayTargetDevice`.
+#[derive(Debug, Clone)]
+pub struct RetryOn {
+    timeout: bool,
+    exceptions: Vec<Exception>,
+    io_errors: Vec<io::ErrorKind>,
+    protocol: bool,
+}
+
+impl Default for RetryOn {
+    fn default() -> Self {
+        RetryOn {
+            timeout: true,
+            exceptions: vec![
+                Exception::Acknowledge,
+                Exception::ServerDeviceBusy,
+                Exception::GatewayPathUnavailable,
+                Exception::GatewayTargetDevice,
+            ],
+            io_errors: Vec::new(),
+            protocol: false,
+        }
+    }
+}
+
+impl RetryOn {
+    /// 只重试超时
+    pub fn timeout_only() -> Self {
+        RetryOn {
+            exceptions: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    /// 设置是否重试超时
+    pub fn timeout(mut self, retry: bool) -> Self {
+        self.timeout = retry;
+        self
+    }
+
+    /// 设置可以重试的异常码, 替换原来的设置
+    pub fn exceptions<I: IntoIterator<Item = Exception>>(mut self, exceptions: I) -> Self {
+        self.exceptions = exceptions.into_iter().collect();
+        self
+    }
+
+    /// 增加一个可以重试的异常码
+    pub fn exception(mut self, exception: Exception) -> Self {
+        if !self.exceptions.contains(&exception) {
+            self.exceptions.push(exception);
+        }
+        self
+    }
+
+    /// 增加一种可以重试的 I/O 错误
+    ///
+    /// tcp 开启自动重连时, I/O 错误总是先重连, 重连成功后再重试.
+    pub fn io_error(mut self, kind: io::ErrorKind) -> Self {
+        if !self.io_errors.contains(&kind) {
+            self.io_errors.push(kind);
+        }
+        self
+    }
+
+    /// 设置是否重试协议错误, 比如响应帧和请求不匹配
+    pub fn protocol(mut self, retry: bool) -> Self {
+        self.protocol = retry;
+        self
+    }
+
+    /// 判断错误是否可以重试
+    pub fn is_retryable(&self, error: &ModbusError) -> bool {
+        match error {
+            ModbusError::Timeout { .. } => self.timeout,
+            ModbusError::Exception(exception) => self.exceptions.contains(exception),
+            ModbusError::Io(e) => self.io_errors.contains(&e.kind()),
+            ModbusError::Protocol(_) => self.protocol,
+            ModbusError::InvalidRequest(_)
+            | ModbusError::QueueFull
+            | ModbusError::VerifyMismatch { .. }
+            | ModbusError::OutcomeUnknown(_)
+            | ModbusError::PartialWrite { .. } => false,
+        }
+    }
+}
+
+/// 重试策略
+pub trait RetryPolicy: Debug + Send + Sync {
+    /// 计算下一次发送之前需要等待的时间
+    ///
+    /// # 参数
+    /// - attempt: 已经发送的次数, 从 1 开始
+    ///
+    /// # 返回
+    /// - Some: 等待返回的时间后再次发送
+    /// - None: 不再重试
+    fn next_delay(&self, attempt: u32) -> Option<Duration>;
+}
+
+/// 固定间隔重试
+#[derive(Debug, Clone)]
+pub struct FixedRetry {
+    delay: Duration,
+    max_attempts: u32,
+}
+
+impl FixedRetry {
+    /// 创建固定间隔重试策略
+    ///
+    /// # 参数
+    /// - delay: 每次重试前等待的时间
+    /// - max_attempts: 最多发送的次数 (包含第一次)
+    pub fn new(delay: Duration, max_attempts: u32) -> Self {
+        FixedRetry {
+            delay,
+            max_attempts,
+        }
+    }
+}
+
+impl RetryPolicy for FixedRetry {
+    fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        (attempt < self.max_attempts).then_some(self.delay)
+    }
+}
+
+/// 线性增长间隔重试
+///
+/// 第 n 次重试前等待 `initial + step * (n - 1)`, 最长不超过 `max_delay`.
+#[derive(Debug, Clone)]
+pub struct LinearRetry {
+    initial: Duration,
+    step: Duration,
+    max_delay: Duration,
+    max_attempts: u32,
+}
+
+impl LinearRetry {
+    /// 创建线性增长间隔重试策略
+    ///
+    /// # 参数
+    /// - initial: 第一次重试前等待的时间
+    /// - step: 每次重试增加的等待时间
+    /// - max_delay: 最长等待时间
+    /// - max_attempts: 最多发送的次数 (包含第一次)
+    pub fn new(initial: Duration, step: Duration, max_delay: Duration, max_attempts: u32) -> Self {
+        LinearRetry {
+            initial,
+            step,
+            max_delay,
+            max_attempts,
+        }
+    }
+}
+
+impl RetryPolicy for LinearRetry {
+    fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = self
+            .initial
+            .saturating_add(self.step.saturating_mul(attempt - 1));
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// 带随机抖动的指数退避重试
+///
+/// 第 n 次重试前等待 `initial * multiplier^(n - 1)`, 最长不超过 `max_delay`,
+/// 然后在 `[delay * (1 - jitter), delay]` 之间随机取值, 避免多个客户端同时重试.
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    multiplier: f64,
+    max_delay: Duration,
+    max_attempts: u32,
+    jitter: f64,
+}
+
+impl Default for ExponentialBackoff {
+    /// 初始 50 毫秒, 每次翻倍, 最长 2 秒, 最多发送 5 次, 抖动 0.5
+    fn default() -> Self {
+        ExponentialBackoff {
+            initial: Duration::from_millis(50),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(2),
+            max_attempts: 5,
+            jitter: 0.5,
+        }
+    }
+}
+
+impl ExponentialBackoff {
+    /// 创建指数退避重试策略
+    ///
+    /// # 参数
+    /// - initial: 第一次重试前等待的时间
+    /// - max_delay: 最长等待时间
+    /// - max_attempts: 最多发送的次数 (包含第一次)
+    pub fn new(initial: Duration, max_delay: Duration, max_attempts: u32) -> Self {
+        ExponentialBackoff {
+            initial,
+            max_delay,
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    /// 设置增长倍数, 最小为 1.0, NaN 和无穷大使用默认值 2.0
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = if multiplier.is_finite() {
+            multiplier.max(1.0)
+        } else {
+            Self::default().multiplier
+        };
+        self
+    }
+
+    /// 设置抖动比例, 取值范围 0.0 ~ 1.0, 0.0 表示不抖动, NaN 和无穷大使用默认值 0.5
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = if jitter.is_finite() {
+            jitter.clamp(0.0, 1.0)
+        } else {
+            Self::default().jitter
+        };
+        self
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let exponent = i32::try_from(attempt - 1).unwrap_or(i32::MAX);
+        let delay = self.initial.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+        let delay = delay * (1.0 - self.jitter * random_unit());
+        // 溢出或者不是有效数字时使用最长等待时间
+        let delay = Duration::try_from_secs_f64(delay).unwrap_or(self.max_delay);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// 返回 [0, 1) 之间的随机数
+///
+/// 只用来打散重试和轮询时间, 不需要密码学安全的随机数.
+pub(crate) fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    hasher.write_u32(nanos);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn fixed_retry() {
+        let policy = FixedRetry::new(MS * 10, 3);
+        assert_eq!(policy.next_delay(1), Some(MS * 10));
+        assert_eq!(policy.next_delay(2), Some(MS * 10));
+        assert_eq!(policy.next_delay(3), None);
+    }
+
+    #[test]
+    fn linear_retry() {
+        let policy = LinearRetry::new(MS * 10, MS * 20, MS * 45, 5);
+        assert_eq!(policy.next_delay(1), Some(MS * 10));
+        assert_eq!(policy.next_delay(2), Some(MS * 30));
+        assert_eq!(policy.next_delay(3), Some(MS * 45));
+        assert_eq!(policy.next_delay(4), Some(MS * 45));
+        assert_eq!(policy.next_delay(5), None);
+    }
+
+    #[test]
+    fn exponential_backoff_without_jitter() {
+        let policy = ExponentialBackoff::new(MS * 10, MS * 50, 6).jitter(0.0);
+        let delays: Vec<_> = (1..=6).map(|attempt| policy.next_delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [
+                Some(MS * 10),
+                Some(MS * 20),
+                Some(MS * 40),
+                Some(MS * 50),
+                Some(MS * 50),
+                None
+            ]
+        );
+
+        let policy = ExponentialBackoff::new(MS * 10, MS * 100, 3)
+            .multiplier(3.0)
+            .jitter(0.0);
+        assert_eq!(policy.next_delay(2), Some(MS * 30));
+    }
+
+    #[test]
+    fn exponential_backoff_jitter_bounds() {
+        let policy = ExponentialBackoff::new(MS * 100, Duration::from_secs(1), 10).jitter(0.25);
+        for attempt in 1..10 {
+            let ceiling = (MS * 100 * 2u32.pow(attempt - 1)).min(Duration::from_secs(1));
+            for _ in 0..50 {
+                let delay = policy.next_delay(attempt).unwrap();
+                assert!(delay <= ceiling, "{delay:?} > {ceiling:?}");
+                assert!(
+                    delay >= ceiling.mul_f64(0.75),
+                    "{delay:?} < 0.75 * {ceiling:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clamp_settings() {
+        let policy = ExponentialBackoff::new(MS * 10, MS * 100, 3)
+            .multiplier(0.5)
+            .jitter(2.0);
+        // 倍数最小为 1.0, 抖动最大为 1.0
+        for _ in 0..50 {
+            assert!(policy.next_delay(2).unwrap() <= MS * 10);
+        }
+    }
+
+    #[test]
+    fn reject_non_finite_settings() {
+        let policy = ExponentialBackoff::new(MS * 10, MS * 100, 3)
+            .multiplier(f64::INFINITY)
+            .jitter(f64::NAN);
+        assert_eq!(policy.multiplier, 2.0);
+        assert_eq!(policy.jitter, 0.5);
+        assert!(policy.next_delay(2).unwrap() <= MS * 20);
+
+        let policy = ExponentialBackoff::new(MS * 10, MS * 100, 3)
+            .multiplier(f64::NAN)
+            .jitter(f64::NEG_INFINITY);
+        assert_eq!(policy.multiplier, 2.0);
+        assert_eq!(policy.jitter, 0.5);
+    }
+
+    #[test]
+    fn saturate_to_max_delay() {
+        let policy = ExponentialBackoff::new(Duration::MAX, MS * 100, u32::MAX)
+            .multiplier(f64::MAX)
+            .jitter(0.0);
+        assert_eq!(policy.next_delay(1), Some(MS * 100));
+        assert_eq!(policy.next_delay(u32::MAX - 1), Some(MS * 100));
+    }
+
+    #[test]
+    fn random_unit_range() {
+        for _ in 0..1000 {
+            let value = random_unit();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn retry_on_classification() {
+        let timeout = ModbusError::Timeout { attempts: 1 };
+        let busy = ModbusError::Exception(Exception::ServerDeviceBusy);
+        let illegal = ModbusError::Exception(Exception::IllegalDataAddress);
+        let reset = ModbusError::Io(io::Error::from(io::ErrorKind::ConnectionReset));
+
+        let retry_on = RetryOn::default();
+        assert!(retry_on.is_retryable(&timeout));
+        assert!(retry_on.is_retryable(&busy));
+        assert!(!retry_on.is_retryable(&illegal));
+        assert!(!retry_on.is_retryable(&reset));
+        assert!(
+            !retry_on.is_retryable(&ModbusError::OutcomeUnknown(Box::new(
+                ModbusError::Timeout { attempts: 1 }
+            )))
+        );
+
+        assert!(!retry_on.is_retryable(&ModbusError::PartialWrite {
+            address: 0,
+            written: 1,
+            source: Box::new(ModbusError::Timeout { attempts: 1 }),
+        }));
+
+        let retry_on = RetryOn::timeout_only()
+            .exception(Exception::IllegalDataAddress)
+            .io_error(io::ErrorKind::ConnectionReset);
+        assert!(!retry_on.is_retryable(&busy));
+        assert!(retry_on.is_retryable(&illegal));
+        assert!(retry_on.is_retryable(&reset));
+        assert!(!retry_on.timeout(false).is_retryable(&timeout));
+    }
+}
@@ -3,6 +3,7 @@
 use crate::Callback;
 use anyhow::Result;
 use std::io;
+#[cfg(feature = "modbus_tcp_server")]
 use std::net::SocketAddr;
 
 /// 创建并启动新的 rtu 服务端
@@ -75,3 +76,45 @@ where
     server.serve(&on_connected, on_process_error).await?;
     Ok(())
 }
+
+/// 按照连接地址创建并启动新的服务端
+///
+/// tcp 地址会监听解析出的第一个地址, rtu 地址会按照参数打开串口, 从机 id 取地址中的 unit 参数, 默认为 1.
+/// 地址格式见 [`crate::endpoint`].
+///
+/// # 参数
+/// - url: 连接地址, 比如 `tcp://0.0.0.0:502?unit=1` 或 `rtu:///dev/ttyUSB0?baud=19200&unit=7`
+/// - on_call_back: 收到客户度消息后的回调
+/// - on_process_error: 处理错误的回调, 只有 tcp 服务端会使用
+///
+/// # 返回
+/// - 成功: 返回空
+/// - 失败: 返回错误信息
+#[cfg_attr(not(feature = "modbus_tcp_server"), allow(unused_variables))]
+pub async fn new_start_server<OnProcessError>(
+    url: &str,
+    on_call_back: Box<dyn Callback>,
+    on_process_error: OnProcessError,
+) -> Result<()>
+where
+    OnProcessError: FnOnce(io::Error) + Clone + Send + 'static,
+{
+    use crate::endpoint::{Endpoint, Transport};
+
+    let endpoint: Endpoint = url.parse()?;
+    let slave_id = endpoint.unit.unwrap_or(1);
+
+    match &endpoint.transport {
+        #[cfg(feature = "modbus_tcp_server")]
+        Transport::Tcp { .. } => {
+            let socket_addr: SocketAddr = endpoint.resolve().await?[0];
+            new_start_tcp_server(socket_addr, slave_id, on_call_back, on_process_error).await
+        }
+        #[cfg(feature = "modbus_rtu_server")]
+        Transport::Rtu(serial) => {
+            new_start_tru_server(serial.open()?, slave_id, on_call_back).await
+        }
+        #[allow(unreachable_patterns)]
+        _ => anyhow::bail!("{endpoint} is not supported by the enabled features"),
+    }
+}
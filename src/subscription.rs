@@ -0,0 +1,326 @@
+//! 变化订阅.
+//!
+//! 在 [`Poller`] 的轮询结果上过滤出变化的数据点, 通过 [`ChangeStream`] 按照异步 `Stream` 的方式返回.
+//! - 数值: 和上一次上报的值比较, 超过死区 ([`Deadband`]) 才上报
+//! - 布尔值: 按照边沿 ([`Edge`]) 上报
+//! - 字符串: 变化就上报
+//!
+//! 订阅条件按照数据点的名字匹配, 不同轮询组中同名的数据点分别记录状态, 互不影响.
+//! 读取失败的轮询结果被忽略, 数据点保持上一次的值.
+
+use crate::poller::{Poller, Sample};
+use crate::tag::Value;
+use futures_core::Stream;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+/// 默认每个订阅缓存的变化事件数量
+pub const DEFAULT_EVENT_CAPACITY: usize = 256;
+
+/// 数值的死区
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Deadband {
+    /// 值不同就上报
+    #[default]
+    None,
+    /// 和上一次上报的值相差超过指定的绝对值才上报
+    Absolute(f64),
+    /// 和上一次上报的值相差超过上一次上报值的百分比才上报, 上一次上报的值为 0 时变化就上报
+    Percent(f64),
+}
+
+impl Deadband {
+    fn exceeded(self, previous: f64, value: f64) -> bool {
+        let delta = (value - previous).abs();
+        match self {
+            Deadband::None => value != previous,
+            Deadband::Absolute(limit) => delta > limit,
+            Deadband::Percent(_) if previous == 0.0 => value != previous,
+            Deadband::Percent(percent) => delta > previous.abs() * percent / 100.0,
+        }
+    }
+}
+
+/// 布尔值上报的边沿
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Edge {
+    /// 上升沿和下降沿都上报
+    #[default]
+    Both,
+    /// 只上报 `false` 到 `true`
+    Rising,
+    /// 只上报 `true` 到 `false`
+    Falling,
+}
+
+/// 一个数据点的订阅条件
+#[derive(Debug, Clone)]
+pub struct ChangeFilter {
+    tag: String,
+    deadband: Deadband,
+    edge: Edge,
+    initial: bool,
+}
+
+impl ChangeFilter {
+    /// 订阅数据点, 默认没有死区, 上报所有边沿, 并且上报第一次读取的值
+    pub fn new(tag: impl Into<String>) -> Self {
+        ChangeFilter {
+            tag: tag.into(),
+            deadband: Deadband::None,
+            edge: Edge::Both,
+            initial: true,
+        }
+    }
+
+    /// 设置数值的死区
+    pub fn deadband(mut self, deadband: Deadband) -> Self {
+        self.deadband = deadband;
+        self
+    }
+
+    /// 设置布尔值上报的边沿
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// 设置是否上报第一次读取的值
+    pub fn initial(mut self, initial: bool) -> Self {
+        self.initial = initial;
+        self
+    }
+}
+
+/// 变化事件
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// 数据点的名字
+    pub tag: String,
+    /// 轮询组的名字
+    pub group: Arc<str>,
+    /// 读取的时间
+    pub timestamp: SystemTime,
+    /// 上一次上报的值, 第一次上报时为 None
+    pub previous: Option<Value>,
+    /// 当前的值
+    pub value: Value,
+}
+
+/// 一个轮询组中一个数据点的状态
+#[derive(Default)]
+struct TagState {
+    /// 上一次读取的值, 用来判断边沿
+    sampled: Option<Value>,
+    /// 上一次上报的值, 用来判断死区
+    reported: Option<Value>,
+}
+
+impl TagState {
+    /// 更新状态, 需要上报时返回上一次上报的值
+    fn update(&mut self, filter: &ChangeFilter, value: &Value) -> Option<Option<Value>> {
+        let sampled = self.sampled.replace(value.clone());
+        let changed = match (&self.reported, value) {
+            (None, _) if !filter.initial => {
+                // 不上报第一次读取的值, 但是作为后续比较的基准
+                self.reported = Some(value.clone());
+                return None;
+            }
+            (None, _) => true,
+            (Some(_), Value::Bool(value)) => match (sampled, filter.edge) {
+                (Some(Value::Bool(previous)), Edge::Both) => previous != *value,
+                (Some(Value::Bool(previous)), Edge::Rising) => !previous && *value,
+                (Some(Value::Bool(previous)), Edge::Falling) => previous && !*value,
+                _ => true,
+            },
+            (Some(Value::String(previous)), Value::String(value)) => previous != value,
+            (Some(previous), value) => match (previous.as_f64(), value.as_f64()) {
+                (Some(previous), Some(value)) => filter.deadband.exceeded(previous, value),
+                _ => true,
+            },
+        };
+        if !changed {
+            return None;
+        }
+        Some(self.reported.replace(value.clone()))
+    }
+}
+
+/// 变化事件流
+///
+/// 释放后停止订阅, 轮询引擎释放后事件流结束.
+pub struct ChangeStream {
+    events: mpsc::Receiver<ChangeEvent>,
+    task: JoinHandle<()>,
+}
+
+impl ChangeStream {
+    /// 订阅轮询引擎的结果, 每个订阅最多缓存 [`DEFAULT_EVENT_CAPACITY`] 个事件
+    ///
+    /// 必须在 tokio 运行时中调用. 事件来不及处理时暂停接收轮询结果, 落后太多时丢失最早的轮询结果.
+    pub fn new(poller: &Poller, filters: Vec<ChangeFilter>) -> ChangeStream {
+        Self::from_receiver(poller.subscribe(), filters)
+    }
+
+    /// 从 [`Poller::subscribe`] 返回的接收端创建事件流
+    pub fn from_receiver(
+        samples: broadcast::Receiver<Arc<Sample>>,
+        filters: Vec<ChangeFilter>,
+    ) -> ChangeStream {
+        let (sender, events) = mpsc::channel(DEFAULT_EVENT_CAPACITY);
+        let filters = filters
+            .into_iter()
+            .map(|filter| (filter.tag.clone(), filter))
+            .collect();
+        let task = tokio::spawn(run(samples, filters, sender));
+        ChangeStream { events, task }
+    }
+}
+
+impl Stream for ChangeStream {
+    type Item = ChangeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ChangeEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for ChangeStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// 后台任务, 过滤轮询结果并发送变化事件
+async fn run(
+    mut samples: broadcast::Receiver<Arc<Sample>>,
+    filters: HashMap<String, ChangeFilter>,
+    events: mpsc::Sender<ChangeEvent>,
+) {
+    let mut states: HashMap<(Arc<str>, String), TagState> = HashMap::new();
+    loop {
+        let sample = match samples.recv().await {
+            Ok(sample) => sample,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                log::warn!("SUBSCRIPTION: Skipped {skipped} samples");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let Ok(values) = &sample.result else {
+            continue;
+        };
+
+        for (tag, value) in values {
+            let Some(filter) = filters.get(tag) else {
+                continue;
+            };
+            let state = states
+                .entry((Arc::clone(&sample.group), tag.clone()))
+                .or_default();
+            let Some(previous) = state.update(filter, value) else {
+                continue;
+            };
+            let event = ChangeEvent {
+                tag: tag.clone(),
+                group: Arc::clone(&sample.group),
+                timestamp: sample.timestamp,
+                previous,
+                value: value.clone(),
+            };
+            if events.send(event).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::poll_fn;
+    use std::time::Duration;
+
+    fn sample(group: &str, tag: &str, value: Value) -> Arc<Sample> {
+        Arc::new(Sample {
+            group: Arc::from(group),
+            timestamp: SystemTime::now(),
+            elapsed: Duration::ZERO,
+            result: Ok(vec![(tag.to_string(), value)]),
+        })
+    }
+
+    async fn next(stream: &mut ChangeStream) -> Option<ChangeEvent> {
+        let next = poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx));
+        tokio::time::timeout(Duration::from_millis(50), next)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn same_tag_in_different_groups() {
+        let (samples, receiver) = broadcast::channel(16);
+        let mut stream = ChangeStream::from_receiver(receiver, vec![ChangeFilter::new("status")]);
+        for _ in 0..2 {
+            samples
+                .send(sample("fast", "status", Value::UInt(1)))
+                .unwrap();
+            samples
+                .send(sample("slow", "status", Value::UInt(100)))
+                .unwrap();
+        }
+
+        let event = next(&mut stream).await.unwrap();
+        assert_eq!((&*event.group, event.value), ("fast", Value::UInt(1)));
+        let event = next(&mut stream).await.unwrap();
+        assert_eq!((&*event.group, event.value), ("slow", Value::UInt(100)));
+        assert!(next(&mut stream).await.is_none());
+
+        samples
+            .send(sample("slow", "status", Value::UInt(101)))
+            .unwrap();
+        let event = next(&mut stream).await.unwrap();
+        assert_eq!(event.previous, Some(Value::UInt(100)));
+        assert_eq!(event.value, Value::UInt(101));
+    }
+
+    #[tokio::test]
+    async fn deadband_and_edges() {
+        let (samples, receiver) = broadcast::channel(16);
+        let filters = vec![
+            ChangeFilter::new("voltage").deadband(Deadband::Absolute(1.0)),
+            ChangeFilter::new("alarm").edge(Edge::Rising).initial(false),
+        ];
+        let mut stream = ChangeStream::from_receiver(receiver, filters);
+        for value in [230.0, 230.5, 231.5] {
+            samples
+                .send(sample("meter", "voltage", Value::Float(value)))
+                .unwrap();
+        }
+        for value in [false, true, false, true] {
+            samples
+                .send(sample("meter", "alarm", Value::Bool(value)))
+                .unwrap();
+        }
+
+        let mut events = Vec::new();
+        while let Some(event) = next(&mut stream).await {
+            events.push((event.tag, event.value));
+        }
+        assert_eq!(
+            events,
+            [
+                ("voltage".to_string(), Value::Float(230.0)),
+                ("voltage".to_string(), Value::Float(231.5)),
+                ("alarm".to_string(), Value::Bool(true)),
+                ("alarm".to_string(), Value::Bool(true)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,502 @@
+//! 数据点 (tag) 定义.
+//!
+//! 一个 [`Tag`] 描述设备上的一个有名字的数据: 所在的数据表, 地址, 数据类型, 字节序, 缩放和访问权限.
+//! 读取时原始值按照 `原始值 * scale + offset` 转换成工程值, 写入时反向转换.
+
+use crate::codec::{self, ByteOrder, StringFormat};
+use crate::error::{ModbusError, Result};
+use crate::planner::{ItemValue, ReadItem, Table, MAX_READ_REGISTERS};
+use crate::registers::WriteItem;
+use std::fmt;
+
+/// 数据类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DataType {
+    /// 线圈或离散输入
+    Bool,
+    /// 无符号 16 位整数, 1 个寄存器
+    U16,
+    /// 有符号 16 位整数, 1 个寄存器
+    I16,
+    /// 无符号 32 位整数, 2 个寄存器
+    U32,
+    /// 有符号 32 位整数, 2 个寄存器
+    I32,
+    /// 32 位浮点数, 2 个寄存器
+    F32,
+    /// 无符号 64 位整数, 4 个寄存器
+    U64,
+    /// 有符号 64 位整数, 4 个寄存器
+    I64,
+    /// 64 位浮点数, 4 个寄存器
+    F64,
+    /// 字符串, 寄存器数量由 [`Tag::length`] 指定
+    String,
+    /// BCD 码, 寄存器数量由 [`Tag::length`] 指定
+    Bcd,
+}
+
+impl DataType {
+    /// 固定占用的寄存器数量, 字符串和 BCD 码返回 None
+    pub fn registers(self) -> Option<u16> {
+        match self {
+            DataType::Bool | DataType::U16 | DataType::I16 => Some(1),
+            DataType::U32 | DataType::I32 | DataType::F32 => Some(2),
+            DataType::U64 | DataType::I64 | DataType::F64 => Some(4),
+            DataType::String | DataType::Bcd => None,
+        }
+    }
+
+    /// 是否是数值类型
+    pub fn is_numeric(self) -> bool {
+        !matches!(self, DataType::Bool | DataType::String)
+    }
+}
+
+/// 访问权限
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Access {
+    /// 只读
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "r", alias = "read"))]
+    Read,
+    /// 读写
+    #[cfg_attr(feature = "serde", serde(rename = "rw", alias = "read_write"))]
+    ReadWrite,
+    /// 只写, 比如命令寄存器
+    #[cfg_attr(feature = "serde", serde(rename = "w", alias = "write"))]
+    Write,
+}
+
+impl Access {
+    /// 是否可读
+    pub fn is_readable(self) -> bool {
+        matches!(self, Access::Read | Access::ReadWrite)
+    }
+
+    /// 是否可写
+    pub fn is_writable(self) -> bool {
+        matches!(self, Access::Write | Access::ReadWrite)
+    }
+}
+
+/// 数据点的值
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(untagged)
+)]
+pub enum Value {
+    /// 线圈或离散输入的值
+    Bool(bool),
+    /// 没有缩放的有符号整数
+    Int(i64),
+    /// 没有缩放的无符号整数
+    UInt(u64),
+    /// 浮点数或者缩放后的值
+    Float(f64),
+    /// 字符串
+    String(String),
+}
+
+impl Value {
+    /// 转换成 f64, 布尔值转换成 0 或 1, 字符串返回 None
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Bool(value) => Some(f64::from(u8::from(*value))),
+            Value::Int(value) => Some(*value as f64),
+            Value::UInt(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            Value::String(_) => None,
+        }
+    }
+
+    /// 返回布尔值, 其他类型返回 None
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// 返回字符串, 其他类型返回 None
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Int(value) => write!(f, "{value}"),
+            Value::UInt(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value}"),
+            Value::String(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::UInt(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+/// 数据点
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct Tag {
+    /// 名字, 同一个设备中唯一
+    pub name: String,
+    /// 数据表
+    pub table: Table,
+    /// 第一个寄存器或线圈的地址
+    pub address: u16,
+    /// 数据类型
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub data_type: DataType,
+    /// 字符串和 BCD 码占用的寄存器数量
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub length: Option<u16>,
+    /// 多寄存器数值的字节序
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub order: ByteOrder,
+    /// 字符串是否交换每个寄存器中的两个字符
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub swap_bytes: bool,
+    /// 缩放系数
+    #[cfg_attr(feature = "serde", serde(default = "default_scale"))]
+    pub scale: f64,
+    /// 偏移量
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub offset: f64,
+    /// 工程单位, 比如 `V` 或 `kWh`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unit: Option<String>,
+    /// 访问权限
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub access: Access,
+    /// 说明
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub description: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl Tag {
+    /// 创建只读, 没有缩放的数据点
+    pub fn new(name: impl Into<String>, table: Table, address: u16, data_type: DataType) -> Self {
+        Tag {
+            name: name.into(),
+            table,
+            address,
+            data_type,
+            length: None,
+            order: ByteOrder::default(),
+            swap_bytes: false,
+            scale: 1.0,
+            offset: 0.0,
+            unit: None,
+            access: Access::default(),
+            description: None,
+        }
+    }
+
+    /// 检查定义是否合法
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 数据类型和数据表不匹配, 缺少长度或者长度超过限制, 地址越界, 缩放系数为 0 或者只读数据表设置了可写
+    pub fn validate(&self) -> Result<()> {
+        if self.table.is_bit() != (self.data_type == DataType::Bool) {
+            return Err(self.invalid("bool must be used with coil or discrete tables"));
+        }
+        if self.access.is_writable()
+            && matches!(self.table, Table::DiscreteInput | Table::InputRegister)
+        {
+            return Err(self.invalid("input tables are read only"));
+        }
+        let count = match (self.data_type.registers(), self.length) {
+            (Some(_), Some(_)) => return Err(self.invalid("length is only used by string and bcd")),
+            (Some(count), None) => count,
+            (None, None) | (None, Some(0)) => {
+                return Err(self.invalid("string and bcd need a length"))
+            }
+            (None, Some(length)) if self.data_type == DataType::Bcd && length > 5 => {
+                return Err(self.invalid("bcd can not be longer than 5 registers"))
+            }
+            (None, Some(length)) if length > MAX_READ_REGISTERS => {
+                return Err(self.invalid("string can not be longer than 125 registers"))
+            }
+            (None, Some(length)) => length,
+        };
+        if u32::from(self.address) + u32::from(count) > 0x1_0000 {
+            return Err(self.invalid("exceeds the address space"));
+        }
+        if !self.scale.is_finite() || self.scale == 0.0 || !self.offset.is_finite() {
+            return Err(self.invalid("scale must be finite and non-zero"));
+        }
+        Ok(())
+    }
+
+    /// 占用的寄存器或线圈数量
+    pub fn count(&self) -> u16 {
+        self.data_type
+            .registers()
+            .unwrap_or_else(|| self.length.unwrap_or(1))
+    }
+
+    /// 返回读取这个数据点的读取项
+    pub fn read_item(&self) -> ReadItem {
+        ReadItem::new(self.table, self.address, self.count())
+    }
+
+    /// 把读取的原始值转换成工程值
+    ///
+    /// # 返回
+    /// - 成功: 返回工程值
+    /// - 失败: 原始值的类型或长度不对
+    pub fn decode(&self, value: &ItemValue) -> Result<Value> {
+        if self.data_type == DataType::Bool {
+            return value
+                .as_bits()
+                .and_then(|bits| bits.first().copied())
+                .map(Value::Bool)
+                .ok_or_else(|| self.protocol("expected a bit"));
+        }
+
+        let registers = value
+            .as_registers()
+            .ok_or_else(|| self.protocol("expected registers"))?;
+        // 寄存器数量和内容由从机决定, 解码失败是协议错误
+        let value = self.decode_registers(registers).map_err(codec::protocol)?;
+        if matches!(value, Value::String(_)) {
+            return Ok(value);
+        }
+
+        if self.is_scaled() {
+            // 上面只会返回数值
+            let raw = value.as_f64().unwrap_or_default();
+            return Ok(Value::Float(raw * self.scale + self.offset));
+        }
+        Ok(value)
+    }
+
+    /// 把工程值转换成要写入的原始值
+    ///
+    /// # 返回
+    /// - 成功: 返回要写入的值
+    /// - 失败: 值的类型不对或者超出数据类型的范围
+    pub fn encode(&self, value: &Value) -> Result<WriteItem> {
+        let address = self.address;
+        let order = self.order;
+        let values = match self.data_type {
+            DataType::Bool => {
+                let value = match value {
+                    Value::Bool(value) => *value,
+                    Value::Int(0) | Value::UInt(0) => false,
+                    Value::Int(1) | Value::UInt(1) => true,
+                    _ => return Err(self.invalid_value(value)),
+                };
+                return Ok(WriteItem::Coil { address, value });
+            }
+            DataType::String => {
+                let value = value.as_str().ok_or_else(|| self.invalid_value(value))?;
+                let format = StringFormat::new().swap_bytes(self.swap_bytes);
+                codec::encode_string(value, self.count(), format)?
+            }
+            DataType::F32 => codec::encode(&(self.raw_float(value)? as f32), order),
+            DataType::F64 => codec::encode(&self.raw_float(value)?, order),
+            DataType::U16 => codec::encode(&(self.raw_integer(value, u16::MIN, u16::MAX)?), order),
+            DataType::I16 => codec::encode(&(self.raw_integer(value, i16::MIN, i16::MAX)?), order),
+            DataType::U32 => codec::encode(&(self.raw_integer(value, u32::MIN, u32::MAX)?), order),
+            DataType::I32 => codec::encode(&(self.raw_integer(value, i32::MIN, i32::MAX)?), order),
+            DataType::U64 => codec::encode(&(self.raw_integer(value, u64::MIN, u64::MAX)?), order),
+            DataType::I64 => codec::encode(&(self.raw_integer(value, i64::MIN, i64::MAX)?), order),
+            DataType::Bcd => {
+                let value = self.raw_integer(value, u64::MIN, u64::MAX)?;
+                codec::encode_bcd(value, self.count(), order)?
+            }
+        };
+        Ok(WriteItem::Registers { address, values })
+    }
+
+    /// 按照数据类型解码寄存器, 不缩放
+    fn decode_registers(&self, registers: &[u16]) -> Result<Value> {
+        let order = self.order;
+        let value = match self.data_type {
+            DataType::U16 => Value::UInt(codec::decode::<u16>(registers, order)?.into()),
+            DataType::I16 => Value::Int(codec::decode::<i16>(registers, order)?.into()),
+            DataType::U32 => Value::UInt(codec::decode::<u32>(registers, order)?.into()),
+            DataType::I32 => Value::Int(codec::decode::<i32>(registers, order)?.into()),
+            DataType::F32 => Value::Float(codec::decode::<f32>(registers, order)?.into()),
+            DataType::U64 => Value::UInt(codec::decode::<u64>(registers, order)?),
+            DataType::I64 => Value::Int(codec::decode::<i64>(registers, order)?),
+            DataType::F64 => Value::Float(codec::decode::<f64>(registers, order)?),
+            DataType::Bcd => Value::UInt(codec::decode_bcd(registers, order)?),
+            DataType::String => {
+                let format = StringFormat::new().swap_bytes(self.swap_bytes);
+                Value::String(codec::decode_string(registers, format)?)
+            }
+            DataType::Bool => unreachable!(),
+        };
+        Ok(value)
+    }
+
+    fn is_scaled(&self) -> bool {
+        self.scale != 1.0 || self.offset != 0.0
+    }
+
+    /// 反向缩放后的浮点数
+    fn raw_float(&self, value: &Value) -> Result<f64> {
+        let value = match value {
+            Value::Bool(_) | Value::String(_) => return Err(self.invalid_value(value)),
+            value => value.as_f64().unwrap_or_default(),
+        };
+        Ok((value - self.offset) / self.scale)
+    }
+
+    /// 反向缩放并四舍五入后的整数
+    fn raw_integer<T>(&self, value: &Value, min: T, max: T) -> Result<T>
+    where
+        T: TryFrom<i128> + Into<i128>,
+    {
+        let raw: i128 = match value {
+            // 没有缩放的整数直接转换, 避免 u64 和 i64 经过 f64 损失精度
+            Value::Int(value) if !self.is_scaled() => (*value).into(),
+            Value::UInt(value) if !self.is_scaled() => (*value).into(),
+            _ => {
+                let raw = self.raw_float(value)?.round();
+                if !raw.is_finite() {
+                    return Err(self.invalid_value(value));
+                }
+                // 超出 i128 范围时饱和, 下面的范围检查会拒绝
+                raw as i128
+            }
+        };
+        if raw < min.into() || raw > max.into() {
+            return Err(self.invalid_value(value));
+        }
+        T::try_from(raw).map_err(|_| self.invalid_value(value))
+    }
+
+    fn invalid(&self, reason: &str) -> ModbusError {
+        ModbusError::InvalidRequest(format!("tag `{}`: {reason}", self.name))
+    }
+
+    fn invalid_value(&self, value: &Value) -> ModbusError {
+        self.invalid(&format!(
+            "{value:?} can not be written as {:?}",
+            self.data_type
+        ))
+    }
+
+    fn protocol(&self, reason: &str) -> ModbusError {
+        ModbusError::Protocol(format!("tag `{}`: {reason}", self.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_tag(length: u16) -> Tag {
+        Tag {
+            length: Some(length),
+            ..Tag::new("serial", Table::HoldingRegister, 200, DataType::String)
+        }
+    }
+
+    #[test]
+    fn validate_length() {
+        assert!(string_tag(125).validate().is_ok());
+        assert!(string_tag(126).validate().is_err());
+        assert!(string_tag(0).validate().is_err());
+
+        let bcd = Tag {
+            length: Some(6),
+            ..Tag::new("energy", Table::HoldingRegister, 0, DataType::Bcd)
+        };
+        assert!(bcd.validate().is_err());
+    }
+
+    #[test]
+    fn decode_errors_are_protocol_errors() {
+        let tag = Tag::new("voltage", Table::HoldingRegister, 0, DataType::F32);
+        let error = tag.decode(&ItemValue::Registers(vec![1])).unwrap_err();
+        assert!(matches!(error, ModbusError::Protocol(_)), "{error:?}");
+
+        let tag = Tag::new("energy", Table::HoldingRegister, 0, DataType::Bcd);
+        let error = tag.decode(&ItemValue::Registers(vec![0x00AF])).unwrap_err();
+        assert!(matches!(error, ModbusError::Protocol(_)), "{error:?}");
+
+        let error = string_tag(1)
+            .decode(&ItemValue::Registers(vec![0xFFFE]))
+            .unwrap_err();
+        assert!(matches!(error, ModbusError::Protocol(_)), "{error:?}");
+    }
+
+    #[test]
+    fn decode_scaled() {
+        let tag = Tag {
+            scale: 0.1,
+            offset: -40.0,
+            ..Tag::new("temperature", Table::InputRegister, 0, DataType::I16)
+        };
+        let value = tag.decode(&ItemValue::Registers(vec![650])).unwrap();
+        assert_eq!(value, Value::Float(650.0 * 0.1 - 40.0));
+
+        let value = string_tag(2)
+            .decode(&ItemValue::Registers(vec![0x4142, 0x4300]))
+            .unwrap();
+        assert_eq!(value, Value::String("ABC".to_string()));
+    }
+}
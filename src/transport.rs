@@ -0,0 +1,1240 @@
+//! tcp 和 rtu 的传输层.
+//!
+//! 代替 tokio-modbus 自带的传输层, 通过 `client::Context::from` 接入. 请求超时后, 从机可能在下一个请求发出后才返回
+//! 上一个请求的响应, 这里保证迟到的响应不会被当作下一个请求的响应:
+//! - tcp: 按照 MBAP 头中的事务 id 匹配响应, 丢弃事务 id 不同的响应, 并且始终保持帧的边界
+//! - rtu: 上一个请求没有正常完成时, 发送下一个请求之前先丢弃线路上残留的数据;
+//!   丢弃从机 id, 功能码或者 CRC 不匹配的帧
+//!
+//! rtu 的帧之间至少间隔 3.5 个字符的静默时间. 广播 (从机 id 0) 只支持写请求, 从机不响应广播,
+//! 传输层发送完成后直接返回, 记录转换延时 (turnaround delay) 结束的时间; `Client` 在请求的超时时间之外
+//! 等待转换延时结束后再返回成功, 下一个请求也不会在这之前发送.
+//!
+//! 读写都可以在任意位置被超时取消, 没有发送完的请求和没有接收完的响应留在缓冲区中, 下一次请求时继续处理.
+//!
+//! tcp 还提供流水线模式 [`Pipeline`], 由单独的任务收发数据, 多个请求可以同时等待响应.
+
+use crate::error::ModbusError;
+#[cfg(feature = "modbus_tcp_client")]
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::io::{Error, ErrorKind};
+#[cfg(feature = "modbus_tcp_client")]
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+#[cfg(any(feature = "modbus_tcp_client", feature = "modbus_rtu_client"))]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "modbus_rtu_client")]
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "modbus_tcp_client")]
+use tokio::sync::{mpsc, oneshot, Semaphore};
+#[cfg(feature = "modbus_tcp_client")]
+use tokio::task::JoinHandle;
+#[cfg(feature = "modbus_rtu_client")]
+use tokio::time::{sleep_until, timeout_at, Instant};
+use tokio_modbus::bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_modbus::client::Client;
+use tokio_modbus::prelude::*;
+use tokio_modbus::slave::SlaveContext;
+
+/// rtu 默认丢弃残留数据时, 线路上持续多久没有数据才认为已经清空
+#[cfg(feature = "modbus_rtu_client")]
+pub(crate) const DEFAULT_RTU_DRAIN: Duration = Duration::from_millis(50);
+
+/// rtu 广播之后默认等待从机处理的时间
+#[cfg(feature = "modbus_rtu_client")]
+pub(crate) const DEFAULT_RTU_TURNAROUND: Duration = Duration::from_millis(100);
+
+/// 流水线退回到一问一答后, 连续收到多少个响应再重新启用流水线, 每次退回后加倍
+#[cfg(feature = "modbus_tcp_client")]
+const PIPELINE_RECOVER_RESPONSES: u32 = 100;
+
+/// rtu 帧的最大长度
+#[cfg(feature = "modbus_rtu_client")]
+const RTU_MAX_FRAME_LEN: usize = 256;
+
+/// 把请求编码成 PDU
+fn encode_request(request: Request<'_>) -> Result<Bytes, Error> {
+    Bytes::try_from(request)
+}
+
+/// 解析响应的 PDU, 异常响应转换成包装了 [`ModbusError::Exception`] 的错误
+fn decode_response(pdu: Bytes) -> Result<Response, Error> {
+    match pdu.first() {
+        Some(&function) if function >= 0x80 => {
+            let code = pdu.get(1).copied().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "Truncated exception response")
+            })?;
+            let exception = Exception::try_from(code)?;
+            Err(Error::other(ModbusError::Exception(exception)))
+        }
+        Some(_) => Response::try_from(pdu),
+        None => Err(Error::new(ErrorKind::InvalidData, "Empty response")),
+    }
+}
+
+/// 发送缓冲区中剩余的数据
+///
+/// 每次写入成功后才从缓冲区中移除, 被取消时没有发送的部分留在缓冲区中.
+async fn write_pending<T>(io: &mut T, pending: &mut BytesMut) -> Result<(), Error>
+where
+    T: AsyncWrite + Unpin,
+{
+    while pending.has_remaining() {
+        if io.write_buf(pending).await? == 0 {
+            return Err(Error::from(ErrorKind::WriteZero));
+        }
+    }
+    io.flush().await
+}
+
+/// 读取更多数据到缓冲区, 连接关闭时返回错误
+async fn read_more<T>(io: &mut T, buffer: &mut BytesMut) -> Result<(), Error>
+where
+    T: AsyncRead + Unpin,
+{
+    if io.read_buf(buffer).await? == 0 {
+        return Err(Error::from(ErrorKind::BrokenPipe));
+    }
+    Ok(())
+}
+
+/// Modbus TCP 传输层
+#[cfg(feature = "modbus_tcp_client")]
+pub(crate) struct TcpTransport<T> {
+    io: T,
+    unit_id: u8,
+    transaction_id: u16,
+    read_buffer: BytesMut,
+    write_buffer: BytesMut,
+    /// 写缓冲区中最后一个请求的长度, 用来判断请求是否已经开始发送
+    queued: usize,
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl<T> TcpTransport<T>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    /// 创建 tcp 传输层并接入 tokio-modbus 的客户端
+    pub(crate) fn attach(io: T, slave: Slave) -> client::Context {
+        let transport = TcpTransport {
+            io,
+            unit_id: slave.into(),
+            transaction_id: 0,
+            read_buffer: BytesMut::new(),
+            write_buffer: BytesMut::new(),
+            queued: 0,
+        };
+        client::Context::from(Box::new(transport) as Box<dyn Client>)
+    }
+
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        if request == Request::Disconnect {
+            self.io.shutdown().await?;
+            return Err(Error::from(ErrorKind::NotConnected));
+        }
+        // 上一次被取消的请求还没有开始发送时直接丢弃, 否则先发送完, 保证帧的边界,
+        // 它的响应会因为事务 id 不同被丢弃
+        if self.write_buffer.len() == self.queued {
+            self.write_buffer.clear();
+        }
+        write_pending(&mut self.io, &mut self.write_buffer).await?;
+
+        let pdu = encode_request(request)?;
+        let transaction_id = self.transaction_id;
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        self.write_buffer.put_u16(transaction_id);
+        self.write_buffer.put_u16(0);
+        self.write_buffer.put_u16(pdu.len() as u16 + 1);
+        self.write_buffer.put_u8(self.unit_id);
+        self.write_buffer.put_slice(&pdu);
+        self.queued = self.write_buffer.len();
+        write_pending(&mut self.io, &mut self.write_buffer).await?;
+
+        loop {
+            while let Some((response_id, unit_id, pdu)) = self.next_frame()? {
+                if response_id != transaction_id {
+                    log::debug!(
+                        "TRANSPORT: Discard response {response_id}, waiting for {transaction_id}"
+                    );
+                    continue;
+                }
+                if unit_id != self.unit_id {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Invalid response unit id: expected {}, actual {unit_id}",
+                            self.unit_id
+                        ),
+                    ));
+                }
+                return decode_response(pdu);
+            }
+            read_more(&mut self.io, &mut self.read_buffer).await?;
+        }
+    }
+
+    /// 从缓冲区中取出一个完整的帧, 返回事务 id, 单元 id 和 PDU
+    fn next_frame(&mut self) -> Result<Option<(u16, u8, Bytes)>, Error> {
+        next_mbap_frame(&mut self.read_buffer)
+    }
+}
+
+/// 从缓冲区中取出一个完整的 MBAP 帧, 返回事务 id, 单元 id 和 PDU
+#[cfg(feature = "modbus_tcp_client")]
+fn next_mbap_frame(buffer: &mut BytesMut) -> Result<Option<(u16, u8, Bytes)>, Error> {
+    if buffer.len() < 7 {
+        return Ok(None);
+    }
+    let protocol_id = u16::from_be_bytes([buffer[2], buffer[3]]);
+    let len = usize::from(u16::from_be_bytes([buffer[4], buffer[5]]));
+    if protocol_id != 0 || !(2..=254).contains(&len) {
+        // 帧的边界已经丢失, 无法继续解析
+        buffer.clear();
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid MBAP header: protocol id {protocol_id}, length {len}"),
+        ));
+    }
+    if buffer.len() < 6 + len {
+        return Ok(None);
+    }
+    let mut frame = buffer.split_to(6 + len);
+    let transaction_id = frame.get_u16();
+    frame.advance(4);
+    let unit_id = frame.get_u8();
+    Ok(Some((transaction_id, unit_id, frame.freeze())))
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl<T: Debug> Debug for TcpTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpTransport")
+            .field("io", &self.io)
+            .field("unit_id", &self.unit_id)
+            .field("transaction_id", &self.transaction_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl<T> SlaveContext for TcpTransport<T> {
+    fn set_slave(&mut self, slave: Slave) {
+        self.unit_id = slave.into();
+    }
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+#[async_trait::async_trait]
+impl<T> Client for TcpTransport<T>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        TcpTransport::call(self, request).await
+    }
+}
+
+/// 流水线模式下等待响应的请求, 按照事务 id 索引
+#[cfg(feature = "modbus_tcp_client")]
+type Pending = HashMap<u16, oneshot::Sender<Result<(u8, Bytes), ErrorKind>>>;
+
+/// 流水线的接收任务和发送任务共享的状态
+#[cfg(feature = "modbus_tcp_client")]
+#[derive(Default)]
+struct Dispatch {
+    pending: Mutex<Pending>,
+    /// 连接已经断开, 不再接受新的请求
+    closed: AtomicBool,
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl Dispatch {
+    /// 连接断开, 所有等待中的请求返回错误
+    fn close(&self, kind: ErrorKind) {
+        let mut pending = self.pending.lock().unwrap();
+        self.closed.store(true, Ordering::Release);
+        for (_, sender) in pending.drain() {
+            let _ = sender.send(Err(kind));
+        }
+    }
+}
+
+/// Modbus TCP 流水线
+///
+/// 不等待上一个响应就发送下一个请求, 同时发送的请求数量不超过窗口大小, 响应按照事务 id 交给对应的请求.
+/// 有的设备不支持同时处理多个请求, 有请求在其他请求发送之后超时时, 认为设备不支持流水线,
+/// 之后退回到严格的一问一答. 请求可能只是偶然超时, 所以一问一答时连续收到
+/// [`PIPELINE_RECOVER_RESPONSES`] 个响应后重新启用流水线, 每次退回后需要的响应数量加倍.
+/// 重新连接时创建新的流水线, 也会重新启用.
+///
+/// 可以克隆, 所有克隆共享同一个连接.
+#[cfg(feature = "modbus_tcp_client")]
+#[derive(Clone)]
+pub(crate) struct Pipeline {
+    inner: Arc<PipelineInner>,
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+struct PipelineInner {
+    dispatch: Arc<Dispatch>,
+    frames: mpsc::Sender<Bytes>,
+    window: Semaphore,
+    size: u32,
+    transaction_id: AtomicU16,
+    /// 已经退回到一问一答
+    strict: AtomicBool,
+    /// 退回到一问一答的次数
+    fallbacks: AtomicU32,
+    /// 退回到一问一答之后连续收到的响应数量
+    answered: AtomicU32,
+    tasks: [JoinHandle<()>; 2],
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl Drop for PipelineInner {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl Pipeline {
+    /// 创建流水线, 启动接收任务和发送任务
+    ///
+    /// # 参数
+    /// - io: 已经建立的连接
+    /// - window: 最多同时发送的请求数量, 最小为 1
+    pub(crate) fn new<T>(io: T, window: usize) -> Pipeline
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let size = u32::try_from(window.clamp(1, usize::from(u16::MAX))).unwrap_or(1);
+        let (reader, writer) = tokio::io::split(io);
+        let dispatch = Arc::new(Dispatch::default());
+        let (frames, receiver) = mpsc::channel(size as usize);
+        let tasks = [
+            tokio::spawn(receive_responses(reader, dispatch.clone())),
+            tokio::spawn(send_requests(writer, receiver, dispatch.clone())),
+        ];
+        Pipeline {
+            inner: Arc::new(PipelineInner {
+                dispatch,
+                frames,
+                window: Semaphore::new(size as usize),
+                size,
+                transaction_id: AtomicU16::new(0),
+                strict: AtomicBool::new(size == 1),
+                fallbacks: AtomicU32::new(0),
+                answered: AtomicU32::new(0),
+                tasks,
+            }),
+        }
+    }
+
+    /// 连接是否已经断开
+    pub(crate) fn is_closed(&self) -> bool {
+        self.inner.dispatch.closed.load(Ordering::Acquire)
+    }
+
+    /// 接入 tokio-modbus 的客户端, 返回的客户端使用这个流水线发送请求
+    pub(crate) fn context(&self, slave: Slave) -> client::Context {
+        let client = PipelineClient {
+            pipeline: self.clone(),
+            unit_id: slave.into(),
+        };
+        client::Context::from(Box::new(client) as Box<dyn Client>)
+    }
+
+    async fn call(&self, unit_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        let inner = &self.inner;
+        if request == Request::Disconnect {
+            inner.dispatch.close(ErrorKind::NotConnected);
+            return Err(Error::from(ErrorKind::NotConnected));
+        }
+        let pdu = encode_request(request)?;
+
+        // 一问一答时占用整个窗口
+        let strict = inner.strict.load(Ordering::Acquire);
+        let permits = if strict { inner.size } else { 1 };
+        let _permit = inner
+            .window
+            .acquire_many(permits)
+            .await
+            .map_err(|_| Error::from(ErrorKind::BrokenPipe))?;
+
+        let (sender, receiver) = oneshot::channel();
+        let mut guard = {
+            let mut pending = inner.dispatch.pending.lock().unwrap();
+            if inner.dispatch.closed.load(Ordering::Acquire) {
+                return Err(Error::from(ErrorKind::BrokenPipe));
+            }
+            let mut transaction_id = inner.transaction_id.fetch_add(1, Ordering::Relaxed);
+            while pending.contains_key(&transaction_id) {
+                transaction_id = inner.transaction_id.fetch_add(1, Ordering::Relaxed);
+            }
+            let concurrent = !pending.is_empty();
+            pending.insert(transaction_id, sender);
+            PendingGuard {
+                inner,
+                transaction_id,
+                concurrent,
+            }
+        };
+
+        let mut frame = BytesMut::with_capacity(7 + pdu.len());
+        frame.put_u16(guard.transaction_id);
+        frame.put_u16(0);
+        frame.put_u16(pdu.len() as u16 + 1);
+        frame.put_u8(unit_id);
+        frame.put_slice(&pdu);
+        inner
+            .frames
+            .send(frame.freeze())
+            .await
+            .map_err(|_| Error::from(ErrorKind::BrokenPipe))?;
+
+        let response = receiver.await;
+        guard.concurrent = false;
+        let (response_unit_id, pdu) = response
+            .map_err(|_| Error::from(ErrorKind::BrokenPipe))?
+            .map_err(Error::from)?;
+        if strict && inner.size > 1 {
+            inner.answered();
+        }
+        if response_unit_id != unit_id {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid response unit id: expected {unit_id}, actual {response_unit_id}"),
+            ));
+        }
+        decode_response(pdu)
+    }
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("window", &self.inner.size)
+            .field("strict", &self.inner.strict.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl PipelineInner {
+    /// 一问一答时收到了响应, 连续收到足够多的响应后重新启用流水线
+    fn answered(&self) {
+        let fallbacks = self.fallbacks.load(Ordering::Acquire).max(1);
+        let required = PIPELINE_RECOVER_RESPONSES << (fallbacks - 1).min(10);
+        if self.answered.fetch_add(1, Ordering::AcqRel) + 1 >= required
+            && self.strict.swap(false, Ordering::AcqRel)
+        {
+            log::info!("TRANSPORT: {required} requests answered, re-enable pipelining");
+        }
+    }
+}
+
+/// 请求结束或者被取消时移除等待的响应
+#[cfg(feature = "modbus_tcp_client")]
+struct PendingGuard<'a> {
+    inner: &'a PipelineInner,
+    transaction_id: u16,
+    /// 发送时还有其他请求在等待响应
+    concurrent: bool,
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let removed = self
+            .inner
+            .dispatch
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&self.transaction_id)
+            .is_some();
+        if removed {
+            self.inner.answered.store(0, Ordering::Release);
+        }
+        // 和其他请求同时发送的请求没有收到响应就被取消, 一般是因为设备不支持流水线而丢弃了请求
+        if removed
+            && self.concurrent
+            && !self.inner.dispatch.closed.load(Ordering::Acquire)
+            && !self.inner.strict.swap(true, Ordering::AcqRel)
+        {
+            self.inner.fallbacks.fetch_add(1, Ordering::AcqRel);
+            log::warn!(
+                "TRANSPORT: Request {} was not answered while pipelined, fall back to strict request/response",
+                self.transaction_id
+            );
+        }
+    }
+}
+
+/// 接收响应并按照事务 id 交给对应的请求, 没有请求在等待的响应直接丢弃
+#[cfg(feature = "modbus_tcp_client")]
+async fn receive_responses<R>(mut reader: R, dispatch: Arc<Dispatch>)
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = BytesMut::new();
+    let kind = loop {
+        match next_mbap_frame(&mut buffer) {
+            Ok(Some((transaction_id, unit_id, pdu))) => {
+                let sender = dispatch.pending.lock().unwrap().remove(&transaction_id);
+                match sender {
+                    Some(sender) => {
+                        let _ = sender.send(Ok((unit_id, pdu)));
+                    }
+                    None => log::debug!("TRANSPORT: Discard response {transaction_id}"),
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => break e.kind(),
+        }
+        if let Err(e) = read_more(&mut reader, &mut buffer).await {
+            break e.kind();
+        }
+    };
+    dispatch.close(kind);
+}
+
+/// 按顺序发送请求帧, 每个帧都完整发送, 请求被取消时不会留下半个帧
+#[cfg(feature = "modbus_tcp_client")]
+async fn send_requests<W>(mut writer: W, mut frames: mpsc::Receiver<Bytes>, dispatch: Arc<Dispatch>)
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(frame) = frames.recv().await {
+        if let Err(e) = writer.write_all(&frame).await {
+            dispatch.close(e.kind());
+            return;
+        }
+        if let Err(e) = writer.flush().await {
+            dispatch.close(e.kind());
+            return;
+        }
+    }
+}
+
+/// 使用流水线发送请求的 tokio-modbus 客户端
+#[cfg(feature = "modbus_tcp_client")]
+#[derive(Debug)]
+struct PipelineClient {
+    pipeline: Pipeline,
+    unit_id: u8,
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+impl SlaveContext for PipelineClient {
+    fn set_slave(&mut self, slave: Slave) {
+        self.unit_id = slave.into();
+    }
+}
+
+#[cfg(feature = "modbus_tcp_client")]
+#[async_trait::async_trait]
+impl Client for PipelineClient {
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        self.pipeline.call(self.unit_id, request).await
+    }
+}
+
+/// rtu 线路的时间参数
+#[cfg(feature = "modbus_rtu_client")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RtuTiming {
+    /// 丢弃残留数据时, 线路上持续多久没有数据才认为已经清空
+    pub(crate) drain: Duration,
+    /// 帧之间至少间隔的静默时间, 也就是 3.5 个字符的时间
+    pub(crate) silence: Duration,
+    /// 广播之后等待从机处理的时间
+    pub(crate) turnaround: Duration,
+}
+
+/// rtu 线路空闲, 可以发送下一个请求的时间
+///
+/// 传输层在每个请求之后更新, [`crate::client::Client`] 在请求的超时时间之外等待,
+/// 所以静默时间和转换延时不计入请求的超时时间. 可以克隆, 所有克隆共享同一个时间.
+#[cfg(feature = "modbus_rtu_client")]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RtuPacing {
+    ready_at: Arc<Mutex<Option<Instant>>>,
+}
+
+#[cfg(feature = "modbus_rtu_client")]
+impl RtuPacing {
+    /// 线路在 `delay` 之后空闲
+    fn ready_after(&self, delay: Duration) {
+        *self.ready_at.lock().unwrap() = Some(Instant::now() + delay);
+    }
+
+    /// 等待线路空闲
+    pub(crate) async fn wait(&self) {
+        let ready_at = *self.ready_at.lock().unwrap();
+        if let Some(ready_at) = ready_at {
+            sleep_until(ready_at).await;
+        }
+    }
+}
+
+/// Modbus RTU 传输层
+#[cfg(feature = "modbus_rtu_client")]
+pub(crate) struct RtuTransport<T> {
+    io: T,
+    slave_id: u8,
+    /// 上一个请求没有正常完成, 线路上可能有残留的数据
+    dirty: bool,
+    timing: RtuTiming,
+    /// 线路空闲, 可以发送下一个请求的时间
+    pacing: RtuPacing,
+    /// 丢弃残留数据时线路开始静默的时间, 丢弃被超时取消后, 下一次请求从这里继续计算
+    quiet_since: Option<Instant>,
+    read_buffer: BytesMut,
+    write_buffer: BytesMut,
+}
+
+#[cfg(feature = "modbus_rtu_client")]
+impl<T> RtuTransport<T>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    /// 创建 rtu 传输层并接入 tokio-modbus 的客户端
+    ///
+    /// # 参数
+    /// - pacing: 线路空闲的时间, 和调用方共享, 调用方可以在请求的超时时间之外等待
+    pub(crate) fn attach(
+        io: T,
+        slave: Slave,
+        timing: RtuTiming,
+        pacing: RtuPacing,
+    ) -> client::Context {
+        let transport = RtuTransport {
+            io,
+            slave_id: slave.into(),
+            dirty: false,
+            timing,
+            pacing,
+            quiet_since: None,
+            read_buffer: BytesMut::new(),
+            write_buffer: BytesMut::new(),
+        };
+        client::Context::from(Box::new(transport) as Box<dyn Client>)
+    }
+
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        if request == Request::Disconnect {
+            self.io.shutdown().await?;
+            return Err(Error::from(ErrorKind::NotConnected));
+        }
+        if self.dirty {
+            // 被取消时没有发送完的请求不再发送, 半个帧会被从机当作错误帧丢弃
+            self.write_buffer.clear();
+            self.drain().await?;
+        }
+        // 调用方已经等待过时立即返回
+        self.pacing.wait().await;
+        let broadcast = if self.slave_id == 0 {
+            let response = broadcast_response(&request).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "Broadcast only supports write requests",
+                )
+            })?;
+            Some(response)
+        } else {
+            None
+        };
+        self.dirty = true;
+
+        let pdu = encode_request(request)?;
+        self.write_buffer.put_u8(self.slave_id);
+        self.write_buffer.put_slice(&pdu);
+        let crc = crc16(&self.write_buffer);
+        self.write_buffer.put_u16_le(crc);
+        write_pending(&mut self.io, &mut self.write_buffer).await?;
+        // 从机不响应广播, 发送完成后直接返回, 下一个请求等待从机处理完再发送
+        if let Some(response) = broadcast {
+            self.dirty = false;
+            self.pacing.ready_after(self.timing.turnaround);
+            return Ok(response);
+        }
+        self.pacing.ready_after(self.timing.silence);
+
+        loop {
+            while let Some(frame) = self.next_frame() {
+                let response = frame.slice(1..frame.len() - 2);
+                if frame[0] != self.slave_id || !response_matches(&pdu, &response) {
+                    log::debug!(
+                        "TRANSPORT: Discard frame from slave {} function {:#04x}",
+                        frame[0],
+                        frame[1]
+                    );
+                    continue;
+                }
+                self.dirty = false;
+                self.pacing.ready_after(self.timing.silence);
+                return decode_response(response);
+            }
+            read_more(&mut self.io, &mut self.read_buffer).await?;
+        }
+    }
+
+    /// 丢弃缓冲区和线路上残留的数据, 直到线路上持续 `drain` 没有数据
+    async fn drain(&mut self) -> Result<(), Error> {
+        let mut discarded = self.read_buffer.len();
+        self.read_buffer.clear();
+        let mut quiet_since = *self.quiet_since.get_or_insert_with(Instant::now);
+        loop {
+            let deadline = quiet_since + self.timing.drain;
+            match timeout_at(deadline, self.io.read_buf(&mut self.read_buffer)).await {
+                Ok(Ok(0)) => return Err(Error::from(ErrorKind::BrokenPipe)),
+                Ok(Ok(_)) => {
+                    discarded += self.read_buffer.len();
+                    self.read_buffer.clear();
+                    quiet_since = Instant::now();
+                    self.quiet_since = Some(quiet_since);
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => break,
+            }
+        }
+        if discarded > 0 {
+            log::debug!("TRANSPORT: Discard {discarded} stale bytes");
+        }
+        self.dirty = false;
+        self.quiet_since = None;
+        Ok(())
+    }
+
+    /// 从缓冲区中取出一个 CRC 正确的帧, 无法解析的数据逐个字节丢弃直到找到帧的开头
+    fn next_frame(&mut self) -> Option<Bytes> {
+        loop {
+            let len = rtu_frame_len(&self.read_buffer)?;
+            if len <= RTU_MAX_FRAME_LEN && self.read_buffer.len() < len {
+                return None;
+            }
+            if len <= RTU_MAX_FRAME_LEN {
+                let crc =
+                    u16::from_le_bytes([self.read_buffer[len - 2], self.read_buffer[len - 1]]);
+                if crc == crc16(&self.read_buffer[..len - 2]) {
+                    return Some(self.read_buffer.split_to(len).freeze());
+                }
+            }
+            self.read_buffer.advance(1);
+        }
+    }
+}
+
+#[cfg(feature = "modbus_rtu_client")]
+impl<T: Debug> Debug for RtuTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RtuTransport")
+            .field("io", &self.io)
+            .field("slave_id", &self.slave_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "modbus_rtu_client")]
+impl<T> SlaveContext for RtuTransport<T> {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave_id = slave.into();
+    }
+}
+
+#[cfg(feature = "modbus_rtu_client")]
+#[async_trait::async_trait]
+impl<T> Client for RtuTransport<T>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    async fn call(&mut self, request: Request<'_>) -> Result<Response, Error> {
+        RtuTransport::call(self, request).await
+    }
+}
+
+/// 广播写请求成功时的响应, 和从机的回显相同, 不是写请求时返回 None
+#[cfg(feature = "modbus_rtu_client")]
+fn broadcast_response(request: &Request<'_>) -> Option<Response> {
+    let response = match *request {
+        Request::WriteSingleCoil(address, value) => Response::WriteSingleCoil(address, value),
+        Request::WriteSingleRegister(address, value) => {
+            Response::WriteSingleRegister(address, value)
+        }
+        Request::WriteMultipleCoils(address, ref values) => {
+            Response::WriteMultipleCoils(address, values.len() as u16)
+        }
+        Request::WriteMultipleRegisters(address, ref values) => {
+            Response::WriteMultipleRegisters(address, values.len() as u16)
+        }
+        Request::MaskWriteRegister(address, and_mask, or_mask) => {
+            Response::MaskWriteRegister(address, and_mask, or_mask)
+        }
+        _ => return None,
+    };
+    Some(response)
+}
+
+/// 按照功能码计算 rtu 响应帧的长度 (包含从机 id 和 CRC)
+///
+/// 数据不够判断时返回 None, 不认识的功能码返回超过最大长度的值, 让调用方丢弃这个字节.
+#[cfg(feature = "modbus_rtu_client")]
+fn rtu_frame_len(buffer: &[u8]) -> Option<usize> {
+    let function = *buffer.get(1)?;
+    let len = match function {
+        0x80.. => 5,
+        0x01..=0x04 | 0x17 => 5 + usize::from(*buffer.get(2)?),
+        0x05 | 0x06 | 0x0F | 0x10 => 8,
+        0x16 => 10,
+        _ => RTU_MAX_FRAME_LEN + 1,
+    };
+    Some(len)
+}
+
+/// 判断响应的 PDU 是否可能是请求的响应
+///
+/// rtu 没有事务 id, 只能按照功能码, 读取的字节数和写请求回显的内容判断.
+#[cfg(feature = "modbus_rtu_client")]
+fn response_matches(request: &[u8], response: &[u8]) -> bool {
+    let (Some(&function), Some(&code)) = (request.first(), response.first()) else {
+        return false;
+    };
+    if code == function | 0x80 {
+        return true;
+    }
+    if code != function {
+        return false;
+    }
+    let quantity = || usize::from(u16::from_be_bytes([request[3], request[4]]));
+    match function {
+        0x01 | 0x02 => response.get(1).map(|&n| usize::from(n)) == Some(quantity().div_ceil(8)),
+        0x03 | 0x04 | 0x17 => response.get(1).map(|&n| usize::from(n)) == Some(quantity() * 2),
+        0x05 | 0x06 | 0x16 => response == request,
+        0x0F | 0x10 => response.get(..5) == request.get(..5),
+        _ => true,
+    }
+}
+
+/// Modbus CRC-16
+#[cfg(feature = "modbus_rtu_client")]
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{duplex, DuplexStream};
+    use tokio::time::timeout;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    /// 读取一个请求帧
+    async fn read_request(server: &mut DuplexStream, len: usize) -> Vec<u8> {
+        let mut frame = vec![0; len];
+        server.read_exact(&mut frame).await.unwrap();
+        frame
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    fn mbap(transaction_id: u16, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+        let mut frame = transaction_id.to_be_bytes().to_vec();
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        frame.push(unit_id);
+        frame.extend_from_slice(pdu);
+        frame
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    fn rtu(slave_id: u8, pdu: &[u8]) -> Vec<u8> {
+        let mut frame = vec![slave_id];
+        frame.extend_from_slice(pdu);
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        frame
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    fn timing() -> RtuTiming {
+        RtuTiming {
+            drain: Duration::from_millis(20),
+            silence: Duration::from_millis(1),
+            turnaround: Duration::from_millis(30),
+        }
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[test]
+    fn mbap_frames() {
+        let mut buffer = BytesMut::new();
+        let frame = mbap(7, 1, &[0x03, 0x02, 0x00, 0x2A]);
+        buffer.extend_from_slice(&frame[..5]);
+        assert!(next_mbap_frame(&mut buffer).unwrap().is_none());
+        buffer.extend_from_slice(&frame[5..]);
+        buffer.extend_from_slice(&mbap(8, 2, &[0x06, 0x00, 0x01, 0x00, 0x05]));
+        buffer.extend_from_slice(&[0x00]);
+
+        let (transaction_id, unit_id, pdu) = next_mbap_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!((transaction_id, unit_id), (7, 1));
+        assert_eq!(&pdu[..], [0x03, 0x02, 0x00, 0x2A]);
+        let (transaction_id, unit_id, _) = next_mbap_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!((transaction_id, unit_id), (8, 2));
+        assert!(next_mbap_frame(&mut buffer).unwrap().is_none());
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[test]
+    fn reject_invalid_mbap_header() {
+        let mut buffer = BytesMut::from(&[0, 1, 0, 9, 0, 3, 1, 3, 0][..]);
+        assert!(next_mbap_frame(&mut buffer).is_err());
+        assert!(buffer.is_empty());
+
+        let mut buffer = BytesMut::from(&[0, 1, 0, 0, 0x01, 0x00, 1, 3][..]);
+        assert!(next_mbap_frame(&mut buffer).is_err());
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn tcp_discards_late_response() {
+        let (client, mut server) = duplex(1024);
+        let mut ctx = TcpTransport::attach(client, Slave(1));
+
+        let first = timeout(TIMEOUT, ctx.read_holding_registers(0, 1)).await;
+        assert!(first.is_err());
+        assert_eq!(read_request(&mut server, 12).await[..2], [0, 0]);
+
+        let script = tokio::spawn(async move {
+            let request = read_request(&mut server, 12).await;
+            assert_eq!(request[..2], [0, 1]);
+            server
+                .write_all(&mbap(0, 1, &[0x03, 0x02, 0x00, 111]))
+                .await
+                .unwrap();
+            server
+                .write_all(&mbap(1, 1, &[0x03, 0x02, 0x00, 222]))
+                .await
+                .unwrap();
+            server
+        });
+        assert_eq!(ctx.read_holding_registers(0, 1).await.unwrap(), [222]);
+        script.await.unwrap();
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn tcp_exception_response() {
+        let (client, mut server) = duplex(1024);
+        let mut ctx = TcpTransport::attach(client, Slave(1));
+        let script = tokio::spawn(async move {
+            read_request(&mut server, 12).await;
+            server.write_all(&mbap(0, 1, &[0x83, 0x02])).await.unwrap();
+            server
+        });
+        let error = ctx.read_holding_registers(0, 1).await.unwrap_err();
+        assert_eq!(
+            ModbusError::from(error).exception(),
+            Some(Exception::IllegalDataAddress)
+        );
+        script.await.unwrap();
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn pipeline_matches_out_of_order_responses() {
+        let (client, mut server) = duplex(1024);
+        let pipeline = Pipeline::new(client, 4);
+        let mut first = pipeline.context(Slave(1));
+        let mut second = pipeline.context(Slave(2));
+
+        let script = tokio::spawn(async move {
+            let a = read_request(&mut server, 12).await;
+            let b = read_request(&mut server, 12).await;
+            for (request, value) in [(b, 2), (a, 1)] {
+                let transaction_id = u16::from_be_bytes([request[0], request[1]]);
+                let pdu = [0x03, 0x02, 0x00, request[6] * 10 + value];
+                server
+                    .write_all(&mbap(transaction_id, request[6], &pdu))
+                    .await
+                    .unwrap();
+            }
+            server
+        });
+        let (a, b) = tokio::join!(
+            first.read_holding_registers(0, 1),
+            second.read_holding_registers(0, 1)
+        );
+        assert_eq!(a.unwrap(), [11]);
+        assert_eq!(b.unwrap(), [22]);
+        assert!(!pipeline.inner.strict.load(Ordering::Acquire));
+        script.await.unwrap();
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn pipeline_falls_back_to_strict() {
+        let (client, mut server) = duplex(1024);
+        let pipeline = Pipeline::new(client, 2);
+        let mut first = pipeline.context(Slave(1));
+        let mut second = pipeline.context(Slave(1));
+
+        let script = tokio::spawn(async move {
+            let request = read_request(&mut server, 12).await;
+            read_request(&mut server, 12).await;
+            // 设备不支持流水线, 丢弃了第二个请求
+            server
+                .write_all(&mbap(
+                    u16::from_be_bytes([request[0], request[1]]),
+                    1,
+                    &[0x03, 0x02, 0x00, 2],
+                ))
+                .await
+                .unwrap();
+            server
+        });
+        let (a, b) = tokio::join!(
+            timeout(TIMEOUT, first.read_holding_registers(0, 1)),
+            timeout(TIMEOUT, second.read_holding_registers(1, 1))
+        );
+        assert_eq!(a.unwrap().unwrap(), [2]);
+        assert!(b.is_err());
+        assert!(pipeline.inner.strict.load(Ordering::Acquire));
+        assert!(pipeline.inner.dispatch.pending.lock().unwrap().is_empty());
+        script.await.unwrap();
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn pipeline_recovers_from_strict() {
+        let (client, mut server) = duplex(1024);
+        let pipeline = Pipeline::new(client, 2);
+        let mut first = pipeline.context(Slave(1));
+        let mut second = pipeline.context(Slave(1));
+
+        let script = tokio::spawn(async move {
+            // 丢弃第二个请求, 之后按顺序响应所有请求
+            let mut requests = 0;
+            loop {
+                let mut request = [0; 12];
+                if server.read_exact(&mut request).await.is_err() {
+                    return;
+                }
+                requests += 1;
+                if requests == 2 {
+                    continue;
+                }
+                let transaction_id = u16::from_be_bytes([request[0], request[1]]);
+                server
+                    .write_all(&mbap(transaction_id, 1, &[0x03, 0x02, 0x00, request[9]]))
+                    .await
+                    .unwrap();
+            }
+        });
+        let (a, b) = tokio::join!(
+            timeout(TIMEOUT, first.read_holding_registers(0, 1)),
+            timeout(TIMEOUT, second.read_holding_registers(1, 1))
+        );
+        assert!(a.unwrap().is_ok());
+        assert!(b.is_err());
+        assert!(pipeline.inner.strict.load(Ordering::Acquire));
+
+        for address in 1..PIPELINE_RECOVER_RESPONSES as u16 {
+            assert_eq!(
+                first.read_holding_registers(address, 1).await.unwrap(),
+                [address]
+            );
+        }
+        assert!(pipeline.inner.strict.load(Ordering::Acquire));
+        first.read_holding_registers(0, 1).await.unwrap();
+        assert!(!pipeline.inner.strict.load(Ordering::Acquire));
+
+        // 重新启用后又有请求没有响应时再次退回, 需要的响应数量加倍
+        pipeline.inner.strict.store(true, Ordering::Release);
+        pipeline.inner.fallbacks.fetch_add(1, Ordering::AcqRel);
+        pipeline.inner.answered.store(0, Ordering::Release);
+        for _ in 0..PIPELINE_RECOVER_RESPONSES * 2 - 1 {
+            first.read_holding_registers(0, 1).await.unwrap();
+        }
+        assert!(pipeline.inner.strict.load(Ordering::Acquire));
+        first.read_holding_registers(0, 1).await.unwrap();
+        assert!(!pipeline.inner.strict.load(Ordering::Acquire));
+
+        drop((first, second, pipeline));
+        script.await.unwrap();
+    }
+
+    #[cfg(feature = "modbus_tcp_client")]
+    #[tokio::test]
+    async fn pipeline_closes_with_connection() {
+        let (client, server) = duplex(1024);
+        let pipeline = Pipeline::new(client, 2);
+        let mut ctx = pipeline.context(Slave(1));
+        drop(server);
+        assert!(ctx.read_holding_registers(0, 1).await.is_err());
+        assert!(pipeline.is_closed());
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), 0xCDC5);
+        assert_eq!(
+            rtu(1, &[0x03, 0x00, 0x00, 0x00, 0x0A]),
+            [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD]
+        );
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[test]
+    fn rtu_frame_lengths() {
+        assert_eq!(rtu_frame_len(&[1]), None);
+        assert_eq!(rtu_frame_len(&[1, 0x03]), None);
+        assert_eq!(rtu_frame_len(&[1, 0x03, 4]), Some(9));
+        assert_eq!(rtu_frame_len(&[1, 0x01, 1]), Some(6));
+        assert_eq!(rtu_frame_len(&[1, 0x83]), Some(5));
+        assert_eq!(rtu_frame_len(&[1, 0x06]), Some(8));
+        assert_eq!(rtu_frame_len(&[1, 0x10]), Some(8));
+        assert_eq!(rtu_frame_len(&[1, 0x16]), Some(10));
+        assert!(rtu_frame_len(&[1, 0x2B]).unwrap() > RTU_MAX_FRAME_LEN);
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[test]
+    fn match_responses() {
+        let read = [0x03, 0x00, 0x00, 0x00, 0x02];
+        assert!(response_matches(&read, &[0x03, 0x04, 0, 1, 0, 2]));
+        assert!(!response_matches(&read, &[0x03, 0x02, 0, 1]));
+        assert!(!response_matches(&read, &[0x04, 0x04, 0, 1, 0, 2]));
+        assert!(response_matches(&read, &[0x83, 0x02]));
+
+        let coils = [0x01, 0x00, 0x00, 0x00, 0x09];
+        assert!(response_matches(&coils, &[0x01, 0x02, 0xFF, 0x01]));
+        assert!(!response_matches(&coils, &[0x01, 0x01, 0xFF]));
+
+        let write = [0x06, 0x00, 0x01, 0x00, 0x05];
+        assert!(response_matches(&write, &write));
+        assert!(!response_matches(&write, &[0x06, 0x00, 0x01, 0x00, 0x06]));
+
+        let write = [0x10, 0x00, 0x01, 0x00, 0x02, 0x04, 0, 1, 0, 2];
+        assert!(response_matches(&write, &[0x10, 0x00, 0x01, 0x00, 0x02]));
+        assert!(!response_matches(&write, &[0x10, 0x00, 0x02, 0x00, 0x02]));
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[tokio::test]
+    async fn rtu_discards_late_response() {
+        let (client, mut server) = duplex(1024);
+        let mut ctx = RtuTransport::attach(client, Slave(1), timing(), RtuPacing::default());
+
+        let first = timeout(TIMEOUT, ctx.read_holding_registers(0, 1)).await;
+        assert!(first.is_err());
+        read_request(&mut server, 8).await;
+        // 超时之后才到达的响应
+        server
+            .write_all(&rtu(1, &[0x03, 0x02, 0x00, 111]))
+            .await
+            .unwrap();
+
+        let script = tokio::spawn(async move {
+            let request = read_request(&mut server, 8).await;
+            assert_eq!(request, rtu(1, &[0x03, 0x00, 0x00, 0x00, 0x01]));
+            // 噪声和其他从机的帧都被丢弃
+            server.write_all(&[0x00, 0xFF]).await.unwrap();
+            server
+                .write_all(&rtu(2, &[0x03, 0x02, 0x00, 1]))
+                .await
+                .unwrap();
+            server
+                .write_all(&rtu(1, &[0x03, 0x02, 0x00, 222]))
+                .await
+                .unwrap();
+            server
+        });
+        assert_eq!(ctx.read_holding_registers(0, 1).await.unwrap(), [222]);
+        script.await.unwrap();
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[tokio::test]
+    async fn rtu_broadcast() {
+        let (client, mut server) = duplex(1024);
+        let mut ctx = RtuTransport::attach(client, Slave(0), timing(), RtuPacing::default());
+
+        let error = ctx.read_holding_registers(0, 1).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+        let sent = Instant::now();
+        ctx.write_single_register(1, 5).await.unwrap();
+        assert_eq!(
+            read_request(&mut server, 8).await,
+            rtu(0, &[0x06, 0x00, 0x01, 0x00, 0x05])
+        );
+
+        // 广播之后的请求等待转换延时之后才发送
+        ctx.set_slave(Slave(1));
+        let script = tokio::spawn(async move {
+            read_request(&mut server, 8).await;
+            let received = Instant::now();
+            server
+                .write_all(&rtu(1, &[0x06, 0x00, 0x01, 0x00, 0x06]))
+                .await
+                .unwrap();
+            received
+        });
+        ctx.write_single_register(1, 6).await.unwrap();
+        assert!(script.await.unwrap() - sent >= timing().turnaround);
+    }
+
+    #[cfg(feature = "modbus_rtu_client")]
+    #[tokio::test]
+    async fn rtu_broadcast_turnaround_longer_than_timeout() {
+        let (client, mut server) = duplex(1024);
+        let timing = RtuTiming {
+            turnaround: TIMEOUT * 2,
+            ..timing()
+        };
+        let mut ctx = RtuTransport::attach(client, Slave(0), timing, RtuPacing::default());
+
+        // 广播发送完成后直接返回, 不在请求的超时时间内等待转换延时
+        timeout(TIMEOUT, ctx.write_single_coil(0, true))
+            .await
+            .unwrap()
+            .unwrap();
+        let sent = Instant::now();
+        read_request(&mut server, 8).await;
+
+        ctx.set_slave(Slave(1));
+        let script = tokio::spawn(async move {
+            read_request(&mut server, 8).await;
+            let received = Instant::now();
+            server
+                .write_all(&rtu(1, &[0x05, 0x00, 0x00, 0x00, 0x00]))
+                .await
+                .unwrap();
+            received
+        });
+        ctx.write_single_coil(0, false).await.unwrap();
+        assert!(script.await.unwrap() - sent >= timing.turnaround - Duration::from_millis(5));
+    }
+}
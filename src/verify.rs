@@ -0,0 +1,472 @@
+//! 写入后读回校验.
+//!
+//! 有些设备确认了写请求, 但是限制了值的范围或者直接忽略. [`VerifiedWriter`] 写入后读回同一段地址并比较,
+//! 不同时返回 [`ModbusError::VerifyMismatch`], 其中包含写入的值和读回的值.
+//!
+//! 读回的值不同时可以按照 [`Verify`] 中的重试策略重新写入, 请求本身的超时和异常仍然由客户端的重试策略处理.
+
+use crate::error::{ModbusError, Result, Values};
+use crate::retry::{FixedRetry, RetryPolicy};
+use crate::{Reader, Writer};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// 读回校验的设置
+///
+/// 默认写入后立即读回, 不同时不重新写入.
+#[derive(Debug, Clone)]
+pub struct Verify {
+    settle: Duration,
+    retry_policy: Arc<dyn RetryPolicy>,
+}
+
+impl Default for Verify {
+    fn default() -> Self {
+        Verify {
+            settle: Duration::ZERO,
+            retry_policy: Arc::new(FixedRetry::new(Duration::ZERO, 1)),
+        }
+    }
+}
+
+impl Verify {
+    /// 创建默认设置
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置写入后等待多久再读回, 给设备留出生效的时间
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// 设置读回的值不同时重新写入的策略
+    pub fn retry_policy<P: RetryPolicy + 'static>(mut self, retry_policy: P) -> Self {
+        self.retry_policy = Arc::new(retry_policy);
+        self
+    }
+}
+
+/// 写入后读回校验的扩展方法, 所有同时实现了 [`Reader`] 和 [`Writer`] 的类型都可以使用
+#[async_trait]
+pub trait VerifiedWriter: Reader + Writer + Send {
+    /// 写入单个线圈 (0x05) 并读回校验
+    ///
+    /// # 参数
+    /// - address: 要写入的地址
+    /// - value: 要写入的值
+    /// - verify: 读回校验的设置
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`], 读回的值不同时返回 [`ModbusError::VerifyMismatch`]
+    async fn write_single_coil_verified(
+        &mut self,
+        address: u16,
+        value: bool,
+        verify: &Verify,
+    ) -> Result<()> {
+        verified(self, address, Write::SingleCoil(value), verify).await
+    }
+
+    /// 写入单个保持寄存器 (0x06) 并读回校验
+    ///
+    /// # 参数
+    /// - address: 要写入的地址
+    /// - value: 要写入的值
+    /// - verify: 读回校验的设置
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`], 读回的值不同时返回 [`ModbusError::VerifyMismatch`]
+    async fn write_single_register_verified(
+        &mut self,
+        address: u16,
+        value: u16,
+        verify: &Verify,
+    ) -> Result<()> {
+        verified(self, address, Write::SingleRegister(value), verify).await
+    }
+
+    /// 写入多个线圈 (0x0F) 并读回校验
+    ///
+    /// # 参数
+    /// - address: 要写入的第一个起始地址
+    /// - value: 从地址 `address` 开始写入的值
+    /// - verify: 读回校验的设置
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`], 读回的值不同时返回 [`ModbusError::VerifyMismatch`]
+    async fn write_multiple_coils_verified(
+        &mut self,
+        address: u16,
+        value: &[bool],
+        verify: &Verify,
+    ) -> Result<()> {
+        verified(self, address, Write::Coils(value), verify).await
+    }
+
+    /// 写入多个保持寄存器 (0x10) 并读回校验
+    ///
+    /// # 参数
+    /// - address: 要写入的第一个起始地址
+    /// - value: 从地址 `address` 开始写入的值
+    /// - verify: 读回校验的设置
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`], 读回的值不同时返回 [`ModbusError::VerifyMismatch`]
+    async fn write_multiple_registers_verified(
+        &mut self,
+        address: u16,
+        value: &[u16],
+        verify: &Verify,
+    ) -> Result<()> {
+        verified(self, address, Write::Registers(value), verify).await
+    }
+
+    /// 设置或清除单个保持寄存器的位 (0x16) 并读回校验
+    ///
+    /// 只比较掩码修改的位, 也就是 AND 掩码为 0 的位, 设备在这期间自己修改的其他位不影响校验.
+    ///
+    /// # 参数
+    /// - address: 地址
+    /// - and_mask: AND 掩码
+    /// - or_mask: OR 掩码
+    /// - verify: 读回校验的设置
+    ///
+    /// # 返回
+    /// - 成功: 返回空
+    /// - 失败: 返回 [`ModbusError`], 读回的值不同时返回 [`ModbusError::VerifyMismatch`]
+    async fn masked_write_register_verified(
+        &mut self,
+        address: u16,
+        and_mask: u16,
+        or_mask: u16,
+        verify: &Verify,
+    ) -> Result<()> {
+        let write = Write::Masked { and_mask, or_mask };
+        verified(self, address, write, verify).await
+    }
+}
+
+impl<T: Reader + Writer + Send + ?Sized> VerifiedWriter for T {}
+
+/// 需要校验的写请求
+#[derive(Clone, Copy)]
+enum Write<'a> {
+    SingleCoil(bool),
+    SingleRegister(u16),
+    Coils(&'a [bool]),
+    Registers(&'a [u16]),
+    Masked { and_mask: u16, or_mask: u16 },
+}
+
+impl Write<'_> {
+    async fn send<T>(self, this: &mut T, address: u16) -> Result<()>
+    where
+        T: Writer + Send + ?Sized,
+    {
+        match self {
+            Write::SingleCoil(value) => this.write_single_coil(address, value).await,
+            Write::SingleRegister(value) => this.write_single_register(address, value).await,
+            Write::Coils(values) => this.write_multiple_coils(address, values).await,
+            Write::Registers(values) => this.write_multiple_registers(address, values).await,
+            Write::Masked { and_mask, or_mask } => {
+                this.masked_write_register(address, and_mask, or_mask).await
+            }
+        }
+    }
+
+    /// 写入的线圈或寄存器数量
+    fn count(self) -> usize {
+        match self {
+            Write::SingleCoil(_) | Write::SingleRegister(_) | Write::Masked { .. } => 1,
+            Write::Coils(values) => values.len(),
+            Write::Registers(values) => values.len(),
+        }
+    }
+
+    /// 读回同一段地址
+    async fn read_back<T>(self, this: &mut T, address: u16, count: u16) -> Result<Values>
+    where
+        T: Reader + Send + ?Sized,
+    {
+        match self {
+            Write::SingleCoil(_) | Write::Coils(_) => {
+                let values = this.read_coils(address, count).await?;
+                Ok(Values::Coils(values))
+            }
+            Write::SingleRegister(_) | Write::Registers(_) | Write::Masked { .. } => {
+                let values = this.read_holding_registers(address, count).await?;
+                Ok(Values::Registers(values))
+            }
+        }
+    }
+
+    /// 写入成功时应该读回的值
+    fn expected(self, actual: &Values) -> Values {
+        match self {
+            Write::SingleCoil(value) => Values::Coils(vec![value]),
+            Write::SingleRegister(value) => Values::Registers(vec![value]),
+            Write::Coils(values) => Values::Coils(values.to_vec()),
+            Write::Registers(values) => Values::Registers(values.to_vec()),
+            Write::Masked { and_mask, or_mask } => {
+                // 协议规定的结果: (current AND and_mask) OR (or_mask AND (NOT and_mask)),
+                // AND 掩码为 0 的位等于 OR 掩码, 其他位保持读回的值
+                let current = match actual {
+                    Values::Registers(values) => values.first().copied().unwrap_or_default(),
+                    Values::Coils(_) => 0,
+                };
+                Values::Registers(vec![(current & and_mask) | (or_mask & !and_mask)])
+            }
+        }
+    }
+}
+
+/// 写入, 读回并比较, 不同时按照重试策略重新写入
+async fn verified<T>(this: &mut T, address: u16, write: Write<'_>, verify: &Verify) -> Result<()>
+where
+    T: Reader + Writer + Send + ?Sized,
+{
+    let count = write.count();
+    let count = u16::try_from(count).map_err(|_| {
+        ModbusError::InvalidRequest(format!("can not read back {count} elements at once"))
+    })?;
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        write.send(this, address).await?;
+        if !verify.settle.is_zero() {
+            sleep(verify.settle).await;
+        }
+        let actual = write.read_back(this, address, count).await?;
+        let expected = write.expected(&actual);
+        if actual == expected {
+            return Ok(());
+        }
+        let error = ModbusError::VerifyMismatch {
+            address,
+            expected,
+            actual,
+        };
+        match verify.retry_policy.next_delay(attempt) {
+            Some(delay) if delay.is_zero() => {}
+            Some(delay) => sleep(delay).await,
+            None => return Err(error),
+        }
+        log::debug!("VERIFY: Rewrite {address} after mismatch");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 内存中的设备, 写入的值经过 `store` 处理后保存, 参数是原来的值和写入的值
+    struct Device {
+        registers: Vec<u16>,
+        coils: Vec<bool>,
+        store: fn(u16, u16) -> u16,
+        writes: u32,
+    }
+
+    impl Device {
+        fn new(store: fn(u16, u16) -> u16) -> Self {
+            Device {
+                registers: vec![0; 8],
+                coils: vec![false; 8],
+                store,
+                writes: 0,
+            }
+        }
+
+        fn store(&mut self, address: u16, value: u16) {
+            let register = &mut self.registers[usize::from(address)];
+            *register = (self.store)(*register, value);
+        }
+    }
+
+    #[async_trait]
+    impl Reader for Device {
+        async fn read_coils(&mut self, address: u16, count: u16) -> Result<Vec<bool>> {
+            let address = usize::from(address);
+            Ok(self.coils[address..address + usize::from(count)].to_vec())
+        }
+
+        async fn read_discrete_inputs(&mut self, _: u16, _: u16) -> Result<Vec<bool>> {
+            Err(unsupported())
+        }
+
+        async fn read_holding_registers(&mut self, address: u16, count: u16) -> Result<Vec<u16>> {
+            let address = usize::from(address);
+            Ok(self.registers[address..address + usize::from(count)].to_vec())
+        }
+
+        async fn read_input_registers(&mut self, _: u16, _: u16) -> Result<Vec<u16>> {
+            Err(unsupported())
+        }
+
+        async fn read_write_multiple_registers(
+            &mut self,
+            _: u16,
+            _: u16,
+            _: u16,
+            _: &[u16],
+        ) -> Result<Vec<u16>> {
+            Err(unsupported())
+        }
+    }
+
+    #[async_trait]
+    impl Writer for Device {
+        async fn write_single_coil(&mut self, address: u16, value: bool) -> Result<()> {
+            self.write_multiple_coils(address, &[value]).await
+        }
+
+        async fn write_single_register(&mut self, address: u16, value: u16) -> Result<()> {
+            self.write_multiple_registers(address, &[value]).await
+        }
+
+        async fn write_multiple_coils(&mut self, address: u16, value: &[bool]) -> Result<()> {
+            self.writes += 1;
+            let address = usize::from(address);
+            self.coils[address..address + value.len()].copy_from_slice(value);
+            Ok(())
+        }
+
+        async fn write_multiple_registers(&mut self, address: u16, value: &[u16]) -> Result<()> {
+            self.writes += 1;
+            for (address, value) in (address..).zip(value) {
+                self.store(address, *value);
+            }
+            Ok(())
+        }
+
+        async fn masked_write_register(
+            &mut self,
+            address: u16,
+            and_mask: u16,
+            or_mask: u16,
+        ) -> Result<()> {
+            self.writes += 1;
+            let current = self.registers[usize::from(address)];
+            self.store(address, (current & and_mask) | (or_mask & !and_mask));
+            Ok(())
+        }
+    }
+
+    /// 校验只使用线圈和保持寄存器, 其他请求返回错误
+    fn unsupported() -> ModbusError {
+        ModbusError::InvalidRequest("not supported by the test device".to_string())
+    }
+
+    /// 把写入的值限制在 100 以内
+    fn clamp(_: u16, value: u16) -> u16 {
+        value.min(100)
+    }
+
+    #[tokio::test]
+    async fn clamped_write_is_a_mismatch() {
+        let mut device = Device::new(clamp);
+        let verify = Verify::new();
+
+        device
+            .write_single_register_verified(1, 80, &verify)
+            .await
+            .unwrap();
+        let e = device
+            .write_multiple_registers_verified(2, &[5, 150], &verify)
+            .await
+            .unwrap_err();
+        match e {
+            ModbusError::VerifyMismatch {
+                address,
+                expected,
+                actual,
+            } => {
+                assert_eq!(address, 2);
+                assert_eq!(expected, Values::Registers(vec![5, 150]));
+                assert_eq!(actual, Values::Registers(vec![5, 100]));
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+        // 默认 FixedRetry::new(Duration::ZERO, 1) 的次数包含第一次写入, 不重新写入
+        assert_eq!(device.writes, 2);
+
+        device
+            .write_multiple_coils_verified(0, &[true, false, true], &verify)
+            .await
+            .unwrap();
+        assert_eq!(device.coils[..3], [true, false, true]);
+    }
+
+    #[tokio::test]
+    async fn rewrite_by_retry_policy() {
+        let mut device = Device::new(clamp);
+        let verify = Verify::new().retry_policy(FixedRetry::new(Duration::ZERO, 3));
+        let e = device
+            .write_single_register_verified(0, 150, &verify)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, ModbusError::VerifyMismatch { .. }), "{e}");
+        assert_eq!(device.writes, 3);
+
+        // 第一次写入被忽略, 重新写入后成功
+        let mut device = Device::new(|current, value| if current == 0 { 1 } else { value });
+        device
+            .write_single_register_verified(0, 150, &verify)
+            .await
+            .unwrap();
+        assert_eq!(device.writes, 2);
+        assert_eq!(device.registers[0], 150);
+    }
+
+    #[tokio::test]
+    async fn masked_write_ignores_bits_outside_mask() {
+        // 设备自己设置最高位, 最高位不在掩码修改的范围内
+        let mut device = Device::new(|_, value| value | 0x8000);
+        let verify = Verify::new();
+        device
+            .masked_write_register_verified(0, 0xFF00, 0x0012, &verify)
+            .await
+            .unwrap();
+        assert_eq!(device.registers[0], 0x8012);
+
+        // 设备忽略写入, 只比较掩码修改的低 8 位
+        let mut device = Device::new(|current, _| current);
+        device.registers[0] = 0x4321;
+        let e = device
+            .masked_write_register_verified(0, 0xFF00, 0x0012, &verify)
+            .await
+            .unwrap_err();
+        match e {
+            ModbusError::VerifyMismatch {
+                address,
+                expected,
+                actual,
+            } => {
+                assert_eq!(address, 0);
+                assert_eq!(expected, Values::Registers(vec![0x4312]));
+                assert_eq!(actual, Values::Registers(vec![0x4321]));
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+        assert_eq!(device.writes, 1);
+
+        // 和其他写请求使用同一个重试策略, 第一次写入被忽略, 重新写入后成功
+        let mut device = Device::new(|current, value| if current == 0 { 1 } else { value });
+        let verify = Verify::new().retry_policy(FixedRetry::new(Duration::ZERO, 2));
+        device
+            .masked_write_register_verified(0, 0xFF00, 0x0012, &verify)
+            .await
+            .unwrap();
+        assert_eq!(device.writes, 2);
+        assert_eq!(device.registers[0], 0x0012);
+    }
+}